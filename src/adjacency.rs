@@ -0,0 +1,344 @@
+use bevy_ecs::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::blueprint::Blueprint;
+use crate::simulation::*;
+
+/// mass production bonus per adjacent mass storage (mass extractors only)
+pub const MASS_STORAGE_MASS_PRODUCTION_BONUS: f64 = 0.125;
+/// energy production bonus per adjacent energy storage (power generators only)
+pub const ENERGY_STORAGE_ENERGY_PRODUCTION_BONUS: f64 = 0.125;
+/// energy consumption reduction per adjacent power generator, indexed by generator tech level
+/// (applies to factories and mass fabricators)
+pub const POWER_GENERATOR_ENERGY_CONSUMPTION_BONUS: [f64; 3] = [0.0625, 0.125, 0.1875];
+/// mass consumption reduction per adjacent mass extractor or fabricator, indexed by its
+/// tech level (applies to factories)
+pub const MASS_PRODUCER_MASS_CONSUMPTION_BONUS: [f64; 3] = [0.025, 0.05, 0.075];
+
+/// Structure size on the build grid
+#[derive(Component, Clone, Debug, Serialize, Deserialize)]
+pub struct Footprint {
+    pub size_x: i32,
+    pub size_z: i32,
+}
+
+/// Structure position on the build grid (lowest corner of footprint)
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GridPosition {
+    pub x: i32,
+    pub z: i32,
+}
+
+/// Structure role for adjacency purposes
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AdjacencyCategory {
+    /// mass extractor of tech level
+    MassExtractor(u8),
+    MassStorage,
+    EnergyStorage,
+    /// power generator of tech level
+    PowerGenerator(u8),
+    Factory,
+    /// mass fabricator of tech level
+    MassFabricator(u8),
+}
+
+impl AdjacencyCategory {
+    pub fn from_blueprint(blueprint: &Blueprint) -> Option<Self> {
+        if !blueprint.has_category("STRUCTURE") {
+            return None;
+        }
+        if blueprint.has_category("MASSEXTRACTION") {
+            blueprint.tech_level().map(AdjacencyCategory::MassExtractor)
+        } else if blueprint.has_category("MASSSTORAGE") {
+            Some(AdjacencyCategory::MassStorage)
+        } else if blueprint.has_category("ENERGYSTORAGE") {
+            Some(AdjacencyCategory::EnergyStorage)
+        } else if blueprint.has_category("MASSFABRICATION") {
+            blueprint
+                .tech_level()
+                .map(AdjacencyCategory::MassFabricator)
+        } else if blueprint.has_category("FACTORY") || blueprint.has_category("GATE") {
+            Some(AdjacencyCategory::Factory)
+        } else if blueprint.has_category("ENERGYPRODUCTION") {
+            blueprint
+                .tech_level()
+                .map(AdjacencyCategory::PowerGenerator)
+        } else {
+            None
+        }
+    }
+}
+
+/// Multipliers applied to an entity due to adjacent structures
+#[derive(Component, Clone, Debug, Serialize, Deserialize)]
+pub struct AdjacencyBonus {
+    /// multiplier for ResourceProducer mass yield
+    pub mass_production_multiplier: f64,
+    /// multiplier for ResourceProducer energy yield
+    pub energy_production_multiplier: f64,
+    /// multiplier for mass consumption
+    pub mass_consumption_multiplier: f64,
+    /// multiplier for energy consumption
+    pub energy_consumption_multiplier: f64,
+}
+
+impl Default for AdjacencyBonus {
+    fn default() -> Self {
+        AdjacencyBonus {
+            mass_production_multiplier: 1.0,
+            energy_production_multiplier: 1.0,
+            mass_consumption_multiplier: 1.0,
+            energy_consumption_multiplier: 1.0,
+        }
+    }
+}
+
+/// whether two footprints share an edge (touching, not overlapping)
+pub fn is_adjacent(a: (&GridPosition, &Footprint), b: (&GridPosition, &Footprint)) -> bool {
+    let ((a_position, a_size), (b_position, b_size)) = (a, b);
+    let x_overlap =
+        a_position.x < b_position.x + b_size.size_x && b_position.x < a_position.x + a_size.size_x;
+    let z_overlap =
+        a_position.z < b_position.z + b_size.size_z && b_position.z < a_position.z + a_size.size_z;
+    let x_touch = a_position.x + a_size.size_x == b_position.x
+        || b_position.x + b_size.size_x == a_position.x;
+    let z_touch = a_position.z + a_size.size_z == b_position.z
+        || b_position.z + b_size.size_z == a_position.z;
+    (x_touch && z_overlap) || (z_touch && x_overlap)
+}
+
+/// recalculate adjacency bonuses of all placed structures
+/// (only structures of the same army give bonuses to each other)
+pub fn update_adjacency_bonus(
+    providers: Query<(&Army, &GridPosition, &Footprint, &AdjacencyCategory), With<Executing>>,
+    mut receivers: Query<(
+        &Army,
+        &GridPosition,
+        &Footprint,
+        &AdjacencyCategory,
+        &mut AdjacencyBonus,
+    )>,
+) {
+    for (army, position, footprint, category, mut bonus) in &mut receivers {
+        let mut mass_production_bonus = 0.0;
+        let mut energy_production_bonus = 0.0;
+        let mut mass_consumption_bonus = 0.0;
+        let mut energy_consumption_bonus = 0.0;
+        // a structure is never adjacent to itself since the footprints overlap
+        for (other_army, other_position, other_footprint, other_category) in providers.iter() {
+            if army != other_army
+                || !is_adjacent((position, footprint), (other_position, other_footprint))
+            {
+                continue;
+            }
+            match (category, other_category) {
+                (AdjacencyCategory::MassExtractor(_), AdjacencyCategory::MassStorage) => {
+                    mass_production_bonus += MASS_STORAGE_MASS_PRODUCTION_BONUS;
+                }
+                (AdjacencyCategory::PowerGenerator(_), AdjacencyCategory::EnergyStorage) => {
+                    energy_production_bonus += ENERGY_STORAGE_ENERGY_PRODUCTION_BONUS;
+                }
+                (
+                    AdjacencyCategory::Factory | AdjacencyCategory::MassFabricator(_),
+                    AdjacencyCategory::PowerGenerator(tech_level),
+                ) => {
+                    energy_consumption_bonus +=
+                        POWER_GENERATOR_ENERGY_CONSUMPTION_BONUS[tech_index(*tech_level)];
+                }
+                (
+                    AdjacencyCategory::Factory,
+                    AdjacencyCategory::MassExtractor(tech_level)
+                    | AdjacencyCategory::MassFabricator(tech_level),
+                ) => {
+                    mass_consumption_bonus +=
+                        MASS_PRODUCER_MASS_CONSUMPTION_BONUS[tech_index(*tech_level)];
+                }
+                _ => {}
+            }
+        }
+        bonus.mass_production_multiplier = 1.0 + mass_production_bonus;
+        bonus.energy_production_multiplier = 1.0 + energy_production_bonus;
+        bonus.mass_consumption_multiplier = f64::max(0.0, 1.0 - mass_consumption_bonus);
+        bonus.energy_consumption_multiplier = f64::max(0.0, 1.0 - energy_consumption_bonus);
+    }
+}
+
+/// index into bonus tables by tech level
+fn tech_index(tech_level: u8) -> usize {
+    tech_level.clamp(1, 3) as usize - 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::replay::{issue, Order};
+    use crate::simulation::tests::*;
+
+    fn placed(x: i32, z: i32, size: i32) -> (GridPosition, Footprint) {
+        (
+            GridPosition { x, z },
+            Footprint {
+                size_x: size,
+                size_z: size,
+            },
+        )
+    }
+
+    fn adjacent(a: &(GridPosition, Footprint), b: &(GridPosition, Footprint)) -> bool {
+        let forward = is_adjacent((&a.0, &a.1), (&b.0, &b.1));
+        assert_eq!(forward, is_adjacent((&b.0, &b.1), (&a.0, &a.1)));
+        forward
+    }
+
+    #[test]
+    fn footprints_touching_along_an_edge_are_adjacent() {
+        let center = placed(0, 0, 2);
+        // each side, including partial edge contact
+        assert!(adjacent(&center, &placed(2, 0, 2)));
+        assert!(adjacent(&center, &placed(-2, 1, 2)));
+        assert!(adjacent(&center, &placed(1, 2, 2)));
+        // larger footprint sharing part of an edge
+        assert!(adjacent(&center, &placed(-5, -6, 6)));
+        // corners only touching
+        assert!(!adjacent(&center, &placed(2, 2, 2)));
+        assert!(!adjacent(&center, &placed(-1, -1, 1)));
+        assert!(!adjacent(&center, &placed(-2, -2, 2)));
+        // gap between footprints
+        assert!(!adjacent(&center, &placed(3, 0, 2)));
+        // overlapping, including with itself
+        assert!(!adjacent(&center, &placed(1, 1, 2)));
+        assert!(!adjacent(&center, &center));
+    }
+
+    /// spawn an executing structure placed on the grid
+    fn spawn_structure(
+        sim: &mut FASimulation,
+        army: Army,
+        category: AdjacencyCategory,
+        (position, footprint): (GridPosition, Footprint),
+    ) -> Entity {
+        sim.world
+            .spawn()
+            .insert(army)
+            .insert(Executing)
+            .insert(category)
+            .insert(position)
+            .insert(footprint)
+            .insert(AdjacencyBonus::default())
+            .id()
+    }
+
+    #[test]
+    fn bonuses_stack_per_adjacent_structure() {
+        let mut sim = test_simulation();
+        let extractor = spawn_structure(
+            &mut sim,
+            Army(0),
+            AdjacencyCategory::MassExtractor(1),
+            placed(0, 0, 2),
+        );
+        for position in [placed(2, 0, 2), placed(-2, 0, 2), placed(0, 2, 2)] {
+            spawn_structure(&mut sim, Army(0), AdjacencyCategory::MassStorage, position);
+        }
+        // other armies give no bonus
+        spawn_structure(
+            &mut sim,
+            Army(1),
+            AdjacencyCategory::MassStorage,
+            placed(0, -2, 2),
+        );
+
+        let factory = spawn_structure(
+            &mut sim,
+            Army(0),
+            AdjacencyCategory::Factory,
+            placed(10, 0, 6),
+        );
+        let neighbours = [
+            (AdjacencyCategory::PowerGenerator(1), placed(8, 0, 2)),
+            (AdjacencyCategory::PowerGenerator(3), placed(16, 0, 4)),
+            (AdjacencyCategory::MassExtractor(2), placed(10, 6, 2)),
+            (AdjacencyCategory::MassFabricator(3), placed(12, 6, 2)),
+            (AdjacencyCategory::MassStorage, placed(10, -2, 2)),
+        ];
+        for (category, position) in neighbours {
+            spawn_structure(&mut sim, Army(0), category, position);
+        }
+        // unfinished structures give no bonus
+        let unfinished = spawn_structure(
+            &mut sim,
+            Army(0),
+            AdjacencyCategory::PowerGenerator(3),
+            placed(14, -4, 4),
+        );
+        sim.world.entity_mut(unfinished).remove::<Executing>();
+        sim.run();
+
+        let bonus = |entity| sim.world.get::<AdjacencyBonus>(entity).unwrap().clone();
+        let extractor = bonus(extractor);
+        assert_eq!(
+            extractor.mass_production_multiplier,
+            1.0 + 3.0 * MASS_STORAGE_MASS_PRODUCTION_BONUS
+        );
+        assert_eq!(extractor.mass_consumption_multiplier, 1.0);
+
+        let factory = bonus(factory);
+        assert_eq!(factory.mass_production_multiplier, 1.0);
+        assert!((factory.energy_consumption_multiplier - (1.0 - 0.0625 - 0.1875)).abs() < 1e-9);
+        assert!((factory.mass_consumption_multiplier - (1.0 - 0.05 - 0.075)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn categories_from_blueprints() {
+        let sim = test_simulation();
+        let registry = sim.world.resource::<crate::registry::UnitRegistry>();
+        let category = |id| AdjacencyCategory::from_blueprint(registry.blueprint(id).unwrap());
+        assert_eq!(
+            category("ueb1103"),
+            Some(AdjacencyCategory::MassExtractor(1))
+        );
+        assert_eq!(
+            category("ueb1302"),
+            Some(AdjacencyCategory::MassExtractor(3))
+        );
+        assert_eq!(
+            category("ueb1303"),
+            Some(AdjacencyCategory::MassFabricator(3))
+        );
+        // not a structure
+        assert_eq!(category("uel0301_RAS"), None);
+    }
+
+    #[test]
+    fn spawned_units_are_placed_at_their_position() {
+        let mut sim = test_simulation();
+        let position = GridPosition { x: 4, z: 8 };
+        let extractor = issue(
+            &mut sim.world,
+            Order::SpawnUnit {
+                unit_id: "ueb1103".to_string(),
+                army: Army(0),
+                position: Some(position),
+            },
+        )
+        .unwrap();
+        assert_eq!(sim.world.get::<GridPosition>(extractor), Some(&position));
+        spawn_structure(
+            &mut sim,
+            Army(0),
+            AdjacencyCategory::MassStorage,
+            placed(6, 8, 2),
+        );
+        sim.world
+            .get_mut::<Damage>(extractor)
+            .unwrap()
+            .finish_construction();
+        sim.run();
+        let bonus = sim.world.get::<AdjacencyBonus>(extractor).unwrap();
+        assert_eq!(
+            bonus.mass_production_multiplier,
+            1.0 + MASS_STORAGE_MASS_PRODUCTION_BONUS
+        );
+    }
+}
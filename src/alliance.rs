@@ -0,0 +1,248 @@
+use std::collections::BTreeMap;
+
+use bevy_ecs::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::simulation::*;
+
+/// Allied armies which share resources overflowing their storage
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Alliances {
+    /// groups of allied armies
+    pub teams: Vec<Vec<Army>>,
+    /// share overflowing mass with allies
+    pub share_mass: bool,
+    /// share overflowing energy with allies
+    pub share_energy: bool,
+}
+
+impl Default for Alliances {
+    fn default() -> Self {
+        Alliances {
+            teams: Vec::new(),
+            share_mass: true,
+            share_energy: true,
+        }
+    }
+}
+
+impl Alliances {
+    /// declare armies as allied with each other
+    pub fn ally(&mut self, armies: &[Army]) {
+        self.teams.push(armies.to_vec());
+    }
+
+    /// all allies of an army, excluding itself
+    pub fn allies_of(&self, army: Army) -> Vec<Army> {
+        let mut allies: Vec<Army> = self
+            .teams
+            .iter()
+            .filter(|team| team.contains(&army))
+            .flatten()
+            .copied()
+            .filter(|ally| *ally != army)
+            .collect();
+        allies.sort();
+        allies.dedup();
+        allies
+    }
+}
+
+/// split overflow between recipients proportionally to their free storage,
+/// returns amount given to each recipient
+fn distribute(overflow: f64, free: &[f64]) -> Vec<f64> {
+    let total_free: f64 = free.iter().sum();
+    if total_free <= EPSILON {
+        return vec![0.0; free.len()];
+    }
+    free.iter()
+        .map(|free| f64::min(*free, overflow * free / total_free))
+        .collect()
+}
+
+/// give resources overflowing storage to allies with free storage
+pub fn share_overflow(alliances: Res<Alliances>, mut economies: ResMut<Economies>) {
+    if alliances.teams.is_empty() {
+        return;
+    }
+    let armies: Vec<Army> = economies.armies().collect();
+    // storage left in each economy, updated as overflow is handed out
+    let mut free_mass: BTreeMap<Army, f64> = economies
+        .iter()
+        .map(|(army, economy)| (army, economy.mass_capacity - economy.mass))
+        .collect();
+    let mut free_energy: BTreeMap<Army, f64> = economies
+        .iter()
+        .map(|(army, economy)| (army, economy.energy_capacity - economy.energy))
+        .collect();
+
+    for army in armies {
+        let allies: Vec<Army> = alliances
+            .allies_of(army)
+            .into_iter()
+            .filter(|ally| economies.get(*ally).is_some())
+            .collect();
+        if allies.is_empty() {
+            continue;
+        }
+        let (mass_overflow, energy_overflow) = {
+            let economy = economies.get(army).unwrap();
+            (economy.mass_overflow, economy.energy_overflow)
+        };
+
+        if alliances.share_mass && mass_overflow > 0.0 {
+            let free: Vec<f64> = allies.iter().map(|ally| free_mass[ally]).collect();
+            let given = distribute(mass_overflow, &free);
+            for (ally, amount) in allies.iter().zip(&given) {
+                *free_mass.get_mut(ally).unwrap() -= amount;
+                let ally_economy = economies.get_mut(*ally).unwrap();
+                ally_economy.mass += amount;
+                ally_economy.mass_received += amount;
+                ally_economy.total_mass_received += amount;
+            }
+            let shared: f64 = given.iter().sum();
+            let economy = economies.get_mut(army).unwrap();
+            economy.mass_shared = shared;
+            economy.total_mass_shared += shared;
+            // shared overflow was counted as wasted during accounting
+            economy.mass_wasted -= shared;
+            economy.total_mass_wasted -= shared;
+        }
+
+        if alliances.share_energy && energy_overflow > 0.0 {
+            let free: Vec<f64> = allies.iter().map(|ally| free_energy[ally]).collect();
+            let given = distribute(energy_overflow, &free);
+            for (ally, amount) in allies.iter().zip(&given) {
+                *free_energy.get_mut(ally).unwrap() -= amount;
+                let ally_economy = economies.get_mut(*ally).unwrap();
+                ally_economy.energy += amount;
+                ally_economy.energy_received += amount;
+                ally_economy.total_energy_received += amount;
+            }
+            let shared: f64 = given.iter().sum();
+            let economy = economies.get_mut(army).unwrap();
+            economy.energy_shared = shared;
+            economy.total_energy_shared += shared;
+            economy.energy_wasted -= shared;
+            economy.total_energy_wasted -= shared;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::tests::*;
+
+    /// simulation with full storage for army 0, which produces mass and energy
+    fn overflowing_simulation(mass_yield: f64, energy_yield: f64) -> FASimulation {
+        let mut sim = test_simulation();
+        sim.add_army(
+            Army(0),
+            Economy {
+                mass: 100.0,
+                energy: 1000.0,
+                mass_capacity: 100.0,
+                energy_capacity: 1000.0,
+                ..Default::default()
+            },
+        );
+        sim.world
+            .spawn()
+            .insert(Army(0))
+            .insert(Executing)
+            .insert(ResourceProducer {
+                mass_yield,
+                energy_yield,
+                ..Default::default()
+            });
+        sim
+    }
+
+    #[test]
+    fn allies_exclude_self_and_duplicates() {
+        let mut alliances = Alliances::default();
+        alliances.ally(&[Army(0), Army(1)]);
+        alliances.ally(&[Army(2), Army(0), Army(1)]);
+        assert_eq!(alliances.allies_of(Army(0)), vec![Army(1), Army(2)]);
+        assert_eq!(alliances.allies_of(Army(3)), Vec::<Army>::new());
+    }
+
+    #[test]
+    fn distribute_by_free_storage() {
+        assert_eq!(distribute(8.0, &[30.0, 10.0]), vec![6.0, 2.0]);
+        // recipients never get more than they can store
+        assert_eq!(distribute(100.0, &[30.0, 10.0]), vec![30.0, 10.0]);
+        assert_eq!(distribute(5.0, &[0.0, 0.0]), vec![0.0, 0.0]);
+    }
+
+    #[test]
+    fn overflow_shared_with_allies() {
+        let mut sim = overflowing_simulation(10.0, 0.0);
+        for (army, mass) in [(1, 70.0), (2, 90.0), (3, 0.0)] {
+            sim.add_army(
+                Army(army),
+                Economy {
+                    mass,
+                    mass_capacity: 100.0,
+                    ..Default::default()
+                },
+            );
+        }
+        sim.world
+            .resource_mut::<Alliances>()
+            .ally(&[Army(0), Army(1), Army(2)]);
+        sim.run();
+
+        let economy = |army| sim.economy(Army(army)).unwrap();
+        assert!((economy(0).mass_shared - 10.0).abs() < 1e-9);
+        assert!((economy(1).mass - 77.5).abs() < 1e-9);
+        assert!((economy(1).mass_received - 7.5).abs() < 1e-9);
+        assert!((economy(2).mass - 92.5).abs() < 1e-9);
+        assert!((economy(2).mass_received - 2.5).abs() < 1e-9);
+        // not allied
+        assert_eq!(economy(3).mass, 0.0);
+        assert_eq!(economy(3).mass_received, 0.0);
+    }
+
+    #[test]
+    fn sharing_can_be_disabled() {
+        let mut sim = overflowing_simulation(10.0, 10.0);
+        sim.add_army(Army(1), Economy::default());
+        {
+            let mut alliances = sim.world.resource_mut::<Alliances>();
+            alliances.ally(&[Army(0), Army(1)]);
+            alliances.share_mass = false;
+        }
+        sim.run();
+        let ally = sim.economy(Army(1)).unwrap();
+        assert_eq!(ally.mass, 0.0);
+        assert!((ally.energy - 10.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn overflow_beyond_ally_storage_is_wasted() {
+        let mut sim = overflowing_simulation(10.0, 0.0);
+        sim.add_army(
+            Army(1),
+            Economy {
+                mass: 97.0,
+                mass_capacity: 100.0,
+                ..Default::default()
+            },
+        );
+        sim.world
+            .resource_mut::<Alliances>()
+            .ally(&[Army(0), Army(1)]);
+        sim.run();
+        sim.run();
+
+        let economy = sim.economy(Army(0)).unwrap();
+        // first tick 3 fits in the ally's storage, second tick nothing does
+        assert!((economy.total_mass_shared - 3.0).abs() < 1e-9);
+        assert_eq!(economy.mass_shared, 0.0);
+        assert!((economy.mass_wasted - 10.0).abs() < 1e-9);
+        assert!((economy.total_mass_wasted - 17.0).abs() < 1e-9);
+        assert_eq!(sim.economy(Army(1)).unwrap().total_mass_wasted, 0.0);
+    }
+}
@@ -0,0 +1,721 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::fabricator::MassFabricator;
+use crate::simulation::*;
+
+/// Lua value as found in FA blueprint files
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum LuaValue {
+    Nil,
+    Boolean(bool),
+    Number(f64),
+    String(String),
+    Table(LuaTable),
+}
+
+/// Lua table, split into named fields and positional items
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LuaTable {
+    /// fields with keys (`Key = value` or `['key'] = value`)
+    pub fields: BTreeMap<String, LuaValue>,
+    /// positional items (`{ 'a', 'b' }`)
+    pub items: Vec<LuaValue>,
+}
+
+impl LuaTable {
+    pub fn get(&self, key: &str) -> Option<&LuaValue> {
+        self.fields.get(key)
+    }
+
+    pub fn get_table(&self, key: &str) -> Option<&LuaTable> {
+        match self.get(key) {
+            Some(LuaValue::Table(table)) => Some(table),
+            _ => None,
+        }
+    }
+
+    pub fn get_number(&self, key: &str) -> Option<f64> {
+        match self.get(key) {
+            Some(LuaValue::Number(number)) => Some(*number),
+            _ => None,
+        }
+    }
+
+    pub fn get_string(&self, key: &str) -> Option<&str> {
+        match self.get(key) {
+            Some(LuaValue::String(string)) => Some(string),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum BlueprintError {
+    Io(std::io::Error),
+    /// syntax error at line
+    Parse {
+        line: usize,
+        message: String,
+    },
+    /// required blueprint field not present
+    MissingField(&'static str),
+    /// two blueprints in a directory have the same id
+    DuplicateId(String),
+}
+
+impl fmt::Display for BlueprintError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlueprintError::Io(err) => write!(f, "failed to read blueprint: {}", err),
+            BlueprintError::Parse { line, message } => {
+                write!(f, "blueprint syntax error on line {}: {}", line, message)
+            }
+            BlueprintError::MissingField(field) => {
+                write!(f, "blueprint is missing required field {}", field)
+            }
+            BlueprintError::DuplicateId(id) => write!(f, "duplicate blueprint id {}", id),
+        }
+    }
+}
+
+impl std::error::Error for BlueprintError {}
+
+impl From<std::io::Error> for BlueprintError {
+    fn from(err: std::io::Error) -> Self {
+        BlueprintError::Io(err)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Identifier(String),
+    Number(f64),
+    String(String),
+    Symbol(char),
+}
+
+/// tokenizer and recursive descent parser for the subset of Lua used by
+/// blueprint files (table constructors, strings, numbers, booleans, comments)
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    position: usize,
+}
+
+impl Parser {
+    fn tokenize(source: &str) -> Result<Vec<(Token, usize)>, BlueprintError> {
+        let chars: Vec<char> = source.chars().collect();
+        let mut tokens = Vec::new();
+        let mut line = 1;
+        let mut i = 0;
+        let error = |line: usize, message: String| BlueprintError::Parse { line, message };
+
+        while i < chars.len() {
+            let c = chars[i];
+            if c == '\n' {
+                line += 1;
+                i += 1;
+            } else if c.is_whitespace() {
+                i += 1;
+            } else if c == '-' && chars.get(i + 1) == Some(&'-') || c == '#' {
+                // comment, FA also accepts '#' line comments
+                if c == '-' && chars.get(i + 2) == Some(&'[') && chars.get(i + 3) == Some(&'[') {
+                    i += 4;
+                    while i < chars.len() && !(chars[i] == ']' && chars.get(i + 1) == Some(&']')) {
+                        if chars[i] == '\n' {
+                            line += 1;
+                        }
+                        i += 1;
+                    }
+                    i += 2;
+                } else {
+                    while i < chars.len() && chars[i] != '\n' {
+                        i += 1;
+                    }
+                }
+            } else if c == '\'' || c == '"' {
+                let quote = c;
+                let mut value = String::new();
+                i += 1;
+                loop {
+                    match chars.get(i) {
+                        None | Some('\n') => {
+                            return Err(error(line, "unterminated string".to_string()))
+                        }
+                        Some('\\') => {
+                            match chars.get(i + 1) {
+                                Some('n') => value.push('\n'),
+                                Some('t') => value.push('\t'),
+                                Some(other) => value.push(*other),
+                                None => return Err(error(line, "unterminated string".to_string())),
+                            }
+                            i += 2;
+                        }
+                        Some(next) if *next == quote => {
+                            i += 1;
+                            break;
+                        }
+                        Some(next) => {
+                            value.push(*next);
+                            i += 1;
+                        }
+                    }
+                }
+                tokens.push((Token::String(value), line));
+            } else if c.is_ascii_digit()
+                || (c == '.' && chars.get(i + 1).is_some_and(|n| n.is_ascii_digit()))
+            {
+                let start = i;
+                while i < chars.len()
+                    && (chars[i].is_ascii_alphanumeric()
+                        || chars[i] == '.'
+                        || ((chars[i] == '-' || chars[i] == '+')
+                            && matches!(chars[i - 1], 'e' | 'E')))
+                {
+                    i += 1;
+                }
+                let text: String = chars[start..i].iter().collect();
+                let number = if let Some(hex) = text.strip_prefix("0x") {
+                    i64::from_str_radix(hex, 16).map(|n| n as f64).ok()
+                } else {
+                    text.parse::<f64>().ok()
+                };
+                match number {
+                    Some(number) => tokens.push((Token::Number(number), line)),
+                    None => return Err(error(line, format!("invalid number {}", text))),
+                }
+            } else if c.is_alphabetic() || c == '_' {
+                let start = i;
+                while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                    i += 1;
+                }
+                tokens.push((Token::Identifier(chars[start..i].iter().collect()), line));
+            } else if "{}[]=,;-".contains(c) {
+                tokens.push((Token::Symbol(c), line));
+                i += 1;
+            } else {
+                return Err(error(line, format!("unexpected character {:?}", c)));
+            }
+        }
+
+        Ok(tokens)
+    }
+
+    fn line(&self) -> usize {
+        self.tokens
+            .get(self.position)
+            .or_else(|| self.tokens.last())
+            .map_or(1, |(_, line)| *line)
+    }
+
+    fn error(&self, message: impl Into<String>) -> BlueprintError {
+        BlueprintError::Parse {
+            line: self.line(),
+            message: message.into(),
+        }
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position).map(|(token, _)| token)
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<&Token> {
+        self.tokens
+            .get(self.position + offset)
+            .map(|(token, _)| token)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self
+            .tokens
+            .get(self.position)
+            .map(|(token, _)| token.clone());
+        self.position += 1;
+        token
+    }
+
+    fn expect_symbol(&mut self, symbol: char) -> Result<(), BlueprintError> {
+        match self.next() {
+            Some(Token::Symbol(c)) if c == symbol => Ok(()),
+            _ => {
+                self.position -= 1;
+                Err(self.error(format!("expected '{}'", symbol)))
+            }
+        }
+    }
+
+    fn parse_value(&mut self) -> Result<LuaValue, BlueprintError> {
+        match self.next() {
+            Some(Token::Number(number)) => Ok(LuaValue::Number(number)),
+            Some(Token::String(string)) => Ok(LuaValue::String(string)),
+            Some(Token::Symbol('-')) => match self.next() {
+                Some(Token::Number(number)) => Ok(LuaValue::Number(-number)),
+                _ => Err(self.error("expected number after '-'")),
+            },
+            Some(Token::Symbol('{')) => Ok(LuaValue::Table(self.parse_table_body()?)),
+            Some(Token::Identifier(identifier)) => match identifier.as_str() {
+                "true" => Ok(LuaValue::Boolean(true)),
+                "false" => Ok(LuaValue::Boolean(false)),
+                "nil" => Ok(LuaValue::Nil),
+                // constructor call such as `Sound { ... }` or `UnitBlueprint { ... }`
+                _ => match self.next() {
+                    Some(Token::Symbol('{')) => Ok(LuaValue::Table(self.parse_table_body()?)),
+                    Some(Token::String(string)) => Ok(LuaValue::String(string)),
+                    _ => Err(self.error(format!("unexpected identifier {}", identifier))),
+                },
+            },
+            Some(token) => {
+                self.position -= 1;
+                Err(self.error(format!("unexpected token {:?}", token)))
+            }
+            None => Err(self.error("unexpected end of input")),
+        }
+    }
+
+    /// parse table contents following the opening brace
+    fn parse_table_body(&mut self) -> Result<LuaTable, BlueprintError> {
+        let mut table = LuaTable::default();
+        loop {
+            match self.peek() {
+                Some(Token::Symbol('}')) => {
+                    self.position += 1;
+                    return Ok(table);
+                }
+                Some(Token::Symbol('[')) => {
+                    self.position += 1;
+                    let key = match self.parse_value()? {
+                        LuaValue::String(string) => string,
+                        LuaValue::Number(number) => number.to_string(),
+                        _ => return Err(self.error("unsupported table key")),
+                    };
+                    self.expect_symbol(']')?;
+                    self.expect_symbol('=')?;
+                    let value = self.parse_value()?;
+                    table.fields.insert(key, value);
+                }
+                Some(Token::Identifier(key)) if self.peek_at(1) == Some(&Token::Symbol('=')) => {
+                    let key = key.clone();
+                    self.position += 2;
+                    let value = self.parse_value()?;
+                    table.fields.insert(key, value);
+                }
+                Some(_) => {
+                    let value = self.parse_value()?;
+                    table.items.push(value);
+                }
+                None => return Err(self.error("unterminated table")),
+            }
+            match self.peek() {
+                Some(Token::Symbol(',')) | Some(Token::Symbol(';')) => self.position += 1,
+                Some(Token::Symbol('}')) => {}
+                _ => return Err(self.error("expected ',' or '}'")),
+            }
+        }
+    }
+}
+
+/// Parse a Lua table constructor expression, such as the contents of a .bp file
+pub fn parse_lua_table(source: &str) -> Result<LuaTable, BlueprintError> {
+    let mut parser = Parser {
+        tokens: Parser::tokenize(source)?,
+        position: 0,
+    };
+    let value = parser.parse_value()?;
+    if parser.peek().is_some() {
+        return Err(parser.error("trailing data after blueprint"));
+    }
+    match value {
+        LuaValue::Table(table) => Ok(table),
+        _ => Err(parser.error("blueprint is not a table")),
+    }
+}
+
+/// Unit stats extracted from a FA unit blueprint
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Blueprint {
+    /// blueprint id (example: uel0301_RAS)
+    pub id: String,
+    /// Economy.BuildCostMass
+    pub build_cost_mass: f64,
+    /// Economy.BuildCostEnergy
+    pub build_cost_energy: f64,
+    /// Economy.BuildTime
+    pub build_time: f64,
+    /// Economy.BuildRate (build_time per second)
+    pub build_rate: f64,
+    /// Economy.ProductionPerSecondMass
+    pub production_per_second_mass: f64,
+    /// Economy.ProductionPerSecondEnergy
+    pub production_per_second_energy: f64,
+    /// Economy.MaintenanceConsumptionPerSecondEnergy
+    pub maintenance_consumption_per_second_energy: f64,
+    /// Defense.MaxHealth
+    pub max_health: f64,
+    /// full blueprint table
+    pub table: LuaTable,
+}
+
+impl Blueprint {
+    /// Parse blueprint source
+    pub fn parse(id: &str, source: &str) -> Result<Blueprint, BlueprintError> {
+        let table = parse_lua_table(source)?;
+        let economy = table
+            .get_table("Economy")
+            .ok_or(BlueprintError::MissingField("Economy"))?;
+        let required = |field: &'static str, name: &'static str| {
+            economy
+                .get_number(field)
+                .ok_or(BlueprintError::MissingField(name))
+        };
+        let build_cost_mass = required("BuildCostMass", "Economy.BuildCostMass")?;
+        let build_cost_energy = required("BuildCostEnergy", "Economy.BuildCostEnergy")?;
+        let build_time = required("BuildTime", "Economy.BuildTime")?;
+        let build_rate = economy.get_number("BuildRate").unwrap_or(0.0);
+        let production_per_second_mass =
+            economy.get_number("ProductionPerSecondMass").unwrap_or(0.0);
+        let production_per_second_energy = economy
+            .get_number("ProductionPerSecondEnergy")
+            .unwrap_or(0.0);
+        let maintenance_consumption_per_second_energy = economy
+            .get_number("MaintenanceConsumptionPerSecondEnergy")
+            .unwrap_or(0.0);
+        let max_health = table
+            .get_table("Defense")
+            .and_then(|defense| defense.get_number("MaxHealth"))
+            .ok_or(BlueprintError::MissingField("Defense.MaxHealth"))?;
+
+        Ok(Blueprint {
+            id: id.to_string(),
+            build_cost_mass,
+            build_cost_energy,
+            build_time,
+            build_rate,
+            production_per_second_mass,
+            production_per_second_energy,
+            maintenance_consumption_per_second_energy,
+            max_health,
+            table,
+        })
+    }
+
+    /// Load blueprint from file, deriving id from file name (uel0301_unit.bp -> uel0301)
+    pub fn load(path: impl AsRef<Path>) -> Result<Blueprint, BlueprintError> {
+        let path = path.as_ref();
+        let source = std::fs::read_to_string(path)?;
+        let stem = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or_default();
+        let id = stem.strip_suffix("_unit").unwrap_or(stem);
+        Blueprint::parse(id, &source)
+    }
+
+    /// unit categories (example: STRUCTURE, TECH1, MASSEXTRACTION)
+    pub fn categories(&self) -> impl Iterator<Item = &str> {
+        self.table
+            .get_table("Categories")
+            .into_iter()
+            .flat_map(|categories| categories.items.iter())
+            .filter_map(|category| match category {
+                LuaValue::String(category) => Some(category.as_str()),
+                _ => None,
+            })
+    }
+
+    pub fn has_category(&self, category: &str) -> bool {
+        self.categories().any(|c| c == category)
+    }
+
+    /// tech level from TECH1/TECH2/TECH3 categories
+    pub fn tech_level(&self) -> Option<u8> {
+        if self.has_category("TECH1") {
+            Some(1)
+        } else if self.has_category("TECH2") {
+            Some(2)
+        } else if self.has_category("TECH3") {
+            Some(3)
+        } else {
+            None
+        }
+    }
+
+    /// structure size on the build grid, from Physics.SkirtSizeX/Z or Footprint.SizeX/Z
+    pub fn footprint_size(&self) -> Option<(i32, i32)> {
+        let size = |table: &str, x: &str, z: &str| {
+            let table = self.table.get_table(table)?;
+            Some((table.get_number(x)? as i32, table.get_number(z)? as i32))
+        };
+        size("Physics", "SkirtSizeX", "SkirtSizeZ").or_else(|| size("Footprint", "SizeX", "SizeZ"))
+    }
+
+    /// Damage component for an unbuilt instance of this unit
+    pub fn damage(&self) -> Damage {
+        Damage {
+            mass_total: self.build_cost_mass,
+            energy_total: self.build_cost_energy,
+            build_time: self.build_time,
+            build_progress: 0.0,
+            health: 0.0,
+            health_points: self.max_health as u64,
+        }
+    }
+
+    /// Engineering component, if unit can build
+    pub fn engineering(&self) -> Option<Engineering> {
+        if self.build_rate > 0.0 {
+            Some(Engineering {
+                build_rate: self.build_rate / TICK_RATE,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// whether this unit converts energy to mass
+    pub fn is_mass_fabricator(&self) -> bool {
+        self.has_category("MASSFABRICATION") && self.maintenance_consumption_per_second_energy > 0.0
+    }
+
+    /// MassFabricator component, if unit converts energy to mass
+    pub fn mass_fabricator(&self) -> Option<MassFabricator> {
+        if self.is_mass_fabricator() {
+            Some(MassFabricator::new(
+                self.maintenance_consumption_per_second_energy / TICK_RATE,
+                self.production_per_second_mass / TICK_RATE,
+            ))
+        } else {
+            None
+        }
+    }
+
+    /// ResourceProducer component, if unit produces resources
+    /// (mass from fabricators is produced by conversion instead)
+    pub fn resource_producer(&self) -> Option<ResourceProducer> {
+        let production_per_second_mass = if self.is_mass_fabricator() {
+            0.0
+        } else {
+            self.production_per_second_mass
+        };
+        if production_per_second_mass > 0.0 || self.production_per_second_energy > 0.0 {
+            Some(ResourceProducer {
+                mass_yield: production_per_second_mass / TICK_RATE,
+                energy_yield: self.production_per_second_energy / TICK_RATE,
+                ..Default::default()
+            })
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SACU: &str = include_str!("../blueprints/uel0301_RAS_unit.bp");
+    const MASS_FABRICATOR: &str = include_str!("../blueprints/ueb1303_unit.bp");
+    const MASS_EXTRACTOR: &str = include_str!("../blueprints/ueb1103_unit.bp");
+
+    fn tokens(source: &str) -> Vec<Token> {
+        Parser::tokenize(source)
+            .unwrap()
+            .into_iter()
+            .map(|(token, _)| token)
+            .collect()
+    }
+
+    #[test]
+    fn tokenize_values() {
+        assert_eq!(
+            tokens("Key = { 'a', \"b\\\"c\", 1.5, 2e3, 0x10, .5, -3 }"),
+            vec![
+                Token::Identifier("Key".to_string()),
+                Token::Symbol('='),
+                Token::Symbol('{'),
+                Token::String("a".to_string()),
+                Token::Symbol(','),
+                Token::String("b\"c".to_string()),
+                Token::Symbol(','),
+                Token::Number(1.5),
+                Token::Symbol(','),
+                Token::Number(2000.0),
+                Token::Symbol(','),
+                Token::Number(16.0),
+                Token::Symbol(','),
+                Token::Number(0.5),
+                Token::Symbol(','),
+                Token::Symbol('-'),
+                Token::Number(3.0),
+                Token::Symbol('}'),
+            ]
+        );
+    }
+
+    #[test]
+    fn tokenize_skips_comments_and_counts_lines() {
+        let tokens = Parser::tokenize("-- line\n# hash\n--[[ block\ncomment ]] a\nb").unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                (Token::Identifier("a".to_string()), 4),
+                (Token::Identifier("b".to_string()), 5),
+            ]
+        );
+    }
+
+    #[test]
+    fn tokenize_errors() {
+        for (source, line) in [("a = 'open\n", 1), ("\n\na = @", 3), ("x = 1.2.3", 1)] {
+            match Parser::tokenize(source) {
+                Err(BlueprintError::Parse {
+                    line: error_line, ..
+                }) => assert_eq!(error_line, line),
+                other => panic!("expected parse error for {:?}, got {:?}", source, other),
+            }
+        }
+    }
+
+    #[test]
+    fn parse_nested_tables() {
+        let table = parse_lua_table(
+            "UnitBlueprint {
+                Name = 'unit', -- trailing comment
+                Nested = { Inner = { Value = 2 }; Flag = true, Missing = nil },
+                ['Quoted Key'] = -4,
+                [1] = 'first',
+                List = { 'a', Sound { Bank = 'b' } },
+            }",
+        )
+        .unwrap();
+        assert_eq!(table.get_string("Name"), Some("unit"));
+        let nested = table.get_table("Nested").unwrap();
+        assert_eq!(
+            nested.get_table("Inner").unwrap().get_number("Value"),
+            Some(2.0)
+        );
+        assert_eq!(nested.get("Flag"), Some(&LuaValue::Boolean(true)));
+        assert_eq!(nested.get("Missing"), Some(&LuaValue::Nil));
+        assert_eq!(table.get_number("Quoted Key"), Some(-4.0));
+        assert_eq!(table.get_string("1"), Some("first"));
+        let list = table.get_table("List").unwrap();
+        assert_eq!(list.items.len(), 2);
+        assert_eq!(list.items[0], LuaValue::String("a".to_string()));
+        match &list.items[1] {
+            LuaValue::Table(sound) => assert_eq!(sound.get_string("Bank"), Some("b")),
+            other => panic!("expected table, got {:?}", other),
+        }
+        // wrong type and missing keys
+        assert_eq!(table.get_number("Name"), None);
+        assert_eq!(table.get_table("Absent"), None);
+    }
+
+    #[test]
+    fn parse_errors() {
+        for (source, line) in [
+            ("{ a = 1 b = 2 }", 1),
+            ("{\n a = {\n", 2),
+            ("{ a = }", 1),
+            ("{ a = 1 } extra", 1),
+            ("'not a table'", 1),
+        ] {
+            match parse_lua_table(source) {
+                Err(BlueprintError::Parse {
+                    line: error_line, ..
+                }) => assert_eq!(error_line, line),
+                other => panic!("expected parse error for {:?}, got {:?}", source, other),
+            }
+        }
+    }
+
+    #[test]
+    fn parse_blueprint_fields() {
+        let blueprint = Blueprint::parse("uel0301_RAS", SACU).unwrap();
+        assert_eq!(blueprint.id, "uel0301_RAS");
+        assert_eq!(blueprint.build_cost_mass, 6450.0);
+        assert_eq!(blueprint.build_cost_energy, 117100.0);
+        assert_eq!(blueprint.build_time, 22800.0);
+        assert_eq!(blueprint.build_rate, 56.0);
+        assert_eq!(blueprint.production_per_second_mass, 11.0);
+        assert_eq!(blueprint.production_per_second_energy, 1020.0);
+        assert_eq!(blueprint.maintenance_consumption_per_second_energy, 0.0);
+        assert_eq!(blueprint.max_health, 15000.0);
+        assert!(blueprint.has_category("ENGINEER"));
+        assert_eq!(blueprint.tech_level(), Some(3));
+        assert_eq!(blueprint.footprint_size(), None);
+    }
+
+    #[test]
+    fn missing_required_fields() {
+        let missing = |source: &str| match Blueprint::parse("test", source) {
+            Err(BlueprintError::MissingField(field)) => field,
+            other => panic!("expected missing field, got {:?}", other),
+        };
+        assert_eq!(missing("{ Defense = { MaxHealth = 1 } }"), "Economy");
+        assert_eq!(
+            missing("{ Economy = { BuildCostEnergy = 1, BuildTime = 1 } }"),
+            "Economy.BuildCostMass"
+        );
+        assert_eq!(
+            missing("{ Economy = { BuildCostMass = 1, BuildCostEnergy = 1, BuildTime = 1 } }"),
+            "Defense.MaxHealth"
+        );
+    }
+
+    #[test]
+    fn load_derives_id_from_file_name() {
+        let path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/blueprints/uel0301_RAS_unit.bp"
+        );
+        assert_eq!(Blueprint::load(path).unwrap().id, "uel0301_RAS");
+        assert!(matches!(
+            Blueprint::load("does/not/exist_unit.bp"),
+            Err(BlueprintError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn engineer_components() {
+        let blueprint = Blueprint::parse("uel0301_RAS", SACU).unwrap();
+        let damage = blueprint.damage();
+        assert_eq!(damage.mass_total, 6450.0);
+        assert_eq!(damage.energy_total, 117100.0);
+        assert_eq!(damage.build_time, 22800.0);
+        assert_eq!(damage.health_points, 15000);
+        assert_eq!(damage.health, 0.0);
+        assert_eq!(damage.build_progress, 0.0);
+        assert_eq!(
+            blueprint.engineering().unwrap().build_rate,
+            56.0 / TICK_RATE
+        );
+        let producer = blueprint.resource_producer().unwrap();
+        assert_eq!(producer.mass_yield, 11.0 / TICK_RATE);
+        assert_eq!(producer.energy_yield, 1020.0 / TICK_RATE);
+        assert!(blueprint.mass_fabricator().is_none());
+    }
+
+    #[test]
+    fn mass_fabricator_components() {
+        let blueprint = Blueprint::parse("ueb1303", MASS_FABRICATOR).unwrap();
+        assert!(blueprint.is_mass_fabricator());
+        assert!(blueprint.mass_fabricator().is_some());
+        // fabricator mass comes from conversion, not production
+        assert!(blueprint.resource_producer().is_none());
+        assert!(blueprint.engineering().is_none());
+        // skirt size is preferred over footprint size
+        assert_eq!(blueprint.footprint_size(), Some((6, 6)));
+    }
+
+    #[test]
+    fn mass_extractor_components() {
+        let blueprint = Blueprint::parse("ueb1103", MASS_EXTRACTOR).unwrap();
+        assert!(!blueprint.is_mass_fabricator());
+        let producer = blueprint.resource_producer().unwrap();
+        assert_eq!(producer.mass_yield, 2.0 / TICK_RATE);
+        assert_eq!(producer.energy_yield, 0.0);
+        assert_eq!(blueprint.tech_level(), Some(1));
+    }
+}
@@ -0,0 +1,399 @@
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use bevy_ecs::prelude::*;
+
+use crate::factory::Factory;
+use crate::registry::{UnitId, UnitRegistry};
+use crate::replay::{issue, Order};
+use crate::simulation::*;
+use crate::snapshot::Snapshots;
+
+/// Number of finished units of a type an army should have
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Goal {
+    pub unit_type: String,
+    pub count: usize,
+}
+
+impl Goal {
+    /// finished units of the goal's type belonging to army
+    fn finished(&self, world: &mut World, army: Army) -> usize {
+        let mut query = world.query_filtered::<(&Army, &UnitId, &Damage), Without<Destroyed>>();
+        query
+            .iter(world)
+            .filter(|(unit_army, unit_id, damage)| {
+                **unit_army == army && unit_id.0 == self.unit_type && damage.is_finished()
+            })
+            .count()
+    }
+}
+
+impl FromStr for Goal {
+    type Err = String;
+
+    /// unit type with an optional count, such as `xsb2401` or `ueb1303:4`
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (unit_type, count) = match value.split_once(':') {
+            Some((unit_type, count)) => (
+                unit_type,
+                count
+                    .parse()
+                    .map_err(|err| format!("invalid count {:?}: {}", count, err))?,
+            ),
+            None => (value, 1),
+        };
+        if unit_type.is_empty() {
+            return Err("missing unit type".to_string());
+        }
+        Ok(Goal {
+            unit_type: unit_type.to_string(),
+            count,
+        })
+    }
+}
+
+/// Parameters of a build order search
+#[derive(Clone, Debug)]
+pub struct BuildOrderSearch {
+    /// army whose builders follow the build order
+    pub army: Army,
+    /// unit types which may be built before the goals
+    pub catalogue: Vec<String>,
+    /// goals which must all be met, built in this order once the build order
+    /// is done
+    pub goals: Vec<Goal>,
+    /// build orders kept after each step
+    pub beam_width: usize,
+    /// most units built before building the goals
+    pub max_length: usize,
+    /// give up on build orders not done by this tick, counted from the start
+    /// of the simulation rather than from the tick the search starts at
+    pub max_ticks: u64,
+    /// threads used to evaluate build orders
+    pub threads: usize,
+}
+
+/// Unit built by a build order and the tick it finished
+#[derive(Clone, Debug)]
+pub struct BuildStep {
+    pub unit_type: String,
+    pub finished_tick: u64,
+}
+
+/// Build order meeting every goal
+#[derive(Clone, Debug)]
+pub struct BuildOrderPlan {
+    /// units built before the goals
+    pub prefix: Vec<BuildStep>,
+    /// goal units built after the prefix
+    pub goals: Vec<BuildStep>,
+    /// tick all goals were met
+    pub ticks: u64,
+}
+
+/// Result of a build order search
+#[derive(Clone, Debug)]
+pub struct BuildOrderReport {
+    pub best: BuildOrderPlan,
+    /// plan building the goals directly
+    pub direct: BuildOrderPlan,
+    /// number of build orders simulated to completion
+    pub evaluations: usize,
+    /// number of build orders given up on for not meeting the goals by
+    /// max_ticks
+    pub timed_out: usize,
+}
+
+#[derive(Debug)]
+pub enum BuildOrderError {
+    /// unit type not in the registry
+    UnknownUnit(String),
+    /// nothing to search for
+    NoGoals,
+    /// goals can't be met by max_ticks, even building them directly
+    Unreachable,
+}
+
+impl fmt::Display for BuildOrderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BuildOrderError::UnknownUnit(id) => write!(f, "unit {} is not registered", id),
+            BuildOrderError::NoGoals => write!(f, "no goals given"),
+            BuildOrderError::Unreachable => {
+                write!(f, "goals can't be met within the tick limit")
+            }
+        }
+    }
+}
+
+impl std::error::Error for BuildOrderError {}
+
+/// entities of army able to build, including builders finished this tick
+fn builders(world: &mut World, army: Army) -> Vec<Entity> {
+    let mut query = world.query_filtered::<(Entity, &Army, Option<&Damage>), (
+        With<Engineering>,
+        With<ResourceConsumer>,
+        Without<Factory>,
+        Or<(With<Executing>, With<WillExecuteOnConstruct>)>,
+    )>();
+    query
+        .iter(world)
+        .filter(|(_, builder_army, damage)| {
+            **builder_army == army && damage.is_none_or(|damage| damage.is_finished())
+        })
+        .map(|(entity, _, _)| entity)
+        .collect()
+}
+
+/// build one unit with every builder of army, running the simulation until
+/// it is finished, returns the tick it finished at
+fn build(sim: &mut FASimulation, army: Army, unit_type: &str, max_ticks: u64) -> Option<u64> {
+    let target = sim.spawn_unit(unit_type, army)?;
+    let entities = builders(&mut sim.world, army);
+    issue(&mut sim.world, Order::Construct { entities, target });
+    loop {
+        let tick = sim.world.resource::<CurrentTick>().0;
+        if sim
+            .world
+            .get::<Damage>(target)
+            .is_some_and(|damage| damage.is_finished())
+        {
+            return Some(tick);
+        }
+        if tick >= max_ticks {
+            return None;
+        }
+        sim.run();
+    }
+}
+
+/// build goal units one at a time until every goal is met
+fn complete_goals(sim: &mut FASimulation, search: &BuildOrderSearch) -> Option<Vec<BuildStep>> {
+    let mut steps = Vec::new();
+    for goal in &search.goals {
+        for _ in goal.finished(&mut sim.world, search.army)..goal.count {
+            let finished_tick = build(sim, search.army, &goal.unit_type, search.max_ticks)?;
+            steps.push(BuildStep {
+                unit_type: goal.unit_type.clone(),
+                finished_tick,
+            });
+        }
+    }
+    Some(steps)
+}
+
+/// Partial build order and the simulation after following it
+struct Candidate {
+    sim: FASimulation,
+    prefix: Vec<BuildStep>,
+}
+
+/// candidate extended by one unit, with the plan completing it
+struct Expansion {
+    candidate: Candidate,
+    plan: BuildOrderPlan,
+}
+
+/// extend a candidate by building one unit in a fork, then complete the goals
+/// in a fork of that, keeping the extended candidate for the next step
+fn expand(candidate: &Candidate, unit_type: &str, search: &BuildOrderSearch) -> Option<Expansion> {
+    let mut sim = candidate.sim.fork();
+    let finished_tick = build(&mut sim, search.army, unit_type, search.max_ticks)?;
+    let mut prefix = candidate.prefix.clone();
+    prefix.push(BuildStep {
+        unit_type: unit_type.to_string(),
+        finished_tick,
+    });
+    let mut rollout = sim.fork();
+    let goals = complete_goals(&mut rollout, search)?;
+    Some(Expansion {
+        plan: BuildOrderPlan {
+            prefix: prefix.clone(),
+            goals,
+            ticks: rollout.world.resource::<CurrentTick>().0,
+        },
+        candidate: Candidate { sim, prefix },
+    })
+}
+
+/// beam search for the build order meeting all goals soonest
+///
+/// Each step extends every kept build order by each unit of the catalogue,
+/// then scores it by forking the simulation and building the goals directly.
+/// The beam_width build orders finishing soonest are kept for the next step.
+pub fn search_build_order(
+    sim: &FASimulation,
+    search: &BuildOrderSearch,
+) -> Result<BuildOrderReport, BuildOrderError> {
+    if search.goals.is_empty() {
+        return Err(BuildOrderError::NoGoals);
+    }
+    let registry = sim.world.resource::<UnitRegistry>();
+    for unit_type in search
+        .catalogue
+        .iter()
+        .chain(search.goals.iter().map(|goal| &goal.unit_type))
+    {
+        if !registry.contains(unit_type) {
+            return Err(BuildOrderError::UnknownUnit(unit_type.clone()));
+        }
+    }
+
+    let mut rollout = sim.fork();
+    let direct = BuildOrderPlan {
+        prefix: Vec::new(),
+        goals: complete_goals(&mut rollout, search).ok_or(BuildOrderError::Unreachable)?,
+        ticks: rollout.world.resource::<CurrentTick>().0,
+    };
+    let mut best = direct.clone();
+    let mut evaluations = 1;
+    let mut timed_out = 0;
+
+    let mut beam = vec![Candidate {
+        sim: sim.fork(),
+        prefix: Vec::new(),
+    }];
+    for step in 0..search.max_length {
+        let work: Vec<(usize, &str)> = (0..beam.len())
+            .flat_map(|index| {
+                search
+                    .catalogue
+                    .iter()
+                    .map(move |unit_type| (index, unit_type.as_str()))
+            })
+            .collect();
+        let next = AtomicUsize::new(0);
+        let results = Mutex::new((0..work.len()).map(|_| None).collect::<Vec<_>>());
+        thread::scope(|scope| {
+            for _ in 0..search.threads.clamp(1, work.len().max(1)) {
+                scope.spawn(|| loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let (candidate, unit_type) = match work.get(index) {
+                        Some(item) => *item,
+                        None => break,
+                    };
+                    let expansion = expand(&beam[candidate], unit_type, search);
+                    results.lock().unwrap()[index] = Some(expansion);
+                });
+            }
+        });
+
+        let results: Vec<Option<Expansion>> = results
+            .into_inner()
+            .unwrap()
+            .into_iter()
+            .map(|result| result.expect("every build order is expanded"))
+            .collect();
+        timed_out += results.iter().filter(|result| result.is_none()).count();
+        let mut expansions: Vec<Expansion> = results.into_iter().flatten().collect();
+        evaluations += expansions.len();
+        // stable sort keeps catalogue order between equally good build orders
+        expansions.sort_by_key(|expansion| expansion.plan.ticks);
+        if let Some(expansion) = expansions.first() {
+            if expansion.plan.ticks < best.ticks {
+                best = expansion.plan.clone();
+            }
+        }
+        if step + 1 == search.max_length {
+            break;
+        }
+        beam = expansions
+            .into_iter()
+            // a prefix finishing after the best plan can't improve on it
+            .filter(|expansion| {
+                expansion
+                    .plan
+                    .prefix
+                    .last()
+                    .is_some_and(|step| step.finished_tick < best.ticks)
+            })
+            .take(search.beam_width.max(1))
+            .map(|expansion| expansion.candidate)
+            .collect();
+        if beam.is_empty() {
+            break;
+        }
+    }
+
+    Ok(BuildOrderReport {
+        best,
+        direct,
+        evaluations,
+        timed_out,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::tests::*;
+
+    /// search for one T1 extractor, optionally building a T2 extractor first
+    fn search(max_ticks: u64) -> BuildOrderSearch {
+        BuildOrderSearch {
+            army: Army(0),
+            catalogue: vec!["ueb1202".to_string()],
+            goals: vec![Goal {
+                unit_type: "ueb1103".to_string(),
+                count: 1,
+            }],
+            beam_width: 1,
+            max_length: 1,
+            max_ticks,
+            threads: 1,
+        }
+    }
+
+    fn builder_simulation() -> FASimulation {
+        let mut sim = test_simulation();
+        set_stored(&mut sim, Army(0), 2000.0, 20000.0);
+        spawn_builder(&mut sim, Army(0), 10.0);
+        sim
+    }
+
+    #[test]
+    fn expansions_past_max_ticks_are_counted() {
+        // build time 60 at build rate 10 takes 60 ticks, 900 takes 900 ticks
+        let report = search_build_order(&builder_simulation(), &search(500)).unwrap();
+        assert_eq!(report.evaluations, 1);
+        assert_eq!(report.timed_out, 1);
+        assert!(report.best.prefix.is_empty());
+        assert!(report.direct.ticks <= 61);
+    }
+
+    #[test]
+    fn later_steps_extend_kept_candidates() {
+        let search = BuildOrderSearch {
+            catalogue: vec!["ueb1103".to_string()],
+            goals: vec![Goal {
+                unit_type: "ueb1103".to_string(),
+                count: 2,
+            }],
+            max_length: 2,
+            ..search(10000)
+        };
+        let report = search_build_order(&builder_simulation(), &search).unwrap();
+        // direct, then one expansion per step
+        assert_eq!(report.evaluations, 3);
+        assert_eq!(report.timed_out, 0);
+        // building the goals early is no faster than building them directly
+        assert_eq!(report.best.ticks, report.direct.ticks);
+        assert!(report.best.prefix.is_empty());
+    }
+
+    #[test]
+    fn max_ticks_is_absolute() {
+        let mut sim = builder_simulation();
+        sim.world.resource_mut::<CurrentTick>().0 = 1000;
+        assert!(matches!(
+            search_build_order(&sim, &search(1050)),
+            Err(BuildOrderError::Unreachable)
+        ));
+        let report = search_build_order(&sim, &search(1100)).unwrap();
+        assert!(report.direct.ticks > 1000);
+    }
+}
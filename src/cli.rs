@@ -0,0 +1,673 @@
+use std::error::Error;
+use std::fmt::Display;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use bevy_ecs::world::World;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+
+use crate::buildorder::*;
+use crate::optimize::*;
+use crate::ras::*;
+use crate::recorder::{RecordFormat, RecordOptions};
+use crate::replay::{replay_kind, Replay};
+use crate::scenario::Scenario;
+use crate::simulation::*;
+use crate::snapshot::SimulationKind;
+use crate::sweep::*;
+use crate::RASSimulation;
+
+/// Forged Alliance economy simulator
+#[derive(Parser, Debug)]
+#[command(name = "derp-fa-sim", version)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Build SACUs from a quantum gate, then sacrifice them into a paragon
+    RasParagon(RasParagonArgs),
+    /// Run a scenario file
+    RunScenario(RunScenarioArgs),
+    /// Compare RAS paragon times for several SACU counts against building directly
+    Compare(CompareArgs),
+    /// Run the RAS paragon experiment over a grid of SACU counts and mass incomes
+    Sweep(SweepArgs),
+    /// Search for the SACU count finishing the RAS paragon soonest
+    Optimize(OptimizeArgs),
+    /// Search for a build order meeting goals soonest, starting from a scenario
+    BuildOrder(BuildOrderArgs),
+    /// Rerun a replay of logged orders
+    Replay(ReplayArgs),
+}
+
+/// Economy and quantum gate settings shared by RAS paragon commands
+#[derive(Args, Debug)]
+pub struct RasEconomyArgs {
+    /// Energy income per second, excluding SACUs
+    #[arg(long, default_value_t = 100_000.0)]
+    pub energy_income: f64,
+    /// Mass storage capacity
+    #[arg(long, default_value_t = 40000.0)]
+    pub mass_storage: f64,
+    /// Energy storage capacity
+    #[arg(long, default_value_t = 100000.0)]
+    pub energy_storage: f64,
+    /// Build rate of the quantum gate
+    #[arg(long, default_value_t = 120000.0)]
+    pub gate_build_rate: f64,
+    /// Ticks for a finished SACU to leave the quantum gate
+    #[arg(long, default_value_t = 15)]
+    pub gate_rolloff_ticks: i32,
+    /// Give up if the paragon isn't finished after this many ticks
+    #[arg(long, default_value_t = RasParagonOptions::default().max_ticks)]
+    pub max_ticks: u64,
+}
+
+impl RasEconomyArgs {
+    pub fn options(&self, sacu_count: u32, mass_income: f64, verbose: bool) -> RasParagonOptions {
+        RasParagonOptions {
+            sacu_count,
+            mass_income,
+            energy_income: self.energy_income,
+            mass_capacity: self.mass_storage,
+            energy_capacity: self.energy_storage,
+            gate_build_rate: self.gate_build_rate,
+            gate_rolloff_time: self.gate_rolloff_ticks,
+            max_ticks: self.max_ticks,
+            verbose,
+            record: None,
+            order_log: None,
+        }
+    }
+}
+
+/// Recording of simulation state over time
+#[derive(Args, Debug)]
+pub struct RecordArgs {
+    /// Write economy state and build progress over time to this file
+    #[arg(long)]
+    pub record: Option<PathBuf>,
+    /// Format of the recording
+    #[arg(long, value_enum, default_value_t = RecordFormatArg::Csv)]
+    pub record_format: RecordFormatArg,
+    /// Record every this many ticks
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..))]
+    pub record_interval: u64,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum RecordFormatArg {
+    /// Comma-separated values, one row per economy and per entity
+    Csv,
+    /// Newline-delimited JSON, one object per tick
+    Ndjson,
+}
+
+impl RecordArgs {
+    pub fn options(&self) -> Option<RecordOptions> {
+        let path = self.record.clone()?;
+        Some(RecordOptions {
+            path,
+            format: match self.record_format {
+                RecordFormatArg::Csv => RecordFormat::Csv,
+                RecordFormatArg::Ndjson => RecordFormat::Ndjson,
+            },
+            interval: self.record_interval,
+        })
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct RasParagonArgs {
+    /// Number of SACUs to build before starting the paragon
+    #[arg(long)]
+    pub sacu_count: u32,
+    /// Mass income per second, excluding SACUs
+    #[arg(long)]
+    pub mass_income: f64,
+    #[command(flatten)]
+    pub economy: RasEconomyArgs,
+    #[command(flatten)]
+    pub record: RecordArgs,
+    /// Write a replay of the orders issued to this file
+    #[arg(long)]
+    pub log_orders: Option<PathBuf>,
+    /// Only print the results, not the state of every tick
+    #[arg(long, short)]
+    pub quiet: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct RunScenarioArgs {
+    /// Path to the scenario file
+    pub path: PathBuf,
+    #[command(flatten)]
+    pub record: RecordArgs,
+    /// Write a replay of the orders issued to this file
+    #[arg(long)]
+    pub log_orders: Option<PathBuf>,
+}
+
+#[derive(Args, Debug)]
+pub struct ReplayArgs {
+    /// Path to a replay written with --log-orders
+    pub path: PathBuf,
+    #[command(flatten)]
+    pub record: RecordArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct CompareArgs {
+    /// SACU counts to compare, separated by commas
+    #[arg(long, required = true, value_delimiter = ',')]
+    pub sacu_counts: Vec<u32>,
+    /// Mass income per second, excluding SACUs
+    #[arg(long)]
+    pub mass_income: f64,
+    #[command(flatten)]
+    pub economy: RasEconomyArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct SweepArgs {
+    /// SACU counts, separated by commas: values or inclusive ranges with an
+    /// optional step, such as 5..20 or 5..40:5
+    #[arg(
+        long,
+        required = true,
+        value_delimiter = ',',
+        allow_hyphen_values = true
+    )]
+    pub sacu_counts: Vec<SweepValues<u32>>,
+    /// Mass incomes per second, in the same format as SACU counts
+    #[arg(
+        long,
+        required = true,
+        value_delimiter = ',',
+        allow_hyphen_values = true
+    )]
+    pub mass_incomes: Vec<SweepValues<f64>>,
+    #[command(flatten)]
+    pub economy: RasEconomyArgs,
+    /// Number of simulations to run in parallel [default: available cores]
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub threads: Option<u32>,
+}
+
+#[derive(Args, Debug)]
+pub struct OptimizeArgs {
+    /// Mass income per second, excluding SACUs
+    #[arg(long)]
+    pub mass_income: f64,
+    #[command(flatten)]
+    pub economy: RasEconomyArgs,
+    /// Fewest SACUs to consider
+    #[arg(long, default_value_t = 1)]
+    pub min_sacus: u32,
+    /// Most SACUs to consider
+    #[arg(long, default_value_t = 60)]
+    pub max_sacus: u32,
+    /// SACU counts either side of the optimum to show
+    #[arg(long, default_value_t = 5)]
+    pub radius: u32,
+    /// Number of simulations to run in parallel [default: available cores]
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub threads: Option<u32>,
+}
+
+#[derive(Args, Debug)]
+pub struct BuildOrderArgs {
+    /// Scenario file setting up the starting economy and units, its orders are
+    /// ignored
+    pub path: PathBuf,
+    /// Units to have finished, as a unit id with an optional count such as
+    /// ueb1303:4, built in the order given
+    #[arg(long = "goal", required = true)]
+    pub goals: Vec<Goal>,
+    /// Unit ids which may be built before the goals, separated by commas
+    #[arg(long, required = true, value_delimiter = ',')]
+    pub catalogue: Vec<String>,
+    /// Army following the build order
+    #[arg(long, default_value_t = 0)]
+    pub army: u32,
+    /// Build orders kept after each step
+    #[arg(long, default_value_t = 4)]
+    pub beam_width: usize,
+    /// Most units to build before the goals
+    #[arg(long, default_value_t = 6)]
+    pub max_length: usize,
+    /// Number of simulations to run in parallel [default: available cores]
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub threads: Option<u32>,
+}
+
+/// Value or inclusive range of values from the command line
+#[derive(Clone, Debug)]
+pub struct SweepValues<T>(pub Vec<T>);
+
+/// most values a single range may expand to
+const MAX_SWEEP_VALUES: usize = 10000;
+
+/// Number which can be swept over a range
+pub trait SweepValue: FromStr + Copy + PartialOrd {
+    const ZERO: Self;
+    const ONE: Self;
+    /// value a number of steps after start
+    fn nth(start: Self, step: Self, index: usize) -> Option<Self>;
+    /// value itself, or end if value only overshoots end by rounding error
+    fn snap_to_end(self, end: Self, step: Self) -> Self;
+}
+
+impl SweepValue for u32 {
+    const ZERO: Self = 0;
+    const ONE: Self = 1;
+
+    fn nth(start: Self, step: Self, index: usize) -> Option<Self> {
+        let index = u32::try_from(index).ok()?;
+        step.checked_mul(index)?.checked_add(start)
+    }
+
+    fn snap_to_end(self, _end: Self, _step: Self) -> Self {
+        self
+    }
+}
+
+impl SweepValue for f64 {
+    const ZERO: Self = 0.0;
+    const ONE: Self = 1.0;
+
+    fn nth(start: Self, step: Self, index: usize) -> Option<Self> {
+        // multiply rather than accumulate so rounding errors don't add up
+        Some(start + step * index as f64)
+    }
+
+    fn snap_to_end(self, end: Self, step: Self) -> Self {
+        if (self - end).abs() <= step * 1e-9 {
+            end
+        } else {
+            self
+        }
+    }
+}
+
+impl<T> FromStr for SweepValues<T>
+where
+    T: SweepValue,
+    T::Err: Display,
+{
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let parse = |value: &str| {
+            value
+                .trim()
+                .parse::<T>()
+                .map_err(|err| format!("invalid value {:?}: {}", value, err))
+        };
+        let (range, step) = match value.split_once(':') {
+            Some((range, step)) => (range, parse(step)?),
+            None => (value, T::ONE),
+        };
+        let (start, end) = match range.split_once("..") {
+            Some((start, end)) => (parse(start)?, parse(end)?),
+            None => return Ok(SweepValues(vec![parse(range)?])),
+        };
+        if step.partial_cmp(&T::ZERO) != Some(std::cmp::Ordering::Greater) {
+            return Err(format!("step of {:?} must be positive", value));
+        }
+        let mut values = Vec::new();
+        while let Some(current) = T::nth(start, step, values.len()) {
+            let current = current.snap_to_end(end, step);
+            if current > end {
+                break;
+            }
+            if values.len() >= MAX_SWEEP_VALUES {
+                return Err(format!(
+                    "{:?} has more than {} values",
+                    value, MAX_SWEEP_VALUES
+                ));
+            }
+            values.push(current);
+        }
+        Ok(SweepValues(values))
+    }
+}
+
+/// run the command selected on the command line
+pub fn run(cli: Cli) -> Result<(), Box<dyn Error>> {
+    match cli.command {
+        Command::RasParagon(args) => ras_paragon(&args),
+        Command::RunScenario(args) => run_scenario(&args),
+        Command::Compare(args) => compare(&args),
+        Command::Sweep(args) => sweep(&args),
+        Command::Optimize(args) => optimize(&args),
+        Command::BuildOrder(args) => build_order(&args),
+        Command::Replay(args) => replay(&args),
+    }
+}
+
+fn ras_paragon(args: &RasParagonArgs) -> Result<(), Box<dyn Error>> {
+    let mut options = args
+        .economy
+        .options(args.sacu_count, args.mass_income, !args.quiet);
+    options.record = args.record.options();
+    options.order_log = args.log_orders.clone();
+    let report = run_ras_paragon(&options)?;
+    if args.quiet {
+        println!("total mass: {:.2}", report.sacu_mass_produced);
+        println!("total energy: {:.2}", report.sacu_energy_produced);
+    }
+    println!("Total time: {} minutes", report.total_minutes());
+    println!(
+        "Time to build paragon directly: {} minutes",
+        report.direct_build_minutes
+    );
+    Ok(())
+}
+
+fn run_scenario(args: &RunScenarioArgs) -> Result<(), Box<dyn Error>> {
+    let scenario = Scenario::load(&args.path)?;
+    let report = scenario.run(args.record.options().as_ref(), args.log_orders.as_deref())?;
+    if report.finished {
+        println!("Scenario finished at tick {}", report.ticks);
+    } else {
+        println!("Scenario stopped at tick limit {}", report.ticks);
+    }
+    println!(
+        "Total time: {} minutes",
+        report.ticks as f64 / TICK_RATE / 60.
+    );
+    println!(
+        "Executed {} of {} orders",
+        report.executed_orders.len(),
+        scenario.orders.len()
+    );
+    Ok(())
+}
+
+fn compare(args: &CompareArgs) -> Result<(), Box<dyn Error>> {
+    println!(
+        "{:>6} {:>12} {:>14} {:>16} {:>12}",
+        "SACUs", "RAS (min)", "direct (min)", "SACU mass", "difference"
+    );
+    for sacu_count in &args.sacu_counts {
+        let report = run_ras_paragon(&args.economy.options(*sacu_count, args.mass_income, false))?;
+        println!(
+            "{:>6} {:>12.2} {:>14.2} {:>16.2} {:>+12.2}",
+            sacu_count,
+            report.total_minutes(),
+            report.direct_build_minutes,
+            report.sacu_mass_produced,
+            report.total_minutes() - report.direct_build_minutes
+        );
+    }
+    Ok(())
+}
+
+fn sweep(args: &SweepArgs) -> Result<(), Box<dyn Error>> {
+    let sacu_counts: Vec<u32> = args
+        .sacu_counts
+        .iter()
+        .flat_map(|values| values.0.clone())
+        .collect();
+    let mass_incomes: Vec<f64> = args
+        .mass_incomes
+        .iter()
+        .flat_map(|values| values.0.clone())
+        .collect();
+    let threads = thread_count(args.threads);
+    let points = sweep_ras_paragon(
+        &args.economy.options(1, 1.0, false),
+        &sacu_counts,
+        &mass_incomes,
+        threads,
+    )?;
+
+    println!(
+        "{:>10} {:>6} {:>8} {:>10} {:>12} {:>12} {:>14} {:>10}",
+        "mass/s",
+        "SACUs",
+        "ticks",
+        "RAS (min)",
+        "direct (min)",
+        "SACU mass",
+        "SACU energy",
+        "saved"
+    );
+    for point in &points {
+        // best SACU count for this mass income
+        let best = points
+            .iter()
+            .filter(|other| other.mass_income == point.mass_income)
+            .all(|other| other.report.total_ticks >= point.report.total_ticks);
+        println!(
+            "{:>10.2} {:>6} {:>8} {:>10.2} {:>12.2} {:>12.2} {:>14.2} {:>+10.2}{}",
+            point.mass_income,
+            point.sacu_count,
+            point.report.total_ticks,
+            point.report.total_minutes(),
+            point.report.direct_build_minutes,
+            point.report.sacu_mass_produced,
+            point.report.sacu_energy_produced,
+            point.minutes_saved(),
+            if best { " *" } else { "" }
+        );
+    }
+    Ok(())
+}
+
+fn optimize(args: &OptimizeArgs) -> Result<(), Box<dyn Error>> {
+    let optimum = optimize_sacu_count(
+        &args.economy.options(1, args.mass_income, false),
+        &SacuSearch {
+            min_sacu_count: args.min_sacus,
+            max_sacu_count: args.max_sacus,
+            radius: args.radius,
+            threads: thread_count(args.threads),
+        },
+    )?;
+    let best = &optimum.best;
+    println!(
+        "Best SACU count: {} ({} minutes, {} simulations run)",
+        best.sacu_count,
+        best.report.total_minutes(),
+        optimum.evaluations
+    );
+    println!(
+        "Time to build paragon directly: {} minutes",
+        best.report.direct_build_minutes
+    );
+    println!();
+    println!(
+        "{:>6} {:>8} {:>10} {:>12} {:>10}",
+        "SACUs", "ticks", "RAS (min)", "vs best", "saved"
+    );
+    for point in &optimum.curve {
+        println!(
+            "{:>6} {:>8} {:>10.2} {:>+12.2} {:>+10.2}{}",
+            point.sacu_count,
+            point.report.total_ticks,
+            point.report.total_minutes(),
+            point.report.total_minutes() - best.report.total_minutes(),
+            point.minutes_saved(),
+            if point.sacu_count == best.sacu_count {
+                " *"
+            } else {
+                ""
+            }
+        );
+    }
+    Ok(())
+}
+
+fn build_order(args: &BuildOrderArgs) -> Result<(), Box<dyn Error>> {
+    let scenario = Scenario::load(&args.path)?;
+    let mut sim = FASimulation::new();
+    sim.world.insert_resource(LogHandler::new(|_| {}));
+    scenario.populate(&mut sim.world)?;
+    let report = search_build_order(
+        &sim,
+        &BuildOrderSearch {
+            army: Army(args.army),
+            catalogue: args.catalogue.clone(),
+            goals: args.goals.clone(),
+            beam_width: args.beam_width,
+            max_length: args.max_length,
+            max_ticks: scenario.max_ticks,
+            threads: thread_count(args.threads),
+        },
+    )?;
+    let minutes = |ticks: u64| ticks as f64 / TICK_RATE / 60.;
+    println!(
+        "Best build order meets goals at tick {} ({} minutes, {} build orders simulated)",
+        report.best.ticks,
+        minutes(report.best.ticks),
+        report.evaluations
+    );
+    println!(
+        "Building goals directly: tick {} ({} minutes)",
+        report.direct.ticks,
+        minutes(report.direct.ticks)
+    );
+    if report.timed_out > 0 {
+        println!(
+            "{} build orders didn't meet goals by tick {}",
+            report.timed_out, scenario.max_ticks
+        );
+    }
+    println!();
+    println!("{:>8} {:>10}  unit", "tick", "minutes");
+    for (step, is_goal) in report
+        .best
+        .prefix
+        .iter()
+        .map(|step| (step, false))
+        .chain(report.best.goals.iter().map(|step| (step, true)))
+    {
+        println!(
+            "{:>8} {:>10.2}  {}{}",
+            step.finished_tick,
+            minutes(step.finished_tick),
+            step.unit_type,
+            if is_goal { " (goal)" } else { "" }
+        );
+    }
+    Ok(())
+}
+
+fn replay(args: &ReplayArgs) -> Result<(), Box<dyn Error>> {
+    let source = std::fs::read_to_string(&args.path)?;
+    let record = args.record.options();
+    match replay_kind(&source)? {
+        SimulationKind::Fa => {
+            let replay = Replay::<FASimulation>::parse(&source)?;
+            print_replay(replay.orders.len(), replay.run(record.as_ref())?.world());
+        }
+        SimulationKind::Ras => {
+            let replay = Replay::<RASSimulation>::parse(&source)?;
+            print_replay(replay.orders.len(), replay.run(record.as_ref())?.world());
+        }
+    }
+    Ok(())
+}
+
+fn print_replay(orders: usize, world: &World) {
+    let tick = world.resource::<CurrentTick>().0;
+    println!("Replayed {} orders to tick {}", orders, tick);
+    println!("Total time: {} minutes", tick as f64 / TICK_RATE / 60.);
+    for (army, economy) in world.resource::<Economies>().iter() {
+        println!(
+            "Army {}: mass {:.2}, energy {:.2}",
+            army.0, economy.mass, economy.energy
+        );
+    }
+}
+
+/// threads requested, or the number of cores available
+fn thread_count(threads: Option<u32>) -> usize {
+    match threads {
+        Some(threads) => threads as usize,
+        None => std::thread::available_parallelism().map_or(1, |threads| threads.get()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values<T: SweepValue>(value: &str) -> Vec<T>
+    where
+        T::Err: Display,
+    {
+        value.parse::<SweepValues<T>>().unwrap().0
+    }
+
+    #[test]
+    fn single_values_and_ranges() {
+        assert_eq!(values::<u32>("7"), [7]);
+        assert_eq!(values::<u32>("5..8"), [5, 6, 7, 8]);
+        assert_eq!(values::<u32>("5..20:5"), [5, 10, 15, 20]);
+        assert_eq!(values::<u32>("5..19:5"), [5, 10, 15]);
+        assert_eq!(values::<u32>("8..5"), Vec::<u32>::new());
+        // stops instead of overflowing
+        assert_eq!(
+            values::<u32>("4294967290..4294967295:4"),
+            [4294967290, 4294967294]
+        );
+    }
+
+    #[test]
+    fn float_ranges_include_end_despite_rounding() {
+        assert_eq!(values::<f64>("0.1..0.3:0.1"), [0.1, 0.2, 0.3]);
+        let incomes = values::<f64>("0..1:0.1");
+        assert_eq!(incomes.len(), 11);
+        assert_eq!(incomes[3], 0.1 * 3.0);
+        assert_eq!(incomes[10], 1.0);
+        assert_eq!(values::<f64>("-1.5..0.5:1"), [-1.5, -0.5, 0.5]);
+    }
+
+    #[test]
+    fn invalid_values() {
+        let error = |value: &str| value.parse::<SweepValues<u32>>().unwrap_err();
+        assert!(error("a").contains("invalid value"));
+        assert!(error("1..b").contains("invalid value"));
+        assert!(error("1..5:0").contains("must be positive"));
+        assert!(error("-1").contains("invalid value"));
+        assert!(error("0..20000").contains("more than"));
+        assert!("1..2:-1"
+            .parse::<SweepValues<f64>>()
+            .unwrap_err()
+            .contains("must be positive"));
+    }
+
+    #[test]
+    fn sweep_arguments_accept_negative_values() {
+        let cli = Cli::try_parse_from([
+            "derp-fa-sim",
+            "sweep",
+            "--sacu-counts",
+            "1,5..10:5",
+            "--mass-incomes",
+            "-10..10:10,-2.5",
+        ])
+        .unwrap();
+        let Command::Sweep(args) = cli.command else {
+            panic!("expected sweep command");
+        };
+        let incomes: Vec<f64> = args
+            .mass_incomes
+            .iter()
+            .flat_map(|values| values.0.clone())
+            .collect();
+        assert_eq!(incomes, [-10.0, 0.0, 10.0, -2.5]);
+        let counts: Vec<u32> = args
+            .sacu_counts
+            .iter()
+            .flat_map(|values| values.0.clone())
+            .collect();
+        assert_eq!(counts, [1, 5, 10]);
+    }
+}
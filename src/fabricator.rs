@@ -0,0 +1,190 @@
+use bevy_ecs::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::adjacency::AdjacencyBonus;
+use crate::simulation::*;
+
+/// Entity converts energy into mass
+#[derive(Component, Clone, Debug, Serialize, Deserialize)]
+pub struct MassFabricator {
+    /// energy consumed per tick when fully supplied
+    pub energy_per_tick: f64,
+    /// mass produced per tick when fully supplied
+    pub mass_per_tick: f64,
+    /// energy requested this tick
+    pub energy_requested: f64,
+    /// total mass produced
+    pub total_mass: f64,
+    /// pause automatically when stored energy is low
+    pub auto_pause: bool,
+    /// whether the fabricator is currently paused due to low energy
+    pub paused: bool,
+    /// pause when stored energy falls below this fraction of capacity
+    pub pause_below: f64,
+    /// resume when stored energy rises above this fraction of capacity
+    pub resume_above: f64,
+}
+
+impl MassFabricator {
+    pub fn new(energy_per_tick: f64, mass_per_tick: f64) -> Self {
+        MassFabricator {
+            energy_per_tick,
+            mass_per_tick,
+            energy_requested: 0.0,
+            total_mass: 0.0,
+            auto_pause: true,
+            paused: false,
+            pause_below: 0.1,
+            resume_above: 0.5,
+        }
+    }
+}
+
+/// auto-pause and request energy for mass fabricators
+pub fn mass_fabricator_request(
+    mut query: Query<
+        (
+            &Army,
+            &mut MassFabricator,
+            &mut ResourceConsumer,
+            Option<&AdjacencyBonus>,
+        ),
+        With<Executing>,
+    >,
+    economies: Res<Economies>,
+) {
+    for (army, mut fabricator, mut resource_consumer, adjacency_bonus) in &mut query {
+        let energy_fraction = economies
+            .get(*army)
+            .map_or(0.0, |economy| economy.energy / economy.energy_capacity);
+        if fabricator.auto_pause {
+            if fabricator.paused && energy_fraction >= fabricator.resume_above {
+                fabricator.paused = false;
+            } else if !fabricator.paused && energy_fraction < fabricator.pause_below {
+                fabricator.paused = true;
+            }
+        } else {
+            fabricator.paused = false;
+        }
+
+        if fabricator.paused {
+            fabricator.energy_requested = 0.0;
+            continue;
+        }
+        let energy_consumption_multiplier =
+            adjacency_bonus.map_or(1.0, |bonus| bonus.energy_consumption_multiplier);
+        fabricator.energy_requested = fabricator.energy_per_tick * energy_consumption_multiplier;
+        resource_consumer.energy_request += fabricator.energy_requested;
+    }
+}
+
+/// consume energy and produce mass in proportion to energy received
+pub fn mass_fabricator_convert(
+    mut query: Query<(&Army, &mut MassFabricator, &mut ResourceConsumer), With<Executing>>,
+    mut economies: ResMut<Economies>,
+) {
+    for (army, mut fabricator, mut resource_consumer) in &mut query {
+        if fabricator.energy_requested <= 0.0 {
+            continue;
+        }
+        let economy = economies.get_or_default(*army);
+        // output scales with the energy actually received
+        let energy_stall = economy.energy_stall_for(resource_consumer.priority);
+        let energy_used = economy.pull_energy(fabricator.energy_requested * energy_stall);
+        let mass_produced = fabricator.mass_per_tick * energy_used / fabricator.energy_requested;
+        resource_consumer.energy_consumed += energy_used;
+        fabricator.total_mass += mass_produced;
+        fabricator.energy_requested = 0.0;
+        economy.mass += mass_produced;
+        economy.mass_produced += mass_produced;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::tests::*;
+
+    /// spawn an executing fabricator converting 100 energy into 1 mass per tick
+    fn spawn_fabricator(sim: &mut FASimulation, auto_pause: bool) -> Entity {
+        sim.world
+            .spawn()
+            .insert(Army(0))
+            .insert(Executing)
+            .insert(MassFabricator {
+                auto_pause,
+                ..MassFabricator::new(100.0, 1.0)
+            })
+            .insert(ResourceConsumer {
+                priority: ConsumerPriority::Low,
+                ..Default::default()
+            })
+            .id()
+    }
+
+    #[test]
+    fn auto_pause_hysteresis() {
+        let mut sim = test_simulation();
+        sim.add_army(
+            Army(0),
+            Economy {
+                energy_capacity: 1000.0,
+                ..Default::default()
+            },
+        );
+        let fabricator = spawn_fabricator(&mut sim, true);
+        let mut stage = SystemStage::single_threaded().with_system(mass_fabricator_request);
+        // paused below 10% of capacity, resumed at 50%
+        let mut paused_at = |sim: &mut FASimulation, energy: f64| {
+            set_stored(sim, Army(0), 0.0, energy);
+            stage.run(&mut sim.world);
+            let fabricator = sim.world.get::<MassFabricator>(fabricator).unwrap();
+            assert_eq!(fabricator.energy_requested == 0.0, fabricator.paused);
+            fabricator.paused
+        };
+        assert!(!paused_at(&mut sim, 150.0));
+        assert!(!paused_at(&mut sim, 100.0));
+        assert!(paused_at(&mut sim, 99.0));
+        assert!(paused_at(&mut sim, 300.0));
+        assert!(paused_at(&mut sim, 499.0));
+        assert!(!paused_at(&mut sim, 500.0));
+        assert!(!paused_at(&mut sim, 300.0));
+    }
+
+    #[test]
+    fn auto_pause_can_be_disabled() {
+        let mut sim = test_simulation();
+        let fabricator = spawn_fabricator(&mut sim, false);
+        set_stored(&mut sim, Army(0), 0.0, 0.0);
+        sim.run();
+        let fabricator = sim.world.get::<MassFabricator>(fabricator).unwrap();
+        assert!(!fabricator.paused);
+    }
+
+    #[test]
+    fn output_scales_with_energy_pulled() {
+        let mut sim = test_simulation();
+        let fabricator = spawn_fabricator(&mut sim, false);
+        // a builder at high priority is served first, leaving the fabricator half
+        // of the energy it requests
+        let builder = spawn_builder(&mut sim, Army(0), 10.0);
+        sim.world
+            .get_mut::<ResourceConsumer>(builder)
+            .unwrap()
+            .priority = ConsumerPriority::High;
+        let target = spawn_target(&mut sim, Army(0));
+        construct(&mut sim, builder, target);
+        // builder wants 1 / 900 * 5400 = 6 energy per tick
+        set_stored(&mut sim, Army(0), 1000.0, 56.0);
+        sim.run();
+
+        let fabricator = sim.world.get::<MassFabricator>(fabricator).unwrap();
+        assert!((fabricator.total_mass - 0.5).abs() < 1e-9);
+        let consumer = sim.world.get::<ResourceConsumer>(builder).unwrap();
+        assert!((consumer.total_energy_consumed - 6.0).abs() < 1e-9);
+        let economy = sim.economy(Army(0)).unwrap();
+        assert!(economy.energy.abs() < 1e-9);
+        assert!((economy.mass_produced - 0.5).abs() < 1e-9);
+        assert_eq!(economy.energy_stall_for(ConsumerPriority::Low), 0.5);
+    }
+}
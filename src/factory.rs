@@ -0,0 +1,253 @@
+use std::collections::VecDeque;
+
+use bevy_ecs::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::registry::UnitRegistry;
+use crate::simulation::*;
+
+/// Entry in a factory build queue
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BuildOrder {
+    /// blueprint id of unit to build
+    pub unit_id: String,
+    /// number of units to build per pass through the queue
+    pub count: u32,
+    /// units left to build in the current pass
+    pub remaining: u32,
+    /// put back at the end of the queue once all units are built
+    pub repeat: bool,
+}
+
+impl BuildOrder {
+    /// build count units then remove from queue
+    pub fn once(unit_id: impl Into<String>, count: u32) -> Self {
+        BuildOrder {
+            unit_id: unit_id.into(),
+            count,
+            remaining: count,
+            repeat: false,
+        }
+    }
+
+    /// build count units then move to back of queue
+    pub fn repeat(unit_id: impl Into<String>, count: u32) -> Self {
+        BuildOrder {
+            repeat: true,
+            ..BuildOrder::once(unit_id, count)
+        }
+    }
+}
+
+/// Entity builds units from a queue (land/air/naval factories, quantum gates)
+#[derive(Component, Clone, Debug, Serialize, Deserialize)]
+pub struct Factory {
+    /// units to build, front is built first
+    pub queue: VecDeque<BuildOrder>,
+    /// time (in ticks) for unit being constructed to exit the factory
+    pub rolloff_time: i32,
+    /// time (in ticks) left for unit to leave, negative if not rolling off
+    pub rolloff_current: i32,
+    /// unit currently being built or rolling off
+    #[serde(default, with = "crate::snapshot::option_entity_bits")]
+    pub current: Option<Entity>,
+}
+
+impl Factory {
+    pub fn new(rolloff_time: i32) -> Self {
+        Factory {
+            queue: VecDeque::new(),
+            rolloff_time,
+            rolloff_current: -1,
+            current: None,
+        }
+    }
+
+    /// add order to back of queue
+    pub fn with_order(mut self, order: BuildOrder) -> Self {
+        self.queue.push_back(order);
+        self
+    }
+
+    /// mark one unit of the front order as built
+    fn advance_queue(&mut self) {
+        if let Some(order) = self.queue.front_mut() {
+            order.remaining = order.remaining.saturating_sub(1);
+            if order.remaining == 0 {
+                let mut order = self.queue.pop_front().unwrap();
+                if order.repeat && order.count > 0 {
+                    order.remaining = order.count;
+                    self.queue.push_back(order);
+                }
+            }
+        }
+    }
+}
+
+/// Sent when a finished unit leaves a factory
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct UnitRolledOff {
+    #[serde(with = "crate::snapshot::entity_bits")]
+    pub factory: Entity,
+    #[serde(with = "crate::snapshot::entity_bits")]
+    pub unit: Entity,
+}
+
+/// start construction of queued units and handle rolloff of finished units
+#[allow(clippy::type_complexity)]
+pub fn factory_production(
+    mut query: Query<
+        (Entity, &Army, &mut Factory),
+        (
+            With<Executing>,
+            Without<ConstructionPaused>,
+            Without<Constructing>,
+        ),
+    >,
+    damage_query: Query<&Damage>,
+    registry: Res<UnitRegistry>,
+    current_tick: Res<CurrentTick>,
+    log_handler: Res<LogHandler>,
+    mut rolled_off: EventWriter<UnitRolledOff>,
+    mut commands: Commands,
+) {
+    for (entity, army, mut factory) in &mut query {
+        if let Some(unit) = factory.current {
+            // construction finished or cancelled
+            if factory.rolloff_current < 0 {
+                factory.rolloff_current = factory.rolloff_time;
+                continue;
+            } else if factory.rolloff_current > 0 {
+                // tick rolloff
+                factory.rolloff_current -= 1;
+                continue;
+            }
+            factory.rolloff_current = -1;
+            factory.current = None;
+            let finished = damage_query
+                .get(unit)
+                .is_ok_and(|damage| damage.is_finished());
+            if finished {
+                rolled_off.send(UnitRolledOff {
+                    factory: entity,
+                    unit,
+                });
+                factory.advance_queue();
+            }
+        }
+
+        let unit_id = match factory.queue.front() {
+            Some(order) => order.unit_id.clone(),
+            None => continue,
+        };
+        // spawn new unit and begin construction
+        match registry.spawn(&mut commands, &unit_id, *army) {
+            Some(construct_target) => {
+                factory.current = Some(construct_target);
+                commands
+                    .entity(entity)
+                    .insert(Constructing::new(construct_target));
+            }
+            None => {
+                (log_handler.emit)(format!(
+                    "tick {}: warn: factory cannot build unknown unit {}",
+                    current_tick.0, unit_id
+                ));
+                factory.queue.pop_front();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry::UnitId;
+    use crate::simulation::tests::*;
+
+    #[test]
+    fn queue_repeats_and_removes_orders() {
+        let mut factory = Factory::new(0)
+            .with_order(BuildOrder::once("a", 2))
+            .with_order(BuildOrder::repeat("b", 1));
+        let mut built = Vec::new();
+        for _ in 0..5 {
+            built.push(factory.queue.front().unwrap().unit_id.clone());
+            factory.advance_queue();
+        }
+        assert_eq!(built, ["a", "a", "b", "b", "b"]);
+        assert_eq!(factory.queue.len(), 1);
+    }
+
+    /// run a factory for ticks, returning the tick and unit id of every unit
+    /// rolling off
+    fn rolled_off(factory: Factory, ticks: u64) -> Vec<(u64, String)> {
+        let mut sim = test_simulation();
+        sim.add_army(
+            Army(0),
+            Economy {
+                mass: 1e9,
+                energy: 1e9,
+                mass_capacity: 1e9,
+                energy_capacity: 1e9,
+                ..Default::default()
+            },
+        );
+        // builds a ueb1104 in a single tick
+        let entity = spawn_builder(&mut sim, Army(0), 10000.0);
+        sim.world.entity_mut(entity).insert(factory);
+        let mut reader = sim.world.resource::<Events<UnitRolledOff>>().get_reader();
+        let mut units = Vec::new();
+        for _ in 0..ticks {
+            sim.run();
+            let tick = sim.world.resource::<CurrentTick>().0;
+            let events = sim.world.resource::<Events<UnitRolledOff>>();
+            for event in reader.iter(events) {
+                assert_eq!(event.factory, entity);
+                let unit = sim.world.entity(event.unit);
+                assert!(unit.get::<Damage>().unwrap().is_finished());
+                units.push((tick, unit.get::<UnitId>().unwrap().0.clone()));
+            }
+        }
+        units
+    }
+
+    #[test]
+    fn factory_builds_queue_in_order() {
+        let units = rolled_off(
+            Factory::new(0)
+                .with_order(BuildOrder::once("ueb1104", 2))
+                .with_order(BuildOrder::repeat("ueb1303", 1))
+                .with_order(BuildOrder::once("ueb1104", 1)),
+            300,
+        );
+        let ids: Vec<&str> = units.iter().map(|(_, id)| id.as_str()).collect();
+        assert_eq!(
+            ids[..5],
+            ["ueb1104", "ueb1104", "ueb1303", "ueb1104", "ueb1303"]
+        );
+        assert!(ids[5..].iter().all(|id| *id == "ueb1303"));
+    }
+
+    #[test]
+    fn rolloff_delays_each_unit() {
+        let order = || BuildOrder::repeat("ueb1104", 1);
+        let immediate = rolled_off(Factory::new(0).with_order(order()), 40);
+        let delayed = rolled_off(Factory::new(5).with_order(order()), 40);
+        let gap = |units: &[(u64, String)]| units[1].0 - units[0].0;
+        assert_eq!(gap(&delayed), gap(&immediate) + 5);
+        assert_eq!(delayed[0].0, immediate[0].0 + 5);
+    }
+
+    #[test]
+    fn unknown_units_are_skipped() {
+        let units = rolled_off(
+            Factory::new(0)
+                .with_order(BuildOrder::once("unknown", 1))
+                .with_order(BuildOrder::once("ueb1104", 1)),
+            20,
+        );
+        assert_eq!(units.len(), 1);
+        assert_eq!(units[0].1, "ueb1104");
+    }
+}
@@ -1,131 +1,93 @@
+pub mod adjacency;
+pub mod alliance;
+pub mod blueprint;
+pub mod buildorder;
+pub mod cli;
+pub mod fabricator;
+pub mod factory;
+pub mod optimize;
+pub mod ras;
+pub mod reclaim;
+pub mod recorder;
+pub mod registry;
+pub mod repair;
+pub mod replay;
+pub mod scenario;
 pub mod simulation;
+pub mod snapshot;
+pub mod sweep;
+pub mod trigger;
+pub mod upgrade;
 
+use alliance::*;
 use bevy_ecs::prelude::*;
+use blueprint::*;
+use clap::Parser;
+use cli::Cli;
+use factory::*;
+use registry::*;
+use replay::{issue, Order};
+use serde::{Deserialize, Serialize};
 use simulation::*;
+use snapshot::*;
+use trigger::*;
 
-/// total resource cost to build paragon
-const PARAGON_DAMAGE: Damage = Damage {
-    mass_total: 250_200.0,
-    energy_total: 7_506_000.0,
-    build_time: 325_000.0,
-    health: 0.0,
-    health_points: 5_000,
-};
-/// total resource cost to build sacrifice-enabled RAS SACU
-const RAS_SACU_DAMAGE: Damage = Damage {
-    // mass_total: 6_600.0,
-    // energy_total: 119_600.0,
-    mass_total: 6_450.0,
-    energy_total: 117_100.0,
-    // build_time: 23_500.0,
-    build_time: 22_800.0,
-    health: 0.0,
-    health_points: 15_000,
-};
-/// RAS SACU resource production
-const RAS_SACU_RESOURCE_PRODUCTION: ResourceProducer = ResourceProducer {
-    mass_yield: 11.0 / TICK_RATE,
-    energy_yield: 1_020.0 / TICK_RATE,
-    total_mass: 0.0,
-    total_energy: 0.0,
-};
+/// blueprint for sacrifice-enabled RAS SACU
+const RAS_SACU_BLUEPRINT: &str = include_str!("../blueprints/uel0301_RAS_unit.bp");
+/// blueprint for paragon
+const PARAGON_BLUEPRINT: &str = include_str!("../blueprints/xsb2401_unit.bp");
 /// RAS SACU sacrifice
 const RAS_SACU_SACRIFICE: SacrificeCapable = SacrificeCapable {
     mass_efficiency: 0.9,
     energy_efficiency: 0.9,
 };
-/// RAS SACU engineering power
-const RAS_SACU_ENGINEERING: Engineering = Engineering {
-    build_rate: 56.0 / TICK_RATE,
-};
 
-#[derive(Component)]
-pub struct QuantumGate {
-    /// time (in ticks) for unit being constructed to exit the factory
-    pub rolloff_time: i32,
-    /// time (in ticks) left for unit to leave
-    pub rolloff_current: i32,
-    // bundle for new unit
-    // this unfortunately does not work
-    // unit_bundle: Box<dyn Bundle>
-}
-
-impl Default for QuantumGate {
-    fn default() -> Self {
-        QuantumGate {
-            rolloff_time: 15,
-            rolloff_current: 0,
-        }
-    }
+/// blueprint id of sacrifice-enabled RAS SACU
+pub const RAS_SACU_ID: &str = "uel0301_RAS";
+/// blueprint id of paragon
+pub const PARAGON_ID: &str = "xsb2401";
+
+/// Unit registry containing the units used by the RAS simulation
+pub fn ras_unit_registry() -> UnitRegistry {
+    let mut registry = UnitRegistry::new();
+    registry.register_with(
+        Blueprint::parse(RAS_SACU_ID, RAS_SACU_BLUEPRINT)
+            .expect("built-in RAS SACU blueprint is invalid"),
+        |entity_commands| {
+            entity_commands
+                .insert(RASSupportCommander)
+                .insert(RAS_SACU_SACRIFICE);
+        },
+    );
+    registry.register_with(
+        Blueprint::parse(PARAGON_ID, PARAGON_BLUEPRINT)
+            .expect("built-in paragon blueprint is invalid"),
+        |entity_commands| {
+            entity_commands.insert(Paragon);
+        },
+    );
+    registry
 }
 
-#[derive(Component)]
+#[derive(Component, Clone, Debug, Default)]
 pub struct RASSupportCommander;
 
-#[derive(Component)]
+#[derive(Component, Clone, Debug, Default)]
 pub struct Paragon;
 
-#[derive(Component)]
+#[derive(Component, Clone, Debug, Serialize, Deserialize)]
 pub struct SacrificeCapable {
     pub mass_efficiency: f64,
     pub energy_efficiency: f64,
 }
 
-#[derive(Component)]
+#[derive(Component, Clone, Debug, Serialize, Deserialize)]
 pub struct Sacrificing {
+    #[serde(with = "crate::snapshot::entity_bits")]
     pub target: Entity,
 }
 
-pub fn quantum_gate_spawn_construct(
-    mut query: Query<
-        (Entity, &mut QuantumGate),
-        (
-            With<Executing>,
-            Without<ConstructionPaused>,
-            Without<Constructing>,
-        ),
-    >,
-    mut commands: Commands,
-) {
-    for (entity, mut quantum_gate) in &mut query {
-        if quantum_gate.rolloff_current > 0 {
-            // tick rolloff
-            quantum_gate.rolloff_current -= 1;
-            continue;
-        } else if quantum_gate.rolloff_current == 0 {
-            quantum_gate.rolloff_current = -1;
-            // spawn new RAS SACU and begin construction
-            let construct_target = commands
-                .spawn()
-                .insert(RASSupportCommander)
-                .insert(RAS_SACU_DAMAGE)
-                .insert(RAS_SACU_ENGINEERING)
-                .insert(WillExecuteOnConstruct)
-                .insert(RAS_SACU_RESOURCE_PRODUCTION)
-                .insert(ResourceConsumer {
-                    mass_request: 0.0,
-                    mass_consumed: 0.0,
-                    energy_request: 0.0,
-                    energy_consumed: 0.0,
-                })
-                .insert(RAS_SACU_SACRIFICE)
-                .id();
-
-            commands.entity(entity).insert(Constructing {
-                target: construct_target,
-                build_amount: 0.0,
-                mass_requested: 0.0,
-                energy_requested: 0.0,
-                mass_consumption_multiplier: 1.0,
-                energy_consumption_multiplier: 1.0,
-            });
-        } else {
-            // construction finished or cancelled
-            quantum_gate.rolloff_current = quantum_gate.rolloff_time;
-        }
-    }
-}
-
+#[allow(clippy::type_complexity)]
 pub fn construct_sacrifice(
     mut param_set: ParamSet<(
         Query<
@@ -148,10 +110,10 @@ pub fn construct_sacrifice(
         sacrifice_list.push(SacrificeInfo {
             source_entity: entity,
             mass_available: damage.mass_total
-                * damage.health
+                * damage.build_progress
                 * sacrifice_capability.mass_efficiency,
             energy_available: damage.energy_total
-                * damage.health
+                * damage.build_progress
                 * sacrifice_capability.energy_efficiency,
             target_entity: sacrificing.target,
         });
@@ -159,7 +121,7 @@ pub fn construct_sacrifice(
     let mut target_query = param_set.p1();
     for sacrificing in &mut sacrifice_list {
         if let Ok(mut target_damage) = target_query.get_mut(sacrificing.target_entity) {
-            if target_damage.health >= 1.0 {
+            if target_damage.is_finished() {
                 // target finished
                 commands
                     .entity(sacrificing.source_entity)
@@ -167,11 +129,11 @@ pub fn construct_sacrifice(
                 continue;
             } else {
                 // contribute build and despawn self
-                target_damage.health += f64::min(
+                let portion = f64::min(
                     sacrificing.mass_available / target_damage.mass_total,
                     sacrificing.energy_available / target_damage.energy_total,
                 );
-                target_damage.health = target_damage.health.min(1.0);
+                target_damage.add_build_progress(portion);
                 commands.entity(sacrificing.source_entity).despawn();
             }
         } else {
@@ -188,47 +150,46 @@ pub struct RASSimulation {
     pub update_schedule: Schedule,
 }
 
+impl Default for RASSimulation {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl RASSimulation {
     pub fn new() -> Self {
         let mut world = World::new();
 
         // resources
         world.insert_resource(CurrentTick(0));
-        world.insert_resource(Economy {
+        world.insert_resource(Economies::single(Economy {
             mass_capacity: 40000.0,
             energy_capacity: 100000.0,
             ..Default::default()
-        });
+        }));
         world.insert_resource(LogHandler::new(|message| println!("{}", message)));
-
-        // schedule and stages
-        let mut schedule = Schedule::default();
-        let tick_stage = SystemStage::single_threaded().with_system(count_tick);
-        let unit_spawn_stage = SystemStage::parallel().with_system(quantum_gate_spawn_construct);
-        let update_stage = SystemStage::parallel()
-            .with_system(execute_on_finished_construction)
-            .with_system(do_construct_resources_request)
-            .with_system(construct_sacrifice);
-        let economy_request_stage = SystemStage::parallel()
-            .with_system(economy_resource_producers)
-            .with_system(economy_process_resource_requests.after(economy_resource_producers));
-        let resource_usage_stage = SystemStage::parallel().with_system(do_construct);
-        let economy_accounting_stage =
-            SystemStage::parallel().with_system(economy_process_resource_consumption);
-
-        schedule.add_stage("tick count", tick_stage);
-        schedule.add_stage("unit spawning", unit_spawn_stage);
-        schedule.add_stage("update", update_stage);
-        schedule.add_stage("economy request", economy_request_stage);
-        schedule.add_stage("resource usage", resource_usage_stage);
-        schedule.add_stage("economy accounting", economy_accounting_stage);
+        world.insert_resource(ras_unit_registry());
+        world.insert_resource(Alliances::default());
+        world.insert_resource(Events::<UnitRolledOff>::default());
+        world.insert_resource(Triggers::default());
 
         RASSimulation {
             world,
-            update_schedule: schedule,
+            update_schedule: RASSimulation::schedule(),
         }
     }
 
+    /// update schedule run once per tick, FASimulation's with sacrifice added
+    fn schedule() -> Schedule {
+        let mut schedule = FASimulation::base_schedule();
+        // units finished by sacrifice start executing the same tick
+        schedule.add_system_to_stage(
+            "update",
+            construct_sacrifice.before(execute_on_finished_construction),
+        );
+        schedule
+    }
+
     pub fn run(&mut self) {
         self.update_schedule.run(&mut self.world);
     }
@@ -244,9 +205,30 @@ impl RASSimulation {
         );
     }
 
+    pub fn registry(&self) -> &UnitRegistry {
+        self.world.get_resource::<UnitRegistry>().unwrap()
+    }
+
+    /// spawn an unbuilt unit by blueprint id
+    pub fn spawn_unit(&mut self, id: &str, army: Army) -> Option<Entity> {
+        issue(
+            &mut self.world,
+            Order::SpawnUnit {
+                unit_id: id.to_string(),
+                army,
+                position: None,
+            },
+        )
+    }
+
     pub fn print_economy(&self) {
-        let economy = self.world.get_resource::<Economy>().unwrap();
-        println!("Economy info:");
+        for (army, economy) in self.world.get_resource::<Economies>().unwrap().iter() {
+            println!("Economy info (army {}):", army.0);
+            Self::print_army_economy(economy);
+        }
+    }
+
+    fn print_army_economy(economy: &Economy) {
         println!(
             "  Mass: {:.2}/{} +{:.4} -{:.4} (stall {:.5}, actual {:+.4})",
             economy.mass,
@@ -265,152 +247,68 @@ impl RASSimulation {
             economy.energy_stall,
             (economy.energy_produced - economy.energy_consumed) * TICK_RATE
         );
+        println!(
+            "  Wasted: mass {:.2}, energy {:.2}",
+            economy.total_mass_wasted, economy.total_energy_wasted
+        );
+        println!(
+            "  Reclaimed: mass {:.2}, energy {:.2}",
+            economy.total_mass_reclaimed, economy.total_energy_reclaimed
+        );
     }
 }
 
-fn main() {
-    println!("Hello, world!");
-    let mut sim = RASSimulation::new();
-
-    let args = std::env::args().collect::<Vec<String>>();
-    let target_count = args
-        .get(1)
-        .expect("requires sacu count")
-        .parse::<u32>()
-        .expect("invalid number");
-    let mass_yield = args
-        .get(2)
-        .expect("requires initial mass income")
-        .parse::<f64>()
-        .expect("invalid number");
-
-    let gate = sim
-        .world
-        .spawn()
-        .insert(QuantumGate::default())
-        .insert(Executing)
-        .insert(ResourceConsumer::default())
-        .insert(Engineering {
-            build_rate: 120000.0 / TICK_RATE,
-        })
-        .id();
-
-    let _resource_producer = sim
-        .world
-        .spawn()
-        .insert(ResourceProducer {
-            mass_yield: mass_yield / TICK_RATE,
-            energy_yield: 100_000.0 / TICK_RATE,
-            ..Default::default()
-        })
-        .insert(Executing)
-        .id();
-
-    // construct sacus
-    let mut sacu_query = sim
-        .world
-        .query_filtered::<Entity, (With<RASSupportCommander>, With<Executing>)>();
-    loop {
-        sim.run();
-        sim.print_tick();
-        if let Some(constructing) = sim.world.entity(gate).get::<Constructing>() {
-            println!(
-                "Quantum gate constructing entity id {}",
-                constructing.target.id()
-            );
-            if let Some(damage) = sim.world.entity(constructing.target).get::<Damage>() {
-                println!("  Build progress: {:.2}%", damage.health * 100.0);
-            }
-        }
-        let mut sacu_count = 0;
-        for _ in sacu_query.iter(&sim.world) {
-            sacu_count += 1;
-        }
-        println!("There are currently {} SACUs", sacu_count);
-        sim.print_economy();
-        if sacu_count >= target_count {
-            // run until target number of sacus
-            break;
-        }
+impl Simulation for RASSimulation {
+    fn world(&self) -> &World {
+        &self.world
     }
 
-    // stop gate
-    sim.world.entity_mut(gate).remove::<Executing>();
-    // create paragon
-    let paragon = sim
-        .world
-        .spawn()
-        .insert(PARAGON_DAMAGE)
-        .insert(Paragon)
-        .id();
-    // construct paragon
-    let sacus: Vec<Entity> = sacu_query.iter(&sim.world).collect();
-    let sacu_count = sacus.len();
-    for entity in sacus {
-        sim.world.entity_mut(entity).insert(Constructing {
-            target: paragon,
-            build_amount: 0.0,
-            energy_consumption_multiplier: 1.0,
-            energy_requested: 0.0,
-            mass_consumption_multiplier: 1.0,
-            mass_requested: 0.0,
-        });
+    fn world_mut(&mut self) -> &mut World {
+        &mut self.world
     }
 
-    let sacrifice_portion = f64::min(
-        RAS_SACU_DAMAGE.mass_total * RAS_SACU_SACRIFICE.mass_efficiency / PARAGON_DAMAGE.mass_total,
-        RAS_SACU_DAMAGE.energy_total * RAS_SACU_SACRIFICE.energy_efficiency
-            / PARAGON_DAMAGE.energy_total,
-    );
-    let sacrifice_point = 1.0 - sacu_count as f64 * sacrifice_portion;
-    assert!(sacrifice_point < 1.0);
-    // wait until close to sacrifice point
-    loop {
-        sim.run();
-        sim.print_tick();
-        sim.print_economy();
-        if let Some(damage) = sim.world.entity(paragon).get::<Damage>() {
-            println!("  Paragon build progress: {:.2}%", damage.health * 100.0);
-            if damage.health >= sacrifice_point {
-                break;
-            }
-        }
+    fn run(&mut self) {
+        RASSimulation::run(self)
     }
+}
 
-    let mut sacu_res_query = sim
-        .world
-        .query_filtered::<&ResourceProducer, (With<RASSupportCommander>, With<Executing>)>();
-    println!("SACU resource production totals");
-    let mut mass_total = 0.0;
-    let mut energy_total = 0.0;
-    for res in sacu_res_query.iter(&sim.world) {
-        mass_total += res.total_mass;
-        energy_total += res.total_energy;
-        println!("  mass: {:.2}, energy: {:.2}", res.total_mass, res.total_energy);
+/// Components of RASSimulation not in FASimulation
+const RAS_COMPONENTS: &[SnapshotComponent] = &[
+    SnapshotComponent::marker::<RASSupportCommander>("ras_support_commander"),
+    SnapshotComponent::marker::<Paragon>("paragon"),
+    SnapshotComponent::new::<SacrificeCapable>("sacrifice_capable"),
+    SnapshotComponent::new::<Sacrificing>("sacrificing"),
+];
+
+/// Resources of RASSimulation not in FASimulation
+const RAS_RESOURCES: &[SnapshotResource] = &[SnapshotResource::new::<ras::RasParagonState>(
+    "ras_paragon_state",
+)];
+
+impl Snapshots for RASSimulation {
+    fn components() -> Vec<SnapshotComponent> {
+        [FA_COMPONENTS, RAS_COMPONENTS].concat()
     }
-    println!("total mass: {:.2}", mass_total);
-    println!("total energy: {:.2}", energy_total);
-
-    // sacrifice sacus
-    println!("Sacrificing");
-    let sacus: Vec<Entity> = sacu_query.iter(&sim.world).collect();
-    for entity in sacus {
-        let mut handle = sim.world.entity_mut(entity);
-        handle.remove::<Constructing>();
-        handle.insert(Sacrificing { target: paragon });
+
+    fn resources() -> Vec<SnapshotResource> {
+        RAS_RESOURCES.to_vec()
     }
 
-    sim.run();
-    sim.print_tick();
-    sim.print_economy();
-    if let Some(damage) = sim.world.entity(paragon).get::<Damage>() {
-        println!("  Paragon build progress: {:.2}%", damage.health * 100.0);
+    fn kind() -> SimulationKind {
+        SimulationKind::Ras
     }
 
-    let tick = sim.get_tick();
-    println!("Total time: {} minutes", tick as f64 / 10. / 60.);
-    println!(
-        "Time to build paragon directly: {} minutes",
-        PARAGON_DAMAGE.mass_total / mass_yield / 60.
-    );
+    fn from_world(world: World) -> Self {
+        RASSimulation {
+            world,
+            update_schedule: RASSimulation::schedule(),
+        }
+    }
+}
+
+fn main() {
+    if let Err(err) = cli::run(Cli::parse()) {
+        eprintln!("error: {}", err);
+        std::process::exit(1);
+    }
 }
@@ -0,0 +1,242 @@
+use std::collections::HashMap;
+
+use crate::ras::*;
+use crate::sweep::*;
+
+/// Best SACU count found for an economy
+#[derive(Clone, Debug)]
+pub struct SacuOptimum {
+    pub best: SweepPoint,
+    /// results for SACU counts around the optimum, in increasing order
+    pub curve: Vec<SweepPoint>,
+    /// number of simulations run while searching
+    pub evaluations: usize,
+}
+
+/// Range of SACU counts searched and how much of the curve around the optimum
+/// to report
+#[derive(Clone, Debug)]
+pub struct SacuSearch {
+    pub min_sacu_count: u32,
+    pub max_sacu_count: u32,
+    /// SACU counts either side of the optimum to include in the curve
+    pub radius: u32,
+    /// threads used to compute the curve
+    pub threads: usize,
+}
+
+impl Default for SacuSearch {
+    fn default() -> Self {
+        SacuSearch {
+            min_sacu_count: 1,
+            max_sacu_count: 60,
+            radius: 5,
+            threads: 1,
+        }
+    }
+}
+
+/// Memoized total ticks of the RAS paragon experiment by SACU count
+struct Evaluator<'a> {
+    base: &'a RasParagonOptions,
+    reports: HashMap<u32, RasParagonReport>,
+}
+
+impl Evaluator<'_> {
+    fn ticks(&mut self, sacu_count: u32) -> Result<u64, RasParagonError> {
+        if let Some(report) = self.reports.get(&sacu_count) {
+            return Ok(report.total_ticks);
+        }
+        let report = run_ras_paragon(&RasParagonOptions {
+            sacu_count,
+            verbose: false,
+            record: None,
+            order_log: None,
+            ..self.base.clone()
+        })?;
+        let ticks = report.total_ticks;
+        self.reports.insert(sacu_count, report);
+        Ok(ticks)
+    }
+
+    /// whether a is better than b, preferring fewer SACUs on ties
+    fn better(&mut self, a: u32, b: u32) -> Result<bool, RasParagonError> {
+        let (a_ticks, b_ticks) = (self.ticks(a)?, self.ticks(b)?);
+        Ok(a_ticks < b_ticks || (a_ticks == b_ticks && a < b))
+    }
+}
+
+/// find the count in [min, max] preferred by better with integer golden-section
+/// search, then hill climb from the result since time is only roughly unimodal
+/// in SACU count
+fn search_minimum<E>(
+    min: u32,
+    max: u32,
+    mut better: impl FnMut(u32, u32) -> Result<bool, E>,
+) -> Result<u32, E> {
+    // golden-section search narrowing [low, high] down to a few counts
+    let inverse_phi = (5f64.sqrt() - 1.0) / 2.0;
+    let (mut low, mut high) = (min, max);
+    while high - low > 2 {
+        let offset = ((high - low) as f64 * inverse_phi).round() as u32;
+        let (left, right) = (high - offset, low + offset);
+        let (left, right) = if left < right {
+            (left, right)
+        } else {
+            (low + (high - low) / 2, low + (high - low) / 2 + 1)
+        };
+        if better(left, right)? {
+            high = right;
+        } else {
+            low = left;
+        }
+    }
+    let mut best = low;
+    for count in low + 1..=high {
+        if better(count, best)? {
+            best = count;
+        }
+    }
+
+    // hill climb to a local minimum
+    loop {
+        let mut next = best;
+        for neighbor in [best.saturating_sub(1), best.saturating_add(1)] {
+            if (min..=max).contains(&neighbor) && better(neighbor, next)? {
+                next = neighbor;
+            }
+        }
+        if next == best {
+            break;
+        }
+        best = next;
+    }
+    Ok(best)
+}
+
+/// find the SACU count minimizing total time to a completed paragon
+pub fn optimize_sacu_count(
+    base: &RasParagonOptions,
+    search: &SacuSearch,
+) -> Result<SacuOptimum, RasParagonError> {
+    let (min, max) = (search.min_sacu_count.max(1), search.max_sacu_count);
+    if min > max {
+        return Err(RasParagonError::InvalidOption {
+            name: "max sacu count",
+            message: "must be at least the min sacu count",
+        });
+    }
+    let mut evaluator = Evaluator {
+        base,
+        reports: HashMap::new(),
+    };
+
+    let best = search_minimum(min, max, |a, b| evaluator.better(a, b))?;
+
+    // sensitivity curve, reusing results already computed
+    let curve_counts: Vec<u32> = (best.saturating_sub(search.radius).max(min)
+        ..=best.saturating_add(search.radius).min(max))
+        .collect();
+    let missing: Vec<u32> = curve_counts
+        .iter()
+        .copied()
+        .filter(|sacu_count| !evaluator.reports.contains_key(sacu_count))
+        .collect();
+    let evaluations = evaluator.reports.len() + missing.len();
+    for point in sweep_ras_paragon(base, &missing, &[base.mass_income], search.threads)? {
+        evaluator.reports.insert(point.sacu_count, point.report);
+    }
+    let point = |sacu_count: u32| SweepPoint {
+        sacu_count,
+        mass_income: base.mass_income,
+        report: evaluator.reports[&sacu_count].clone(),
+    };
+
+    Ok(SacuOptimum {
+        best: point(best),
+        curve: curve_counts.into_iter().map(point).collect(),
+        evaluations,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::convert::Infallible;
+
+    use super::*;
+
+    /// search costs by count, returning the result and the counts evaluated
+    fn search_costs(min: u32, max: u32, cost: impl Fn(u32) -> u32) -> (u32, HashSet<u32>) {
+        let mut evaluated = HashSet::new();
+        let best = search_minimum(min, max, |a, b| {
+            evaluated.extend([a, b]);
+            Ok::<_, Infallible>(cost(a) < cost(b) || (cost(a) == cost(b) && a < b))
+        })
+        .unwrap();
+        (best, evaluated)
+    }
+
+    #[test]
+    fn golden_section_finds_unimodal_minimum() {
+        for target in 1..=60 {
+            let (best, evaluated) = search_costs(1, 60, |count| count.abs_diff(target));
+            assert_eq!(best, target);
+            assert!(evaluated.len() < 20, "{} counts evaluated", evaluated.len());
+        }
+        // ties go to fewer SACUs
+        assert_eq!(search_costs(1, 60, |count| count.max(20)).0, 1);
+        assert_eq!(search_costs(7, 7, |count| count).0, 7);
+    }
+
+    #[test]
+    fn hill_climb_leaves_golden_section_local_minimum() {
+        // golden section ends on 7, hill climbing walks down to 9
+        let costs = [1, 0, 5, 1, 4, 3, 2, 1, 0, 1];
+        let (best, _) = search_costs(1, 10, |count| costs[count as usize - 1]);
+        assert_eq!(best, 9);
+    }
+
+    #[test]
+    fn optimizes_small_range() {
+        let base = RasParagonOptions {
+            mass_income: 20000.0,
+            energy_income: 1_000_000.0,
+            mass_capacity: 1_000_000.0,
+            energy_capacity: 10_000_000.0,
+            ..Default::default()
+        };
+        let search = SacuSearch {
+            min_sacu_count: 36,
+            max_sacu_count: 40,
+            radius: 10,
+            threads: 2,
+        };
+        let optimum = optimize_sacu_count(&base, &search).unwrap();
+
+        // the curve is clamped to the range and covers every count in it
+        let counts: Vec<u32> = optimum.curve.iter().map(|point| point.sacu_count).collect();
+        assert_eq!(counts, vec![36, 37, 38, 39, 40]);
+        assert_eq!(optimum.evaluations, 5);
+        let fastest = optimum
+            .curve
+            .iter()
+            .map(|point| point.report.total_ticks)
+            .min()
+            .unwrap();
+        assert_eq!(optimum.best.report.total_ticks, fastest);
+    }
+
+    #[test]
+    fn min_above_max_is_an_error() {
+        let search = SacuSearch {
+            min_sacu_count: 10,
+            max_sacu_count: 5,
+            ..Default::default()
+        };
+        assert!(matches!(
+            optimize_sacu_count(&RasParagonOptions::default(), &search),
+            Err(RasParagonError::InvalidOption { .. })
+        ));
+    }
+}
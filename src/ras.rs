@@ -0,0 +1,437 @@
+use std::fmt;
+use std::path::PathBuf;
+
+use bevy_ecs::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::factory::*;
+use crate::recorder::*;
+use crate::replay::*;
+use crate::simulation::*;
+use crate::trigger::*;
+use crate::{RASSimulation, RASSupportCommander, PARAGON_ID, RAS_SACU_ID, RAS_SACU_SACRIFICE};
+
+/// Parameters of the RAS paragon experiment: build SACUs from a quantum gate,
+/// have them construct a paragon, then sacrifice them into it
+#[derive(Clone, Debug)]
+pub struct RasParagonOptions {
+    /// number of SACUs to build before starting the paragon
+    pub sacu_count: u32,
+    /// mass income per second, excluding SACUs
+    pub mass_income: f64,
+    /// energy income per second, excluding SACUs
+    pub energy_income: f64,
+    /// mass storage capacity
+    pub mass_capacity: f64,
+    /// energy storage capacity
+    pub energy_capacity: f64,
+    /// build rate of the quantum gate (build_time per second)
+    pub gate_build_rate: f64,
+    /// time (in ticks) for a SACU to leave the quantum gate
+    pub gate_rolloff_time: i32,
+    /// give up if the paragon isn't finished after this many ticks
+    pub max_ticks: u64,
+    /// print simulation state every tick
+    pub verbose: bool,
+    /// record simulation state to a file
+    pub record: Option<RecordOptions>,
+    /// write a replay of the orders issued to a file
+    pub order_log: Option<PathBuf>,
+}
+
+impl Default for RasParagonOptions {
+    fn default() -> Self {
+        RasParagonOptions {
+            sacu_count: 10,
+            mass_income: 500.0,
+            energy_income: 100_000.0,
+            mass_capacity: 40000.0,
+            energy_capacity: 100000.0,
+            gate_build_rate: 120000.0,
+            gate_rolloff_time: 15,
+            // six hours of game time
+            max_ticks: 6 * 60 * 60 * TICK_RATE as u64,
+            verbose: false,
+            record: None,
+            order_log: None,
+        }
+    }
+}
+
+/// Outcome of the RAS paragon experiment
+#[derive(Clone, Debug)]
+pub struct RasParagonReport {
+    /// ticks until the paragon was finished
+    pub total_ticks: u64,
+    /// mass produced by SACUs before they were sacrificed
+    pub sacu_mass_produced: f64,
+    /// energy produced by SACUs before they were sacrificed
+    pub sacu_energy_produced: f64,
+    /// minutes to build the paragon from mass income alone
+    pub direct_build_minutes: f64,
+}
+
+impl RasParagonReport {
+    /// minutes until the paragon was finished
+    pub fn total_minutes(&self) -> f64 {
+        self.total_ticks as f64 / TICK_RATE / 60.
+    }
+}
+
+#[derive(Debug)]
+pub enum RasParagonError {
+    /// option value out of range
+    InvalidOption {
+        name: &'static str,
+        message: &'static str,
+    },
+    /// unit missing from the registry
+    MissingUnit(&'static str),
+    /// paragon not finished within max_ticks
+    TickLimit(u64),
+    /// failed to write the recording
+    Record(std::io::Error),
+    /// failed to write the replay of orders
+    OrderLog(ReplayError),
+}
+
+impl fmt::Display for RasParagonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RasParagonError::InvalidOption { name, message } => {
+                write!(f, "invalid value for {}: {}", name, message)
+            }
+            RasParagonError::MissingUnit(id) => write!(f, "unit {} is not registered", id),
+            RasParagonError::TickLimit(ticks) => {
+                write!(f, "paragon not finished after {} ticks", ticks)
+            }
+            RasParagonError::Record(err) => write!(f, "failed to write recording: {}", err),
+            RasParagonError::OrderLog(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for RasParagonError {}
+
+/// finite and above zero
+fn is_positive(value: f64) -> bool {
+    value.is_finite() && value > 0.0
+}
+
+impl RasParagonOptions {
+    fn validate(&self) -> Result<(), RasParagonError> {
+        let invalid = |name, message| Err(RasParagonError::InvalidOption { name, message });
+        if self.sacu_count == 0 {
+            return invalid("sacu count", "must be at least 1");
+        }
+        if !is_positive(self.mass_income) {
+            return invalid("mass income", "must be positive");
+        }
+        if !is_positive(self.energy_income) {
+            return invalid("energy income", "must be positive");
+        }
+        if !is_positive(self.mass_capacity) {
+            return invalid("mass storage", "must be positive");
+        }
+        if !is_positive(self.energy_capacity) {
+            return invalid("energy storage", "must be positive");
+        }
+        if !is_positive(self.gate_build_rate) {
+            return invalid("gate build rate", "must be positive");
+        }
+        if self.gate_rolloff_time < 0 {
+            return invalid("gate rolloff time", "must not be negative");
+        }
+        if self.max_ticks == 0 {
+            return invalid("max ticks", "must be at least 1");
+        }
+        Ok(())
+    }
+}
+
+/// run the RAS paragon experiment to completion
+pub fn run_ras_paragon(options: &RasParagonOptions) -> Result<RasParagonReport, RasParagonError> {
+    run_experiment(options).map(|(report, _)| report)
+}
+
+/// run the RAS paragon experiment to completion, returning the simulation as
+/// it finished
+fn run_experiment(
+    options: &RasParagonOptions,
+) -> Result<(RasParagonReport, RASSimulation), RasParagonError> {
+    options.validate()?;
+    let mut sim = RASSimulation::new();
+    if let Some(record) = &options.record {
+        start_recording(&mut sim.world, record).map_err(RasParagonError::Record)?;
+    }
+    if !options.verbose {
+        sim.world.insert_resource(LogHandler::new(|_| {}));
+    }
+    {
+        let mut economies = sim.world.resource_mut::<Economies>();
+        let economy = economies.get_or_default(Army(0));
+        economy.mass_capacity = options.mass_capacity;
+        economy.energy_capacity = options.energy_capacity;
+    }
+    let sacu_damage = sim
+        .registry()
+        .blueprint(RAS_SACU_ID)
+        .ok_or(RasParagonError::MissingUnit(RAS_SACU_ID))?
+        .damage();
+    let paragon_damage = sim
+        .registry()
+        .blueprint(PARAGON_ID)
+        .ok_or(RasParagonError::MissingUnit(PARAGON_ID))?
+        .damage();
+
+    let gate = sim
+        .world
+        .spawn()
+        .insert(
+            Factory::new(options.gate_rolloff_time).with_order(BuildOrder::repeat(RAS_SACU_ID, 1)),
+        )
+        .insert(Army(0))
+        .insert(Executing)
+        .insert(ResourceConsumer {
+            priority: ConsumerPriority::High,
+            ..Default::default()
+        })
+        .insert(Engineering {
+            build_rate: options.gate_build_rate / TICK_RATE,
+        })
+        .id();
+
+    let _resource_producer = sim
+        .world
+        .spawn()
+        .insert(ResourceProducer {
+            mass_yield: options.mass_income / TICK_RATE,
+            energy_yield: options.energy_income / TICK_RATE,
+            ..Default::default()
+        })
+        .insert(Army(0))
+        .insert(Executing)
+        .id();
+
+    // once enough sacus are built, have them construct a paragon
+    let sacrifice_portion = f64::min(
+        sacu_damage.mass_total * RAS_SACU_SACRIFICE.mass_efficiency / paragon_damage.mass_total,
+        sacu_damage.energy_total * RAS_SACU_SACRIFICE.energy_efficiency
+            / paragon_damage.energy_total,
+    );
+    let verbose = options.verbose;
+    sim.world.insert_resource(RasParagonState::default());
+    add_trigger(
+        &mut sim.world,
+        "start paragon",
+        Condition::count_at_least::<(With<RASSupportCommander>, With<Executing>)>(
+            options.sacu_count as usize,
+        ),
+        move |world| start_paragon(world, gate, sacrifice_portion, verbose),
+    );
+    if options.order_log.is_some() {
+        start_order_log(&mut sim);
+    }
+
+    let mut sacu_query = sim
+        .world
+        .query_filtered::<Entity, (With<RASSupportCommander>, With<Executing>)>();
+    loop {
+        sim.run();
+        let paragon = sim.world.resource::<RasParagonState>().paragon;
+        if verbose {
+            sim.print_tick();
+            match paragon {
+                None => {
+                    if let Some(constructing) = sim.world.entity(gate).get::<Constructing>() {
+                        println!(
+                            "Quantum gate constructing entity id {}",
+                            constructing.target.id()
+                        );
+                        if let Some(damage) = sim.world.entity(constructing.target).get::<Damage>()
+                        {
+                            println!("  Build progress: {:.2}%", damage.build_progress * 100.0);
+                        }
+                    }
+                    let sacu_count = sacu_query.iter(&sim.world).count();
+                    println!("There are currently {} SACUs", sacu_count);
+                }
+                Some(paragon) => {
+                    if let Some(damage) = sim.world.get::<Damage>(paragon) {
+                        println!(
+                            "  Paragon build progress: {:.2}%",
+                            damage.build_progress * 100.0
+                        );
+                    }
+                }
+            }
+            sim.print_economy();
+        }
+        let finished = paragon
+            .and_then(|paragon| sim.world.get::<Damage>(paragon))
+            .is_some_and(|damage| damage.is_finished());
+        if finished {
+            break;
+        }
+        if sim.get_tick() >= options.max_ticks {
+            finish_recording(&mut sim.world).map_err(RasParagonError::Record)?;
+            return Err(RasParagonError::TickLimit(options.max_ticks));
+        }
+    }
+
+    finish_recording(&mut sim.world).map_err(RasParagonError::Record)?;
+    if let (Some(path), Some(replay)) = (&options.order_log, finish_order_log(&mut sim)) {
+        replay.save(path).map_err(RasParagonError::OrderLog)?;
+    }
+    let state = sim.world.resource::<RasParagonState>();
+    let report = RasParagonReport {
+        total_ticks: sim.get_tick(),
+        sacu_mass_produced: state.sacu_mass_produced,
+        sacu_energy_produced: state.sacu_energy_produced,
+        direct_build_minutes: paragon_damage.mass_total / options.mass_income / 60.,
+    };
+    Ok((report, sim))
+}
+
+/// Progress of the RAS paragon experiment, updated by its triggers
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub(crate) struct RasParagonState {
+    #[serde(default, with = "crate::snapshot::option_entity_bits")]
+    paragon: Option<Entity>,
+    /// resources produced by SACUs before they were sacrificed
+    sacu_mass_produced: f64,
+    sacu_energy_produced: f64,
+}
+
+/// stop the gate and have all SACUs construct a paragon, sacrificing them once
+/// that finishes it
+fn start_paragon(world: &mut World, gate: Entity, sacrifice_portion: f64, verbose: bool) {
+    issue(
+        world,
+        Order::Stop {
+            entities: vec![gate],
+        },
+    );
+    let paragon = issue(
+        world,
+        Order::SpawnUnit {
+            unit_id: PARAGON_ID.to_string(),
+            army: Army(0),
+            position: None,
+        },
+    )
+    .expect("paragon is registered");
+    world.resource_mut::<RasParagonState>().paragon = Some(paragon);
+
+    let sacus: Vec<Entity> = world
+        .query_filtered::<Entity, (With<RASSupportCommander>, With<Executing>)>()
+        .iter(world)
+        .collect();
+    let sacrifice_point = 1.0 - sacus.len() as f64 * sacrifice_portion;
+    issue(
+        world,
+        Order::Construct {
+            entities: sacus,
+            target: paragon,
+        },
+    );
+    add_trigger(
+        world,
+        "sacrifice",
+        Condition::build_progress(paragon, sacrifice_point),
+        move |world| sacrifice_sacus(world, paragon, verbose),
+    );
+}
+
+/// record SACU production and sacrifice all SACUs into the paragon
+fn sacrifice_sacus(world: &mut World, paragon: Entity, verbose: bool) {
+    let mut sacu_query = world
+        .query_filtered::<(Entity, &ResourceProducer), (With<RASSupportCommander>, With<Executing>)>();
+    if verbose {
+        println!("SACU resource production totals");
+    }
+    let mut sacus = Vec::new();
+    let mut mass_total = 0.0;
+    let mut energy_total = 0.0;
+    for (entity, res) in sacu_query.iter(world) {
+        sacus.push(entity);
+        mass_total += res.total_mass;
+        energy_total += res.total_energy;
+        if verbose {
+            println!(
+                "  mass: {:.2}, energy: {:.2}",
+                res.total_mass, res.total_energy
+            );
+        }
+    }
+    if verbose {
+        println!("total mass: {:.2}", mass_total);
+        println!("total energy: {:.2}", energy_total);
+        println!("Sacrificing");
+    }
+    let mut state = world.resource_mut::<RasParagonState>();
+    state.sacu_mass_produced = mass_total;
+    state.sacu_energy_produced = energy_total;
+
+    issue(
+        world,
+        Order::Sacrifice {
+            entities: sacus,
+            target: paragon,
+        },
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn order_log_replays_experiment() {
+        let path = std::env::temp_dir().join(format!("ras-paragon-{}.toml", std::process::id()));
+        let options = RasParagonOptions {
+            sacu_count: 40,
+            mass_income: 20000.0,
+            energy_income: 1_000_000.0,
+            mass_capacity: 1_000_000.0,
+            energy_capacity: 10_000_000.0,
+            order_log: Some(path.clone()),
+            ..Default::default()
+        };
+        let (report, sim) = run_experiment(&options).unwrap();
+        let replay = Replay::<RASSimulation>::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        let replayed = replay.unwrap().run(None).unwrap();
+
+        assert_eq!(replayed.get_tick(), report.total_ticks);
+        let paragon = sim.world.resource::<RasParagonState>().paragon.unwrap();
+        assert!(replayed
+            .world
+            .get::<Damage>(paragon)
+            .is_some_and(|damage| damage.is_finished()));
+        let economy = sim.world.resource::<Economies>().get(Army(0)).unwrap();
+        let replayed_economy = replayed.world.resource::<Economies>().get(Army(0)).unwrap();
+        assert_eq!(replayed_economy.mass, economy.mass);
+        assert_eq!(replayed_economy.energy, economy.energy);
+        assert_eq!(
+            replayed_economy.total_mass_wasted,
+            economy.total_mass_wasted
+        );
+        assert_eq!(
+            replayed_economy.total_energy_wasted,
+            economy.total_energy_wasted
+        );
+    }
+
+    #[test]
+    fn unfinished_paragon_stops_at_tick_limit() {
+        let options = RasParagonOptions {
+            max_ticks: 100,
+            ..Default::default()
+        };
+        assert!(matches!(
+            run_ras_paragon(&options),
+            Err(RasParagonError::TickLimit(100))
+        ));
+    }
+}
@@ -0,0 +1,370 @@
+use bevy_ecs::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::registry::UnitId;
+use crate::simulation::*;
+
+/// energy worth the same reclaim time as one mass
+pub const RECLAIM_ENERGY_PER_MASS: f64 = 10.0;
+/// fraction of a unit's mass cost left in its wreck
+pub const WRECKAGE_MASS_FRACTION: f64 = 0.81;
+
+/// Wreck or prop which can be reclaimed for resources
+#[derive(Component, Clone, Debug, Serialize, Deserialize)]
+pub struct Reclaimable {
+    /// total mass held when untouched
+    pub mass_total: f64,
+    /// total energy held when untouched
+    pub energy_total: f64,
+    /// time to reclaim fully, unitless (see build_rate)
+    pub reclaim_time: f64,
+    /// fraction left to reclaim (1.0 = untouched)
+    pub remaining: f64,
+}
+
+impl Reclaimable {
+    /// reclaimable holding mass and energy, taking roughly one second per mass
+    /// per point of build rate
+    pub fn new(mass_total: f64, energy_total: f64) -> Self {
+        Reclaimable {
+            mass_total,
+            energy_total,
+            reclaim_time: f64::max(
+                EPSILON,
+                f64::max(mass_total, energy_total / RECLAIM_ENERGY_PER_MASS),
+            ),
+            remaining: 1.0,
+        }
+    }
+}
+
+/// Wreck left behind by a destroyed unit
+#[derive(Component, Clone, Debug, Serialize, Deserialize)]
+pub struct Wreck {
+    /// blueprint id of destroyed unit, if known
+    pub unit_id: Option<String>,
+}
+
+/// Entity is reclaiming a wreck or prop
+#[derive(Component, Clone, Debug, Serialize, Deserialize)]
+pub struct Reclaiming {
+    #[serde(with = "crate::snapshot::entity_bits")]
+    pub target: Entity,
+}
+
+/// Resources an entity has reclaimed
+#[derive(Component, Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ReclaimTotals {
+    /// total mass reclaimed
+    pub total_mass: f64,
+    /// total energy reclaimed
+    pub total_energy: f64,
+}
+
+/// despawn destroyed units, leaving a wreck with part of the mass built so far
+#[allow(clippy::type_complexity)]
+pub fn destroy_units(
+    query: Query<(Entity, Option<&Damage>, Option<&UnitId>), With<Destroyed>>,
+    mut commands: Commands,
+) {
+    for (entity, damage, unit_id) in &query {
+        commands.entity(entity).despawn();
+        let mass = damage.map_or(0.0, |damage| {
+            damage.mass_total * damage.build_progress * WRECKAGE_MASS_FRACTION
+        });
+        if mass <= EPSILON {
+            continue;
+        }
+        commands
+            .spawn()
+            .insert(Wreck {
+                unit_id: unit_id.map(|unit_id| unit_id.0.clone()),
+            })
+            .insert(Reclaimable::new(mass, 0.0));
+    }
+}
+
+/// reclaim targets and add reclaimed resources to the economy
+#[allow(clippy::type_complexity)]
+pub fn do_reclaim(
+    mut query: Query<
+        (
+            Entity,
+            &Army,
+            &Reclaiming,
+            &Engineering,
+            Option<&mut ReclaimTotals>,
+        ),
+        (With<Executing>, Without<ConstructionPaused>),
+    >,
+    mut target_query: Query<&mut Reclaimable>,
+    mut economies: ResMut<Economies>,
+    mut commands: Commands,
+) {
+    for (_, economy) in economies.iter_mut() {
+        economy.mass_reclaimed = 0.0;
+        economy.energy_reclaimed = 0.0;
+    }
+    for (entity, army, reclaiming, engineering, reclaim_totals) in &mut query {
+        let mut target = match target_query.get_mut(reclaiming.target) {
+            Ok(target) => target,
+            Err(_) => {
+                // target gone
+                commands.entity(entity).remove::<Reclaiming>();
+                continue;
+            }
+        };
+        let portion = f64::min(
+            target.remaining,
+            engineering.build_rate / target.reclaim_time,
+        );
+        target.remaining -= portion;
+        let mass = portion * target.mass_total;
+        let energy = portion * target.energy_total;
+        if target.remaining <= EPSILON {
+            commands.entity(reclaiming.target).despawn();
+            commands.entity(entity).remove::<Reclaiming>();
+        }
+
+        let economy = economies.get_or_default(*army);
+        economy.mass += mass;
+        economy.energy += energy;
+        economy.mass_reclaimed += mass;
+        economy.energy_reclaimed += energy;
+        economy.total_mass_reclaimed += mass;
+        economy.total_energy_reclaimed += energy;
+        match reclaim_totals {
+            Some(mut reclaim_totals) => {
+                reclaim_totals.total_mass += mass;
+                reclaim_totals.total_energy += energy;
+            }
+            None => {
+                commands.entity(entity).insert(ReclaimTotals {
+                    total_mass: mass,
+                    total_energy: energy,
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::factory::{BuildOrder, Factory};
+    use crate::replay::{issue, Order};
+    use crate::simulation::tests::*;
+    use crate::upgrade::UpgradeProgress;
+
+    fn wrecks(sim: &mut FASimulation) -> Vec<(Option<String>, Reclaimable)> {
+        let mut query = sim.world.query::<(&Wreck, &Reclaimable)>();
+        query
+            .iter(&sim.world)
+            .map(|(wreck, reclaimable)| (wreck.unit_id.clone(), reclaimable.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn wreck_keeps_fraction_of_mass_built() {
+        let mut sim = test_simulation();
+        let unit = sim.spawn_unit("ueb1202", Army(0)).unwrap();
+        sim.world.get_mut::<Damage>(unit).unwrap().build_progress = 0.5;
+        issue(
+            &mut sim.world,
+            Order::Destroy {
+                entities: vec![unit],
+            },
+        );
+        sim.run();
+        assert!(sim.world.get_entity(unit).is_none());
+        let wrecks = wrecks(&mut sim);
+        assert_eq!(wrecks.len(), 1);
+        assert_eq!(wrecks[0].0.as_deref(), Some("ueb1202"));
+        assert!((wrecks[0].1.mass_total - 900.0 * 0.5 * WRECKAGE_MASS_FRACTION).abs() < 1e-9);
+        assert_eq!(wrecks[0].1.energy_total, 0.0);
+    }
+
+    #[test]
+    fn destroy_and_assist_in_same_tick() {
+        // order of commands within a stage isn't fixed, so try several times
+        for _ in 0..10 {
+            let mut sim = test_simulation();
+            set_stored(&mut sim, Army(0), 4000.0, 100000.0);
+            let factory = spawn_builder(&mut sim, Army(0), 10.0);
+            sim.world
+                .entity_mut(factory)
+                .insert(Factory::new(0).with_order(BuildOrder::repeat("ueb1103", 1)));
+            let engineer = spawn_builder(&mut sim, Army(0), 10.0);
+            sim.run();
+            assert!(sim.world.entity(factory).contains::<Constructing>());
+
+            issue(
+                &mut sim.world,
+                Order::Destroy {
+                    entities: vec![engineer],
+                },
+            );
+            issue(
+                &mut sim.world,
+                Order::Assist {
+                    entities: vec![engineer],
+                    target: factory,
+                },
+            );
+            for _ in 0..5 {
+                sim.run();
+            }
+            assert!(sim.world.get_entity(engineer).is_none());
+            assert!(sim.world.entity(factory).contains::<Constructing>());
+        }
+    }
+
+    #[test]
+    fn destroy_and_upgrade_in_same_tick() {
+        for _ in 0..10 {
+            let mut sim = test_simulation();
+            set_stored(&mut sim, Army(0), 4000.0, 100000.0);
+            let extractor = spawn_built(&mut sim, "ueb1103", Army(0));
+            let built = sim.world.get::<Damage>(extractor).unwrap().clone();
+
+            issue(
+                &mut sim.world,
+                Order::Destroy {
+                    entities: vec![extractor],
+                },
+            );
+            issue(
+                &mut sim.world,
+                Order::Upgrade {
+                    entities: vec![extractor],
+                    unit_id: "ueb1202".to_string(),
+                },
+            );
+            for _ in 0..5 {
+                sim.run();
+            }
+            assert!(sim.world.get_entity(extractor).is_none());
+            // placeholder of the cancelled upgrade is cleaned up
+            let mut progress_query = sim.world.query::<&UpgradeProgress>();
+            assert_eq!(progress_query.iter(&sim.world).count(), 0);
+            let wrecks = wrecks(&mut sim);
+            assert_eq!(wrecks.len(), 1);
+            assert!(
+                (wrecks[0].1.mass_total - built.mass_total * WRECKAGE_MASS_FRACTION).abs() < 1e-9
+            );
+        }
+    }
+
+    /// spawn a prop and an engineer reclaiming it with build rate 10
+    fn reclaim_prop(sim: &mut FASimulation, mass: f64, energy: f64) -> (Entity, Entity) {
+        let prop = sim
+            .world
+            .spawn()
+            .insert(Reclaimable::new(mass, energy))
+            .id();
+        let engineer = spawn_builder(sim, Army(0), 10.0);
+        issue(
+            &mut sim.world,
+            Order::Reclaim {
+                entities: vec![engineer],
+                target: prop,
+            },
+        );
+        (prop, engineer)
+    }
+
+    #[test]
+    fn reclaim_time_is_larger_of_mass_and_energy() {
+        assert_eq!(Reclaimable::new(100.0, 500.0).reclaim_time, 100.0);
+        assert_eq!(Reclaimable::new(10.0, 500.0).reclaim_time, 50.0);
+        assert_eq!(Reclaimable::new(0.0, 0.0).reclaim_time, EPSILON);
+    }
+
+    #[test]
+    fn reclaim_at_build_rate() {
+        let mut sim = test_simulation();
+        let (prop, engineer) = reclaim_prop(&mut sim, 100.0, 200.0);
+        for _ in 0..10 {
+            sim.run();
+        }
+        // one point of build rate per tick reclaims 1 / 100 of the prop
+        assert!((sim.world.get::<Reclaimable>(prop).unwrap().remaining - 0.9).abs() < 1e-9);
+        let totals = sim.world.get::<ReclaimTotals>(engineer).unwrap();
+        assert!((totals.total_mass - 10.0).abs() < 1e-9);
+        assert!((totals.total_energy - 20.0).abs() < 1e-9);
+        let economy = sim.economy(Army(0)).unwrap();
+        assert!((economy.mass - 10.0).abs() < 1e-9);
+        assert!((economy.mass_reclaimed - 1.0).abs() < 1e-9);
+        assert!((economy.total_energy_reclaimed - 20.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn energy_heavy_props_take_longer() {
+        // 500 energy takes as long as 50 mass
+        let mut sim = test_simulation();
+        let (prop, engineer) = reclaim_prop(&mut sim, 10.0, 500.0);
+        for _ in 0..49 {
+            sim.run();
+        }
+        assert!(sim.world.get_entity(prop).is_some());
+        sim.run();
+
+        // removed once fully reclaimed
+        assert!(sim.world.get_entity(prop).is_none());
+        assert!(!sim.world.entity(engineer).contains::<Reclaiming>());
+        let totals = sim.world.get::<ReclaimTotals>(engineer).unwrap();
+        assert!((totals.total_mass - 10.0).abs() < 1e-9);
+        assert!((totals.total_energy - 500.0).abs() < 1e-9);
+        // nothing left to reclaim
+        sim.run();
+        assert!((sim.economy(Army(0)).unwrap().total_mass_reclaimed - 10.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn reclaim_is_clamped_to_storage() {
+        let mut sim = test_simulation();
+        sim.add_army(
+            Army(0),
+            Economy {
+                mass: 99.5,
+                mass_capacity: 100.0,
+                ..Default::default()
+            },
+        );
+        reclaim_prop(&mut sim, 100.0, 0.0);
+        sim.run();
+        let economy = sim.economy(Army(0)).unwrap();
+        assert_eq!(economy.mass, 100.0);
+        assert!((economy.mass_reclaimed - 1.0).abs() < 1e-9);
+        assert!((economy.mass_wasted - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn wrecks_are_removed_once_reclaimed() {
+        let mut sim = test_simulation();
+        let unit = spawn_built(&mut sim, "ueb1103", Army(0));
+        issue(
+            &mut sim.world,
+            Order::Destroy {
+                entities: vec![unit],
+            },
+        );
+        sim.run();
+        let wreck = sim
+            .world
+            .query_filtered::<Entity, With<Wreck>>()
+            .single(&sim.world);
+        let engineer = spawn_builder(&mut sim, Army(0), 1000.0);
+        issue(
+            &mut sim.world,
+            Order::Reclaim {
+                entities: vec![engineer],
+                target: wreck,
+            },
+        );
+        sim.run();
+        assert!(sim.world.get_entity(wreck).is_none());
+        assert!(!sim.world.entity(engineer).contains::<Reclaiming>());
+        assert!(wrecks(&mut sim).is_empty());
+    }
+}
@@ -0,0 +1,390 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+
+use bevy_ecs::prelude::*;
+
+use crate::registry::UnitId;
+use crate::simulation::*;
+
+/// Reads one economy field
+type EconomyField = fn(&Economy) -> f64;
+
+/// Economy fields written to recordings, in column order
+const ECONOMY_FIELDS: &[(&str, EconomyField)] = &[
+    ("mass", |economy| economy.mass),
+    ("energy", |economy| economy.energy),
+    ("mass_capacity", |economy| economy.mass_capacity),
+    ("energy_capacity", |economy| economy.energy_capacity),
+    ("mass_stall", |economy| economy.mass_stall),
+    ("energy_stall", |economy| economy.energy_stall),
+    ("mass_produced", |economy| economy.mass_produced),
+    ("energy_produced", |economy| economy.energy_produced),
+    ("mass_requested", |economy| economy.mass_requested),
+    ("energy_requested", |economy| economy.energy_requested),
+    ("mass_consumed", |economy| economy.mass_consumed),
+    ("energy_consumed", |economy| economy.energy_consumed),
+    ("mass_overflow", |economy| economy.mass_overflow),
+    ("energy_overflow", |economy| economy.energy_overflow),
+    ("mass_shared", |economy| economy.mass_shared),
+    ("energy_shared", |economy| economy.energy_shared),
+    ("mass_received", |economy| economy.mass_received),
+    ("energy_received", |economy| economy.energy_received),
+    ("mass_wasted", |economy| economy.mass_wasted),
+    ("energy_wasted", |economy| economy.energy_wasted),
+    ("mass_reclaimed", |economy| economy.mass_reclaimed),
+    ("energy_reclaimed", |economy| economy.energy_reclaimed),
+    ("total_mass_shared", |economy| economy.total_mass_shared),
+    ("total_energy_shared", |economy| economy.total_energy_shared),
+    ("total_mass_received", |economy| economy.total_mass_received),
+    ("total_energy_received", |economy| {
+        economy.total_energy_received
+    }),
+    ("total_mass_wasted", |economy| economy.total_mass_wasted),
+    ("total_energy_wasted", |economy| economy.total_energy_wasted),
+    ("total_mass_reclaimed", |economy| {
+        economy.total_mass_reclaimed
+    }),
+    ("total_energy_reclaimed", |economy| {
+        economy.total_energy_reclaimed
+    }),
+];
+
+/// Output format of a recording
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecordFormat {
+    /// one row per army economy and per entity, with a kind column telling
+    /// them apart
+    Csv,
+    /// one JSON object per recorded tick
+    Ndjson,
+}
+
+/// Where and how often to record simulation state
+#[derive(Clone, Debug)]
+pub struct RecordOptions {
+    pub path: PathBuf,
+    pub format: RecordFormat,
+    /// record every this many ticks
+    pub interval: u64,
+}
+
+impl RecordOptions {
+    /// create the output file and a recorder writing to it
+    pub fn open(&self) -> io::Result<Recorder> {
+        Ok(Recorder::new(
+            Box::new(BufWriter::new(File::create(&self.path)?)),
+            self.format,
+            self.interval,
+        ))
+    }
+}
+
+/// Writes economy state and build progress to a time series
+pub struct Recorder {
+    writer: Box<dyn Write + Send + Sync>,
+    format: RecordFormat,
+    interval: u64,
+    header_written: bool,
+    /// first write error, recording stops once set
+    error: Option<io::Error>,
+}
+
+impl Recorder {
+    pub fn new(writer: Box<dyn Write + Send + Sync>, format: RecordFormat, interval: u64) -> Self {
+        Recorder {
+            writer,
+            format,
+            interval: interval.max(1),
+            header_written: false,
+            error: None,
+        }
+    }
+
+    /// flush output, returning the first error hit while recording
+    pub fn finish(&mut self) -> io::Result<()> {
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+        self.writer.flush()
+    }
+
+    fn write_csv<'a>(
+        &mut self,
+        tick: u64,
+        economies: &Economies,
+        entities: impl Iterator<Item = (Entity, &'a Damage, Option<&'a UnitId>)>,
+    ) -> io::Result<()> {
+        let writer = &mut self.writer;
+        if !self.header_written {
+            write!(writer, "tick,kind,army")?;
+            for (name, _) in ECONOMY_FIELDS {
+                write!(writer, ",{}", name)?;
+            }
+            writeln!(writer, ",entity,unit,build_progress,health")?;
+            self.header_written = true;
+        }
+        for (army, economy) in economies.iter() {
+            write!(writer, "{},economy,{}", tick, army.0)?;
+            for (_, field) in ECONOMY_FIELDS {
+                write!(writer, ",{}", field(economy))?;
+            }
+            writeln!(writer, ",,,,")?;
+        }
+        let empty_economy = ",".repeat(ECONOMY_FIELDS.len());
+        for (entity, damage, unit_id) in entities {
+            writeln!(
+                writer,
+                "{},entity,{},{},{},{},{}",
+                tick,
+                empty_economy,
+                entity.id(),
+                unit_id.map_or("", |unit_id| unit_id.0.as_str()),
+                damage.build_progress,
+                damage.health
+            )?;
+        }
+        Ok(())
+    }
+
+    fn write_ndjson<'a>(
+        &mut self,
+        tick: u64,
+        economies: &Economies,
+        entities: impl Iterator<Item = (Entity, &'a Damage, Option<&'a UnitId>)>,
+    ) -> io::Result<()> {
+        let writer = &mut self.writer;
+        write!(writer, "{{\"tick\":{},\"economies\":[", tick)?;
+        for (index, (army, economy)) in economies.iter().enumerate() {
+            if index > 0 {
+                write!(writer, ",")?;
+            }
+            write!(writer, "{{\"army\":{}", army.0)?;
+            for (name, field) in ECONOMY_FIELDS {
+                write!(writer, ",\"{}\":{}", name, json_number(field(economy)))?;
+            }
+            write!(writer, "}}")?;
+        }
+        write!(writer, "],\"entities\":[")?;
+        for (index, (entity, damage, unit_id)) in entities.enumerate() {
+            if index > 0 {
+                write!(writer, ",")?;
+            }
+            write!(writer, "{{\"entity\":{},\"unit\":", entity.id())?;
+            match unit_id {
+                Some(unit_id) => write!(writer, "{}", json_string(&unit_id.0))?,
+                None => write!(writer, "null")?,
+            }
+            write!(
+                writer,
+                ",\"build_progress\":{},\"health\":{}}}",
+                json_number(damage.build_progress),
+                json_number(damage.health)
+            )?;
+        }
+        writeln!(writer, "]}}")
+    }
+}
+
+/// JSON has no representation for infinities and NaN
+fn json_number(value: f64) -> String {
+    if value.is_finite() {
+        value.to_string()
+    } else {
+        "null".to_string()
+    }
+}
+
+fn json_string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+/// write the state of this tick if a recorder is present and the tick is due
+pub fn record_state(
+    recorder: Option<ResMut<Recorder>>,
+    current_tick: Res<CurrentTick>,
+    economies: Res<Economies>,
+    query: Query<(Entity, &Damage, Option<&UnitId>)>,
+) {
+    let mut recorder = match recorder {
+        Some(recorder) => recorder,
+        None => return,
+    };
+    if recorder.error.is_some() || !current_tick.0.is_multiple_of(recorder.interval) {
+        return;
+    }
+    let result = match recorder.format {
+        RecordFormat::Csv => recorder.write_csv(current_tick.0, &economies, query.iter()),
+        RecordFormat::Ndjson => recorder.write_ndjson(current_tick.0, &economies, query.iter()),
+    };
+    if let Err(err) = result {
+        recorder.error = Some(err);
+    }
+}
+
+/// start recording the world's state
+pub fn start_recording(world: &mut World, options: &RecordOptions) -> io::Result<()> {
+    world.insert_resource(options.open()?);
+    Ok(())
+}
+
+/// stop recording the world's state, flushing output
+pub fn finish_recording(world: &mut World) -> io::Result<()> {
+    match world.remove_resource::<Recorder>() {
+        Some(mut recorder) => recorder.finish(),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    /// writer whose output stays readable after the recorder takes it
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl SharedBuffer {
+        fn contents(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    /// world with two armies and one unit, recorded into the returned buffer
+    fn recorded_world(format: RecordFormat, interval: u64) -> (World, SharedBuffer) {
+        let buffer = SharedBuffer::default();
+        let mut world = World::new();
+        world.insert_resource(CurrentTick(0));
+        let mut economies = Economies::single(Economy {
+            mass: 100.0,
+            energy: 2000.0,
+            ..Default::default()
+        });
+        economies.get_or_default(Army(1)).mass_capacity = 500.0;
+        world.insert_resource(economies);
+        world.insert_resource(Recorder::new(Box::new(buffer.clone()), format, interval));
+        world
+            .spawn()
+            .insert(UnitId("ueb1103".to_string()))
+            .insert(Damage {
+                build_progress: 0.5,
+                health: 300.0,
+                health_points: 600,
+                mass_total: 36.0,
+                energy_total: 360.0,
+                build_time: 60.0,
+            });
+        (world, buffer)
+    }
+
+    fn record_ticks(world: &mut World, ticks: std::ops::Range<u64>) {
+        let mut stage = SystemStage::single_threaded().with_system(record_state);
+        for tick in ticks {
+            world.resource_mut::<CurrentTick>().0 = tick;
+            stage.run(world);
+        }
+    }
+
+    #[test]
+    fn csv_rows_line_up_with_header() {
+        let (mut world, buffer) = recorded_world(RecordFormat::Csv, 1);
+        record_ticks(&mut world, 0..2);
+        let output = buffer.contents();
+        let mut lines = output.lines();
+        let header: Vec<&str> = lines.next().unwrap().split(',').collect();
+        let column = |name: &str| header.iter().position(|column| *column == name).unwrap();
+        let rows: Vec<Vec<&str>> = lines.map(|line| line.split(',').collect()).collect();
+
+        // header written once, two economies and one entity per tick
+        assert_eq!(rows.len(), 6);
+        assert!(rows.iter().all(|row| row.len() == header.len()));
+        assert!(rows.iter().all(|row| row[column("tick")] != "tick"));
+
+        let economy = &rows[0];
+        assert_eq!(economy[column("kind")], "economy");
+        assert_eq!(economy[column("army")], "0");
+        assert_eq!(economy[column("mass")], "100");
+        assert_eq!(economy[column("energy")], "2000");
+        assert_eq!(economy[column("unit")], "");
+        assert_eq!(rows[1][column("army")], "1");
+        assert_eq!(rows[1][column("mass_capacity")], "500");
+
+        let entity = &rows[2];
+        assert_eq!(entity[column("tick")], "0");
+        assert_eq!(entity[column("kind")], "entity");
+        assert_eq!(entity[column("mass")], "");
+        assert_eq!(entity[column("unit")], "ueb1103");
+        assert_eq!(entity[column("build_progress")], "0.5");
+        assert_eq!(entity[column("health")], "300");
+        assert_eq!(rows[5][column("tick")], "1");
+    }
+
+    #[test]
+    fn ndjson_writes_non_finite_numbers_as_null() {
+        let (mut world, buffer) = recorded_world(RecordFormat::Ndjson, 1);
+        {
+            let mut economies = world.resource_mut::<Economies>();
+            let economy = economies.get_or_default(Army(0));
+            economy.mass_stall = f64::NAN;
+            economy.energy = f64::INFINITY;
+        }
+        world
+            .spawn()
+            .insert(UnitId("quoted \"id\"".to_string()))
+            .insert(Damage {
+                build_progress: f64::NEG_INFINITY,
+                health: 0.0,
+                health_points: 1,
+                mass_total: 1.0,
+                energy_total: 1.0,
+                build_time: 1.0,
+            });
+        record_ticks(&mut world, 0..1);
+        let output = buffer.contents();
+
+        assert_eq!(output.lines().count(), 1);
+        assert!(output.starts_with("{\"tick\":0,\"economies\":[{\"army\":0,\"mass\":100,"));
+        assert!(output.contains("\"energy\":null,"));
+        assert!(output.contains("\"mass_stall\":null,"));
+        assert!(output.contains("\"unit\":\"ueb1103\",\"build_progress\":0.5,\"health\":300}"));
+        assert!(output.contains("\"unit\":\"quoted \\\"id\\\"\",\"build_progress\":null,"));
+        assert!(!output.contains("NaN"));
+        assert!(!output.contains("inf"));
+        assert!(output.ends_with("]}\n"));
+    }
+
+    #[test]
+    fn records_every_interval_ticks() {
+        let (mut world, buffer) = recorded_world(RecordFormat::Ndjson, 3);
+        record_ticks(&mut world, 0..8);
+        let output = buffer.contents();
+        let ticks: Vec<&str> = output
+            .lines()
+            .map(|line| line.split(',').next().unwrap())
+            .collect();
+        assert_eq!(ticks, vec!["{\"tick\":0", "{\"tick\":3", "{\"tick\":6"]);
+    }
+}
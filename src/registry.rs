@@ -0,0 +1,348 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::Arc;
+
+use bevy_ecs::prelude::*;
+use bevy_ecs::system::{CommandQueue, EntityCommands};
+use serde::{Deserialize, Serialize};
+
+use crate::adjacency::*;
+use crate::blueprint::*;
+use crate::fabricator::MassFabricator;
+use crate::simulation::*;
+
+/// Identifies the blueprint an entity was spawned from
+#[derive(Component, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UnitId(pub String);
+
+/// Inserts unit-specific components onto a spawned entity
+pub type InsertExtra = Arc<dyn Fn(&mut EntityCommands) + Send + Sync>;
+
+/// Unit type which can be spawned by the registry
+#[derive(Clone)]
+pub struct UnitDefinition {
+    pub blueprint: Blueprint,
+    /// inserts unit-specific components not described by the blueprint
+    pub insert_extra: Option<InsertExtra>,
+}
+
+/// Registry of spawnable unit types, keyed by blueprint id
+///
+/// Definitions are shared between clones, so cloning is cheap until a clone
+/// registers more units.
+#[derive(Clone, Default)]
+pub struct UnitRegistry {
+    units: Arc<HashMap<String, UnitDefinition>>,
+}
+
+impl UnitRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// register unit type with only blueprint-derived components
+    pub fn register(&mut self, blueprint: Blueprint) {
+        Arc::make_mut(&mut self.units).insert(
+            blueprint.id.clone(),
+            UnitDefinition {
+                blueprint,
+                insert_extra: None,
+            },
+        );
+    }
+
+    /// register unit type with additional components
+    pub fn register_with(
+        &mut self,
+        blueprint: Blueprint,
+        insert_extra: impl Fn(&mut EntityCommands) + Send + Sync + 'static,
+    ) {
+        Arc::make_mut(&mut self.units).insert(
+            blueprint.id.clone(),
+            UnitDefinition {
+                blueprint,
+                insert_extra: Some(Arc::new(insert_extra)),
+            },
+        );
+    }
+
+    /// register all *.bp files in a directory, returns number of blueprints loaded
+    ///
+    /// Blueprints replace registered units with the same id, but two files in
+    /// the directory resolving to the same id are an error.
+    pub fn load_directory(&mut self, path: impl AsRef<Path>) -> Result<usize, BlueprintError> {
+        let mut ids = HashSet::new();
+        for dir_entry in std::fs::read_dir(path)? {
+            let path = dir_entry?.path();
+            if path.extension().is_some_and(|extension| extension == "bp") {
+                let blueprint = Blueprint::load(&path)?;
+                if !ids.insert(blueprint.id.clone()) {
+                    return Err(BlueprintError::DuplicateId(blueprint.id));
+                }
+                self.register(blueprint);
+            }
+        }
+        Ok(ids.len())
+    }
+
+    pub fn get(&self, id: &str) -> Option<&UnitDefinition> {
+        self.units.get(id)
+    }
+
+    pub fn blueprint(&self, id: &str) -> Option<&Blueprint> {
+        self.get(id).map(|definition| &definition.blueprint)
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        self.units.contains_key(id)
+    }
+
+    pub fn ids(&self) -> impl Iterator<Item = &str> {
+        self.units.keys().map(|id| id.as_str())
+    }
+
+    /// insert all components for a unit type onto an existing entity
+    pub fn insert_components(&self, entity_commands: &mut EntityCommands, id: &str) -> bool {
+        let definition = match self.get(id) {
+            Some(definition) => definition,
+            None => return false,
+        };
+        let blueprint = &definition.blueprint;
+        entity_commands
+            .insert(UnitId(blueprint.id.clone()))
+            .insert(blueprint.damage());
+        if let Some(engineering) = blueprint.engineering() {
+            entity_commands.insert(engineering);
+        }
+        if let Some(mass_fabricator) = blueprint.mass_fabricator() {
+            entity_commands.insert(mass_fabricator);
+        }
+        if blueprint.is_mass_fabricator() {
+            // fabricators only run on surplus energy
+            entity_commands.insert(ResourceConsumer {
+                priority: ConsumerPriority::Low,
+                ..Default::default()
+            });
+        } else if blueprint.build_rate > 0.0 {
+            entity_commands.insert(ResourceConsumer::default());
+        }
+        if let Some(resource_producer) = blueprint.resource_producer() {
+            entity_commands.insert(resource_producer);
+        }
+        if let (Some(category), Some((size_x, size_z))) = (
+            AdjacencyCategory::from_blueprint(blueprint),
+            blueprint.footprint_size(),
+        ) {
+            entity_commands
+                .insert(category)
+                .insert(Footprint { size_x, size_z })
+                .insert(AdjacencyBonus::default());
+        }
+        if let Some(insert_extra) = &definition.insert_extra {
+            insert_extra(entity_commands);
+        }
+        true
+    }
+
+    /// spawn an unbuilt unit which will begin executing once constructed
+    pub fn spawn(&self, commands: &mut Commands, id: &str, army: Army) -> Option<Entity> {
+        if !self.contains(id) {
+            return None;
+        }
+        let mut entity_commands = commands.spawn();
+        self.insert_components(&mut entity_commands, id);
+        entity_commands.insert(army).insert(WillExecuteOnConstruct);
+        Some(entity_commands.id())
+    }
+}
+
+/// Remove components inserted from a blueprint by UnitRegistry::insert_components
+/// (components from `insert_extra` are left alone)
+pub fn remove_blueprint_components(entity_commands: &mut EntityCommands) {
+    entity_commands.remove_bundle::<(
+        UnitId,
+        Damage,
+        Engineering,
+        MassFabricator,
+        ResourceConsumer,
+        ResourceProducer,
+        AdjacencyCategory,
+        Footprint,
+        AdjacencyBonus,
+    )>();
+}
+
+/// Spawn an unbuilt unit directly into a world containing a UnitRegistry
+pub fn spawn_unit(world: &mut World, id: &str, army: Army) -> Option<Entity> {
+    world.resource_scope(|world, registry: Mut<UnitRegistry>| {
+        let mut command_queue = CommandQueue::default();
+        let entity = registry.spawn(&mut Commands::new(&mut command_queue, world), id, army);
+        command_queue.apply(world);
+        entity
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::tests::test_simulation;
+
+    const SAMPLE_BLUEPRINTS: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/blueprints");
+
+    /// empty directory in the system temp directory, unique to this process and test
+    fn temp_directory(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("registry-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        path
+    }
+
+    fn copy_blueprint(directory: &Path, file_name: &str, target_name: &str) {
+        std::fs::copy(
+            Path::new(SAMPLE_BLUEPRINTS).join(file_name),
+            directory.join(target_name),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn load_directory_registers_blueprints() {
+        let mut registry = UnitRegistry::new();
+        assert_eq!(registry.load_directory(SAMPLE_BLUEPRINTS).unwrap(), 7);
+        let mut ids: Vec<&str> = registry.ids().collect();
+        ids.sort_unstable();
+        assert_eq!(
+            ids,
+            [
+                "ueb1103",
+                "ueb1104",
+                "ueb1202",
+                "ueb1302",
+                "ueb1303",
+                "uel0301_RAS",
+                "xsb2401"
+            ]
+        );
+        assert_eq!(registry.blueprint("ueb1202").unwrap().build_time, 900.0);
+    }
+
+    #[test]
+    fn load_directory_skips_other_files_and_rejects_invalid_blueprints() {
+        let directory = temp_directory("invalid");
+        copy_blueprint(&directory, "ueb1103_unit.bp", "ueb1103_unit.bp");
+        std::fs::write(directory.join("notes.txt"), "not a blueprint").unwrap();
+        let mut registry = UnitRegistry::new();
+        assert_eq!(registry.load_directory(&directory).unwrap(), 1);
+
+        std::fs::write(
+            directory.join("broken_unit.bp"),
+            "UnitBlueprint { Economy = ",
+        )
+        .unwrap();
+        let result = UnitRegistry::new().load_directory(&directory);
+        assert!(matches!(result, Err(BlueprintError::Parse { .. })));
+        assert!(matches!(
+            UnitRegistry::new().load_directory(directory.join("missing")),
+            Err(BlueprintError::Io(_))
+        ));
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn duplicate_ids() {
+        // file names with and without the _unit suffix resolve to the same id
+        let directory = temp_directory("duplicate");
+        copy_blueprint(&directory, "ueb1103_unit.bp", "ueb1103_unit.bp");
+        copy_blueprint(&directory, "ueb1202_unit.bp", "ueb1103.bp");
+        let result = UnitRegistry::new().load_directory(&directory);
+        assert!(matches!(result, Err(BlueprintError::DuplicateId(id)) if id == "ueb1103"));
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        // registering again replaces the definition
+        let mut registry = UnitRegistry::new();
+        registry.load_directory(SAMPLE_BLUEPRINTS).unwrap();
+        let mut blueprint = registry.blueprint("ueb1202").unwrap().clone();
+        blueprint.id = "ueb1103".to_string();
+        registry.register(blueprint);
+        assert_eq!(registry.ids().count(), 7);
+        assert_eq!(registry.blueprint("ueb1103").unwrap().build_time, 900.0);
+    }
+
+    #[test]
+    fn spawn_inserts_blueprint_components() {
+        let mut sim = test_simulation();
+        let sacu = sim.spawn_unit("uel0301_RAS", Army(2)).unwrap();
+        let entity = sim.world.entity(sacu);
+        assert_eq!(entity.get::<UnitId>().unwrap().0, "uel0301_RAS");
+        assert_eq!(entity.get::<Army>(), Some(&Army(2)));
+        assert!(entity.contains::<WillExecuteOnConstruct>());
+        assert!(!entity.contains::<Executing>());
+        assert_eq!(entity.get::<Damage>().unwrap().build_progress, 0.0);
+        assert!(entity.contains::<Engineering>());
+        assert!(entity.contains::<ResourceProducer>());
+        let consumer = entity.get::<ResourceConsumer>().unwrap();
+        assert_eq!(consumer.priority, ConsumerPriority::Normal);
+        assert!(!entity.contains::<Footprint>());
+
+        let fabricator = sim.spawn_unit("ueb1303", Army(0)).unwrap();
+        let entity = sim.world.entity(fabricator);
+        assert!(entity.contains::<MassFabricator>());
+        let consumer = entity.get::<ResourceConsumer>().unwrap();
+        assert_eq!(consumer.priority, ConsumerPriority::Low);
+        assert!(entity.contains::<Footprint>());
+        assert!(entity.contains::<AdjacencyBonus>());
+    }
+
+    #[test]
+    fn unknown_id_spawns_nothing() {
+        let mut sim = test_simulation();
+        let entities = sim.world.entities().len();
+        assert_eq!(sim.spawn_unit("unknown", Army(0)), None);
+        assert_eq!(sim.world.entities().len(), entities);
+
+        let entity = sim.world.spawn().id();
+        let registry = sim.world.resource::<UnitRegistry>().clone();
+        let mut command_queue = CommandQueue::default();
+        let mut commands = Commands::new(&mut command_queue, &sim.world);
+        assert!(!registry.insert_components(&mut commands.entity(entity), "unknown"));
+        command_queue.apply(&mut sim.world);
+        assert!(!sim.world.entity(entity).contains::<UnitId>());
+    }
+
+    #[test]
+    fn insert_and_remove_blueprint_components() {
+        #[derive(Component)]
+        struct Extra;
+
+        let mut sim = test_simulation();
+        let mut registry = sim.world.resource::<UnitRegistry>().clone();
+        let blueprint = registry.blueprint("ueb1103").unwrap().clone();
+        registry.register_with(blueprint, |entity_commands| {
+            entity_commands.insert(Extra);
+        });
+        let entity = sim.world.spawn().insert(Executing).id();
+        let mut command_queue = CommandQueue::default();
+        let mut commands = Commands::new(&mut command_queue, &sim.world);
+        let mut entity_commands = commands.entity(entity);
+        assert!(registry.insert_components(&mut entity_commands, "ueb1103"));
+        command_queue.apply(&mut sim.world);
+        let entity_ref = sim.world.entity(entity);
+        assert_eq!(entity_ref.get::<UnitId>().unwrap().0, "ueb1103");
+        assert!(entity_ref.contains::<ResourceProducer>());
+        assert!(entity_ref.contains::<Footprint>());
+        assert!(entity_ref.contains::<Extra>());
+
+        let mut commands = Commands::new(&mut command_queue, &sim.world);
+        remove_blueprint_components(&mut commands.entity(entity));
+        command_queue.apply(&mut sim.world);
+        let entity_ref = sim.world.entity(entity);
+        assert!(!entity_ref.contains::<UnitId>());
+        assert!(!entity_ref.contains::<Damage>());
+        assert!(!entity_ref.contains::<ResourceProducer>());
+        assert!(!entity_ref.contains::<Footprint>());
+        assert!(!entity_ref.contains::<AdjacencyBonus>());
+        // not inserted from the blueprint
+        assert!(entity_ref.contains::<Extra>());
+        assert!(entity_ref.contains::<Executing>());
+    }
+}
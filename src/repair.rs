@@ -0,0 +1,170 @@
+use bevy_ecs::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::simulation::*;
+
+/// fraction of build cost charged to repair the same fraction of health
+pub const REPAIR_COST_RATIO: f64 = 0.75;
+
+/// Entity is repairing a damaged finished entity
+#[derive(Component, Clone, Debug, Serialize, Deserialize)]
+pub struct Repairing {
+    /// entity being repaired
+    #[serde(with = "crate::snapshot::entity_bits")]
+    pub target: Entity,
+    /// mass requested for repair
+    pub mass_requested: f64,
+    /// energy requested for repair
+    pub energy_requested: f64,
+    /// proportion of health that would be restored this tick if no stall
+    pub repair_amount: f64,
+}
+
+impl Repairing {
+    pub fn new(target: Entity) -> Self {
+        Repairing {
+            target,
+            mass_requested: 0.0,
+            energy_requested: 0.0,
+            repair_amount: 0.0,
+        }
+    }
+}
+
+/// request resources for repairs
+#[allow(clippy::type_complexity)]
+pub fn do_repair_resources_request(
+    mut query: Query<
+        (Entity, &mut Repairing, &Engineering, &mut ResourceConsumer),
+        (With<Executing>, Without<ConstructionPaused>),
+    >,
+    target_query: Query<&Damage>,
+    mut commands: Commands,
+) {
+    for (entity, mut repairing, engineering, mut resource_consumer) in &mut query {
+        let target_damage = match target_query.get(repairing.target) {
+            Ok(target_damage) if target_damage.is_damaged() => target_damage,
+            _ => {
+                // target gone, unfinished or fully repaired
+                commands.entity(entity).remove::<Repairing>();
+                continue;
+            }
+        };
+        // health is restored at the same rate it grows during construction
+        let repair_amount = f64::min(
+            engineering.build_rate / target_damage.build_time,
+            1.0 - target_damage.health_fraction(),
+        );
+        repairing.repair_amount = repair_amount;
+        repairing.mass_requested = repair_amount * target_damage.mass_total * REPAIR_COST_RATIO;
+        repairing.energy_requested = repair_amount * target_damage.energy_total * REPAIR_COST_RATIO;
+        resource_consumer.mass_request += repairing.mass_requested;
+        resource_consumer.energy_request += repairing.energy_requested;
+    }
+}
+
+/// restore health of repair targets with the resources allocated
+#[allow(clippy::type_complexity)]
+pub fn do_repair(
+    mut query: Query<
+        (Entity, &Army, &Repairing, &mut ResourceConsumer),
+        (With<Executing>, Without<ConstructionPaused>),
+    >,
+    mut target_query: Query<&mut Damage>,
+    mut economies: ResMut<Economies>,
+    mut commands: Commands,
+) {
+    for (entity, army, repairing, mut resource_consumer) in &mut query {
+        let economy = economies.get_or_default(*army);
+        let mut target_damage = match target_query.get_mut(repairing.target) {
+            Ok(target_damage) if target_damage.is_damaged() => target_damage,
+            _ => {
+                commands.entity(entity).remove::<Repairing>();
+                continue;
+            }
+        };
+        // cost of restoring full health
+        let mass_cost = target_damage.mass_total * REPAIR_COST_RATIO;
+        let energy_cost = target_damage.energy_total * REPAIR_COST_RATIO;
+        // determine resource bottleneck, never exceeding what is left in storage
+        let mass_available = f64::min(
+            repairing.mass_requested * economy.mass_stall_for(resource_consumer.priority),
+            f64::max(0.0, economy.mass),
+        );
+        let energy_available = f64::min(
+            repairing.energy_requested * economy.energy_stall_for(resource_consumer.priority),
+            f64::max(0.0, economy.energy),
+        );
+        let min_portion = f64::min(
+            repairing.repair_amount,
+            f64::min(mass_available / mass_cost, energy_available / energy_cost),
+        );
+        // clamp to missing health
+        let missing = 1.0 - target_damage.health_fraction();
+        let finished = min_portion >= missing;
+        let portion = if finished { missing } else { min_portion };
+
+        // pull resources actually used
+        resource_consumer.mass_consumed += economy.pull_mass(portion * mass_cost);
+        resource_consumer.energy_consumed += economy.pull_energy(portion * energy_cost);
+
+        if finished {
+            target_damage.health = target_damage.health_points as f64;
+            commands.entity(entity).remove::<Repairing>();
+        } else {
+            target_damage.health += portion * target_damage.health_points as f64;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::tests::*;
+
+    #[test]
+    fn repair_costs_fraction_of_build_cost() {
+        let mut sim = test_simulation();
+        set_stored(&mut sim, Army(0), 1000.0, 10000.0);
+        let target = spawn_built(&mut sim, "ueb1202", Army(0));
+        sim.world.entity_mut(target).remove::<Executing>();
+        sim.world.get_mut::<Damage>(target).unwrap().health = 750.0;
+        let engineer = spawn_builder(&mut sim, Army(0), 30.0);
+        sim.world
+            .entity_mut(engineer)
+            .insert(Repairing::new(target));
+        for _ in 0..400 {
+            sim.run();
+            if !sim.world.entity(engineer).contains::<Repairing>() {
+                break;
+            }
+        }
+        assert!(!sim.world.entity(engineer).contains::<Repairing>());
+        assert_eq!(sim.world.get::<Damage>(target).unwrap().health, 1500.0);
+
+        // half of the health costs half of the build cost times the repair ratio
+        let consumer = sim.world.get::<ResourceConsumer>(engineer).unwrap();
+        let mass = 0.5 * 900.0 * REPAIR_COST_RATIO;
+        let energy = 0.5 * 5400.0 * REPAIR_COST_RATIO;
+        assert!((consumer.total_mass_consumed - mass).abs() < 1e-6);
+        assert!((consumer.total_energy_consumed - energy).abs() < 1e-6);
+        let economy = sim.economy(Army(0)).unwrap();
+        assert!((economy.mass - (1000.0 - mass)).abs() < 1e-6);
+        assert!((economy.energy - (10000.0 - energy)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn unfinished_targets_are_not_repaired() {
+        let mut sim = test_simulation();
+        set_stored(&mut sim, Army(0), 1000.0, 10000.0);
+        let target = sim.spawn_unit("ueb1202", Army(0)).unwrap();
+        let engineer = spawn_builder(&mut sim, Army(0), 30.0);
+        sim.world
+            .entity_mut(engineer)
+            .insert(Repairing::new(target));
+        sim.run();
+        assert!(!sim.world.entity(engineer).contains::<Repairing>());
+        assert_eq!(sim.world.get::<Damage>(target).unwrap().health, 0.0);
+        assert_eq!(sim.economy(Army(0)).unwrap().mass, 1000.0);
+    }
+}
@@ -0,0 +1,599 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::marker::PhantomData;
+use std::path::Path;
+
+use bevy_ecs::prelude::*;
+use bevy_ecs::world::EntityMut;
+use serde::{Deserialize, Serialize};
+
+use crate::adjacency::GridPosition;
+use crate::reclaim::Reclaiming;
+use crate::recorder::{finish_recording, start_recording, RecordOptions};
+use crate::registry::spawn_unit;
+use crate::repair::Repairing;
+use crate::simulation::*;
+use crate::snapshot::*;
+use crate::trigger::{add_trigger, evaluate_triggers, Condition, Triggers};
+use crate::upgrade::Upgrading;
+use crate::Sacrificing;
+
+/// Change to the world made from outside the update schedule
+///
+/// Orders naming entities which no longer exist skip them.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "order", rename_all = "kebab-case")]
+pub enum Order {
+    /// spawn an unbuilt unit by blueprint id, placed on the build grid if a
+    /// position is given
+    SpawnUnit {
+        unit_id: String,
+        army: Army,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        position: Option<GridPosition>,
+    },
+    /// start constructing target, dropping any assist
+    Construct {
+        #[serde(with = "crate::snapshot::entity_vec_bits")]
+        entities: Vec<Entity>,
+        #[serde(with = "crate::snapshot::entity_bits")]
+        target: Entity,
+    },
+    /// assist target
+    Assist {
+        #[serde(with = "crate::snapshot::entity_vec_bits")]
+        entities: Vec<Entity>,
+        #[serde(with = "crate::snapshot::entity_bits")]
+        target: Entity,
+    },
+    /// pause construction
+    Pause {
+        #[serde(with = "crate::snapshot::entity_vec_bits")]
+        entities: Vec<Entity>,
+    },
+    /// resume construction
+    Resume {
+        #[serde(with = "crate::snapshot::entity_vec_bits")]
+        entities: Vec<Entity>,
+    },
+    /// stop executing
+    Stop {
+        #[serde(with = "crate::snapshot::entity_vec_bits")]
+        entities: Vec<Entity>,
+    },
+    /// start executing
+    Start {
+        #[serde(with = "crate::snapshot::entity_vec_bits")]
+        entities: Vec<Entity>,
+    },
+    /// stop constructing and sacrifice into target
+    Sacrifice {
+        #[serde(with = "crate::snapshot::entity_vec_bits")]
+        entities: Vec<Entity>,
+        #[serde(with = "crate::snapshot::entity_bits")]
+        target: Entity,
+    },
+    /// upgrade into another blueprint id
+    Upgrade {
+        #[serde(with = "crate::snapshot::entity_vec_bits")]
+        entities: Vec<Entity>,
+        unit_id: String,
+    },
+    /// reclaim target
+    Reclaim {
+        #[serde(with = "crate::snapshot::entity_vec_bits")]
+        entities: Vec<Entity>,
+        #[serde(with = "crate::snapshot::entity_bits")]
+        target: Entity,
+    },
+    /// repair target
+    Repair {
+        #[serde(with = "crate::snapshot::entity_vec_bits")]
+        entities: Vec<Entity>,
+        #[serde(with = "crate::snapshot::entity_bits")]
+        target: Entity,
+    },
+    /// destroy, leaving wrecks
+    Destroy {
+        #[serde(with = "crate::snapshot::entity_vec_bits")]
+        entities: Vec<Entity>,
+    },
+}
+
+impl Order {
+    /// replace each entity the order names
+    fn map_entities(&mut self, map: impl Fn(Entity) -> Entity) {
+        let (entities, target) = match self {
+            Order::SpawnUnit { .. } => return,
+            Order::Construct { entities, target }
+            | Order::Assist { entities, target }
+            | Order::Sacrifice { entities, target }
+            | Order::Reclaim { entities, target }
+            | Order::Repair { entities, target } => (entities, Some(target)),
+            Order::Pause { entities }
+            | Order::Resume { entities }
+            | Order::Stop { entities }
+            | Order::Start { entities }
+            | Order::Upgrade { entities, .. }
+            | Order::Destroy { entities } => (entities, None),
+        };
+        for entity in entities.iter_mut() {
+            *entity = map(*entity);
+        }
+        if let Some(target) = target {
+            *target = map(*target);
+        }
+    }
+}
+
+/// Order and the tick it was issued at
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LoggedOrder {
+    pub tick: u64,
+    #[serde(flatten)]
+    pub order: Order,
+    /// entity spawned by the order, which later orders name it by
+    ///
+    /// Replays resolve it to the entity the replayed order spawned, whatever
+    /// id that has.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "crate::snapshot::option_entity_bits"
+    )]
+    pub spawned: Option<Entity>,
+}
+
+/// Orders issued since logging started, present while a simulation is logged
+pub struct OrderLog {
+    /// state when logging started
+    start: Snapshot,
+    orders: Vec<LoggedOrder>,
+}
+
+impl OrderLog {
+    /// independent copy of the log, replaying to a fork of the logged
+    /// simulation
+    pub(crate) fn fork<S: Snapshots>(&self) -> OrderLog {
+        let mut start = World::new();
+        copy_world(
+            &self.start.world,
+            &mut start,
+            &S::components(),
+            &S::resources(),
+        );
+        OrderLog {
+            start: Snapshot { world: start },
+            orders: self.orders.clone(),
+        }
+    }
+}
+
+/// apply an order to the world, logging it if orders are being logged,
+/// returns the entity spawned by the order
+pub fn issue(world: &mut World, order: Order) -> Option<Entity> {
+    let spawned = apply(world, &order);
+    let tick = world.resource::<CurrentTick>().0;
+    if let Some(mut log) = world.get_resource_mut::<OrderLog>() {
+        log.orders.push(LoggedOrder {
+            tick,
+            order,
+            spawned,
+        });
+    }
+    spawned
+}
+
+fn apply(world: &mut World, order: &Order) -> Option<Entity> {
+    let each = |world: &mut World, entities: &[Entity], f: &dyn Fn(&mut EntityMut)| {
+        for entity in entities {
+            if let Some(mut entity_mut) = world.get_entity_mut(*entity) {
+                f(&mut entity_mut);
+            }
+        }
+    };
+    match order {
+        Order::SpawnUnit {
+            unit_id,
+            army,
+            position,
+        } => {
+            let spawned = spawn_unit(world, unit_id, *army);
+            if let (Some(spawned), Some(position)) = (spawned, position) {
+                world.entity_mut(spawned).insert(*position);
+            }
+            return spawned;
+        }
+        Order::Construct { entities, target } => each(world, entities, &|entity| {
+            entity.remove::<Assisting>();
+            entity.insert(Constructing::new(*target));
+        }),
+        Order::Assist { entities, target } => each(world, entities, &|entity| {
+            entity.insert(Assisting { target: *target });
+        }),
+        Order::Pause { entities } => each(world, entities, &|entity| {
+            entity.insert(ConstructionPaused);
+        }),
+        Order::Resume { entities } => each(world, entities, &|entity| {
+            entity.remove::<ConstructionPaused>();
+        }),
+        Order::Stop { entities } => each(world, entities, &|entity| {
+            entity.remove::<Executing>();
+        }),
+        Order::Start { entities } => each(world, entities, &|entity| {
+            entity.insert(Executing);
+        }),
+        Order::Sacrifice { entities, target } => each(world, entities, &|entity| {
+            entity.remove::<Constructing>();
+            entity.insert(Sacrificing { target: *target });
+        }),
+        Order::Upgrade { entities, unit_id } => each(world, entities, &|entity| {
+            entity.insert(Upgrading::new(unit_id.clone()));
+        }),
+        Order::Reclaim { entities, target } => each(world, entities, &|entity| {
+            entity.insert(Reclaiming { target: *target });
+        }),
+        Order::Repair { entities, target } => each(world, entities, &|entity| {
+            entity.insert(Repairing::new(*target));
+        }),
+        Order::Destroy { entities } => each(world, entities, &|entity| {
+            entity.insert(Destroyed);
+        }),
+    }
+    None
+}
+
+/// log orders issued to the simulation from its current state on
+///
+/// The simulation continues from a copy of its state laid out like the one
+/// replays start from, so entities spawned by systems get the same ids when
+/// replayed.
+pub fn start_order_log<S: Snapshots>(sim: &mut S) {
+    let mut start = sim.snapshot();
+    sim.restore(&start);
+    // orders issued by triggers are logged and replayed without them
+    start.world.insert_resource(Triggers::default());
+    sim.world_mut().insert_resource(OrderLog {
+        start,
+        orders: Vec::new(),
+    });
+}
+
+/// stop logging orders, returning a replay of the run since logging started
+pub fn finish_order_log<S: Snapshots>(sim: &mut S) -> Option<Replay<S>> {
+    let log = sim.world_mut().remove_resource::<OrderLog>()?;
+    Some(Replay {
+        start: log.start,
+        orders: log.orders,
+        end_tick: sim.world().resource::<CurrentTick>().0,
+        simulation: PhantomData,
+    })
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(io::Error),
+    Serialize(toml::ser::Error),
+    Parse(toml::de::Error),
+    Snapshot(SnapshotError),
+    /// replay file was logged in another kind of simulation
+    WrongSimulation(SimulationKind),
+    /// order spawned an entity when it didn't when logged, or the other way
+    /// round
+    Diverged {
+        tick: u64,
+        order: usize,
+    },
+    /// failed to write the recording
+    Record(io::Error),
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplayError::Io(err) => write!(f, "failed to access replay: {}", err),
+            ReplayError::Serialize(err) => write!(f, "failed to write replay: {}", err),
+            ReplayError::Parse(err) => write!(f, "invalid replay: {}", err),
+            ReplayError::Snapshot(err) => write!(f, "{}", err),
+            ReplayError::WrongSimulation(kind) => {
+                write!(f, "replay was logged in a {:?} simulation", kind)
+            }
+            ReplayError::Diverged { tick, order } => {
+                write!(f, "replay diverged at tick {} (order {})", tick, order)
+            }
+            ReplayError::Record(err) => write!(f, "failed to write recording: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+impl From<io::Error> for ReplayError {
+    fn from(err: io::Error) -> Self {
+        ReplayError::Io(err)
+    }
+}
+
+impl From<toml::ser::Error> for ReplayError {
+    fn from(err: toml::ser::Error) -> Self {
+        ReplayError::Serialize(err)
+    }
+}
+
+impl From<toml::de::Error> for ReplayError {
+    fn from(err: toml::de::Error) -> Self {
+        ReplayError::Parse(err)
+    }
+}
+
+impl From<SnapshotError> for ReplayError {
+    fn from(err: SnapshotError) -> Self {
+        ReplayError::Snapshot(err)
+    }
+}
+
+/// Starting state and logged orders of a run, reproducing it when replayed
+pub struct Replay<S> {
+    pub start: Snapshot,
+    /// in the order they were issued
+    pub orders: Vec<LoggedOrder>,
+    /// tick logging stopped at
+    pub end_tick: u64,
+    simulation: PhantomData<fn() -> S>,
+}
+
+/// First order of a replay which didn't spawn an entity as logged
+struct Divergence {
+    tick: u64,
+    order: usize,
+}
+
+/// Entities spawned by replayed orders, by the entity their logged order
+/// spawned
+#[derive(Default)]
+struct SpawnedEntities(HashMap<Entity, Entity>);
+
+/// Contents of a replay file
+#[derive(Serialize, Deserialize)]
+struct ReplayFile {
+    simulation: SimulationKind,
+    end_tick: u64,
+    start: SnapshotFile,
+    #[serde(default)]
+    orders: Vec<LoggedOrder>,
+}
+
+/// kind of simulation a replay file was logged in
+pub fn replay_kind(source: &str) -> Result<SimulationKind, ReplayError> {
+    #[derive(Deserialize)]
+    struct Header {
+        simulation: SimulationKind,
+    }
+    Ok(toml::from_str::<Header>(source)?.simulation)
+}
+
+impl<S: Snapshots> Replay<S> {
+    /// rerun from the starting state, issuing each order at the tick it was
+    /// logged at, until the tick logging stopped at
+    ///
+    /// Orders are issued by triggers, at the same point in the tick they were
+    /// logged at.
+    pub fn run(&self, record: Option<&RecordOptions>) -> Result<S, ReplayError> {
+        let mut sim = S::from_world(World::new());
+        sim.restore(&self.start);
+        sim.world_mut().insert_resource(SpawnedEntities::default());
+        if let Some(record) = record {
+            start_recording(sim.world_mut(), record).map_err(ReplayError::Record)?;
+        }
+        let mut first = 0;
+        for chunk in self.orders.chunk_by(|a, b| a.tick == b.tick) {
+            let (tick, orders, offset) = (chunk[0].tick, chunk.to_vec(), first);
+            first += chunk.len();
+            add_trigger(
+                sim.world_mut(),
+                format!("replay orders {}..{}", offset, first),
+                Condition::tick_reached(tick),
+                move |world| {
+                    for (index, logged) in orders.iter().enumerate() {
+                        let mut order = logged.order.clone();
+                        let spawned_entities = &world.resource::<SpawnedEntities>().0;
+                        order.map_entities(|entity| {
+                            spawned_entities.get(&entity).copied().unwrap_or(entity)
+                        });
+                        match (logged.spawned, issue(world, order)) {
+                            (Some(logged), Some(spawned)) => {
+                                world
+                                    .resource_mut::<SpawnedEntities>()
+                                    .0
+                                    .insert(logged, spawned);
+                            }
+                            (None, None) => {}
+                            _ if world.contains_resource::<Divergence>() => {}
+                            _ => {
+                                let tick = world.resource::<CurrentTick>().0;
+                                world.insert_resource(Divergence {
+                                    tick,
+                                    order: offset + index,
+                                });
+                            }
+                        }
+                    }
+                },
+            );
+        }
+        // orders issued before the first tick
+        evaluate_triggers(sim.world_mut());
+        loop {
+            if let Some(Divergence { tick, order }) = sim.world_mut().remove_resource() {
+                return Err(ReplayError::Diverged { tick, order });
+            }
+            if sim.world().resource::<CurrentTick>().0 >= self.end_tick {
+                break;
+            }
+            sim.run();
+        }
+        finish_recording(sim.world_mut()).map_err(ReplayError::Record)?;
+        Ok(sim)
+    }
+
+    /// write the replay to a file
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), ReplayError> {
+        let file = ReplayFile {
+            simulation: S::kind(),
+            end_tick: self.end_tick,
+            start: SnapshotFile::new(&self.start.world, &S::components(), &S::resources())?,
+            orders: self.orders.clone(),
+        };
+        fs::write(path, toml::to_string(&file)?)?;
+        Ok(())
+    }
+}
+
+impl<S: Snapshots + Default> Replay<S> {
+    /// read a replay, with the log handler and units registered in a new
+    /// simulation
+    pub fn parse(source: &str) -> Result<Self, ReplayError> {
+        let file: ReplayFile = toml::from_str(source)?;
+        if file.simulation != S::kind() {
+            return Err(ReplayError::WrongSimulation(file.simulation));
+        }
+        let base = S::default();
+        Ok(Replay {
+            start: file
+                .start
+                .into_snapshot(base.world(), &S::components(), &S::resources())?,
+            orders: file.orders,
+            end_tick: file.end_tick,
+            simulation: PhantomData,
+        })
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ReplayError> {
+        Replay::parse(&fs::read_to_string(path)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry::UnitId;
+    use crate::simulation::tests::*;
+
+    #[test]
+    fn orders_name_spawned_entities_by_logged_id() {
+        let mut sim = test_simulation();
+        let builder = spawn_builder(&mut sim, Army(0), 10.0);
+        // logged in a run where ids were allocated differently
+        let logged = Entity::from_raw(1000);
+        let replay = Replay::<FASimulation> {
+            start: sim.snapshot(),
+            orders: vec![
+                LoggedOrder {
+                    tick: 1,
+                    order: Order::SpawnUnit {
+                        unit_id: "ueb1103".to_string(),
+                        army: Army(0),
+                        position: None,
+                    },
+                    spawned: Some(logged),
+                },
+                LoggedOrder {
+                    tick: 1,
+                    order: Order::Construct {
+                        entities: vec![builder],
+                        target: logged,
+                    },
+                    spawned: None,
+                },
+            ],
+            end_tick: 2,
+            simulation: PhantomData,
+        };
+        let replayed = replay.run(None).unwrap();
+        let target = replayed.world.get::<Constructing>(builder).unwrap().target;
+        assert_ne!(target, logged);
+        assert_eq!(replayed.world.get::<UnitId>(target).unwrap().0, "ueb1103");
+    }
+
+    /// simulation with a builder and enough stored resources to build
+    fn logged_simulation() -> (FASimulation, Entity) {
+        let mut sim = test_simulation();
+        set_stored(&mut sim, Army(0), 2000.0, 20000.0);
+        let builder = spawn_builder(&mut sim, Army(0), 10.0);
+        start_order_log(&mut sim);
+        (sim, builder)
+    }
+
+    /// spawn a unit and build it with builder
+    fn build(sim: &mut FASimulation, builder: Entity, unit_id: &str) -> Entity {
+        let order = Order::SpawnUnit {
+            unit_id: unit_id.to_string(),
+            army: Army(0),
+            position: None,
+        };
+        let target = issue(&mut sim.world, order).unwrap();
+        let order = Order::Construct {
+            entities: vec![builder],
+            target,
+        };
+        issue(&mut sim.world, order);
+        target
+    }
+
+    #[test]
+    fn forks_keep_logging_orders() {
+        let (mut sim, builder) = logged_simulation();
+        build(&mut sim, builder, "ueb1103");
+        for _ in 0..5 {
+            sim.run();
+        }
+        let mut fork = sim.fork();
+        let target = build(&mut fork, builder, "ueb1202");
+        for _ in 0..5 {
+            fork.run();
+        }
+        assert!(fork.world.get::<Damage>(target).unwrap().build_progress > 0.0);
+
+        assert_eq!(finish_order_log(&mut sim).unwrap().orders.len(), 2);
+        let replay = finish_order_log(&mut fork).unwrap();
+        assert_eq!(replay.orders.len(), 4);
+        let replayed = replay.run(None).unwrap();
+        assert_eq!(
+            replayed.world.resource::<CurrentTick>().0,
+            fork.world.resource::<CurrentTick>().0
+        );
+        assert_eq!(
+            replayed.world.get::<Damage>(target).unwrap().build_progress,
+            fork.world.get::<Damage>(target).unwrap().build_progress
+        );
+    }
+
+    #[test]
+    fn restoring_starts_the_order_log_over() {
+        let (mut sim, builder) = logged_simulation();
+        sim.run();
+        let snapshot = sim.snapshot();
+        build(&mut sim, builder, "ueb1103");
+        sim.run();
+
+        sim.restore(&snapshot);
+        let target = build(&mut sim, builder, "ueb1202");
+        for _ in 0..5 {
+            sim.run();
+        }
+        let replay = finish_order_log(&mut sim).unwrap();
+        assert_eq!(replay.start.tick(), 1);
+        assert_eq!(replay.orders.len(), 2);
+        assert!(matches!(
+            replay.orders[0].order,
+            Order::SpawnUnit { ref unit_id, .. } if unit_id == "ueb1202"
+        ));
+        let replayed = replay.run(None).unwrap();
+        assert_eq!(
+            replayed.world.get::<Damage>(target).unwrap().build_progress,
+            sim.world.get::<Damage>(target).unwrap().build_progress
+        );
+    }
+}
@@ -0,0 +1,1032 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use bevy_ecs::prelude::*;
+use serde::Deserialize;
+
+use crate::adjacency::GridPosition;
+use crate::alliance::Alliances;
+use crate::blueprint::BlueprintError;
+use crate::factory::*;
+use crate::reclaim::Reclaimable;
+use crate::recorder::{finish_recording, start_recording, RecordOptions};
+use crate::registry::{spawn_unit, UnitId, UnitRegistry};
+use crate::replay::{self, finish_order_log, issue, start_order_log, ReplayError};
+use crate::simulation::*;
+use crate::snapshot::{SimulationKind, Snapshots};
+use crate::trigger::{self, add_trigger, evaluate_triggers, TriggerId, Triggers};
+use crate::RASSimulation;
+
+/// Declarative description of an initial world and scripted orders
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Scenario {
+    #[serde(default)]
+    pub simulation: SimulationKind,
+    /// directory of extra blueprints, relative to the scenario file
+    pub blueprints: Option<PathBuf>,
+    /// stop after this many ticks if the end condition is never met
+    #[serde(default = "default_max_ticks")]
+    pub max_ticks: u64,
+    /// groups of allied armies
+    #[serde(default)]
+    pub allies: Vec<Vec<u32>>,
+    /// scenario is finished once this holds, or once all orders are executed
+    /// if not given
+    pub end: Option<Condition>,
+    #[serde(default, rename = "economy")]
+    pub economies: Vec<EconomySpec>,
+    #[serde(default, rename = "entity")]
+    pub entities: Vec<EntitySpec>,
+    #[serde(default, rename = "order")]
+    pub orders: Vec<Order>,
+}
+
+/// six hours of game time
+fn default_max_ticks() -> u64 {
+    6 * 60 * 60 * TICK_RATE as u64
+}
+
+/// Starting economy of an army
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct EconomySpec {
+    #[serde(default)]
+    pub army: u32,
+    #[serde(default)]
+    pub mass: f64,
+    #[serde(default)]
+    pub energy: f64,
+    pub mass_capacity: Option<f64>,
+    pub energy_capacity: Option<f64>,
+}
+
+/// Entities to spawn at the start of the scenario
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct EntitySpec {
+    /// name used to refer to the entities in orders and conditions
+    pub name: Option<String>,
+    #[serde(default)]
+    pub army: u32,
+    /// blueprint id to spawn from the unit registry
+    pub unit: Option<String>,
+    /// number of entities to spawn
+    #[serde(default = "default_count")]
+    pub count: u32,
+    /// spawn finished and executing instead of waiting for construction
+    #[serde(default = "default_built")]
+    pub built: bool,
+    /// position on the build grid (lowest corner of the footprint), only for
+    /// a single entity
+    pub position: Option<GridPosition>,
+    pub factory: Option<FactorySpec>,
+    pub engineering: Option<EngineeringSpec>,
+    pub producer: Option<ProducerSpec>,
+    pub consumer: Option<ConsumerSpec>,
+    pub reclaimable: Option<ReclaimableSpec>,
+}
+
+fn default_count() -> u32 {
+    1
+}
+
+fn default_built() -> bool {
+    true
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct FactorySpec {
+    /// time (in ticks) for a finished unit to leave the factory
+    #[serde(default)]
+    pub rolloff_time: i32,
+    #[serde(default)]
+    pub queue: Vec<BuildOrderSpec>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct BuildOrderSpec {
+    pub unit: String,
+    #[serde(default = "default_count")]
+    pub count: u32,
+    #[serde(default)]
+    pub repeat: bool,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct EngineeringSpec {
+    /// build rate per second
+    pub build_rate: f64,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ProducerSpec {
+    /// mass per second
+    #[serde(default)]
+    pub mass: f64,
+    /// energy per second
+    #[serde(default)]
+    pub energy: f64,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ConsumerSpec {
+    #[serde(default)]
+    pub priority: PrioritySpec,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ReclaimableSpec {
+    #[serde(default)]
+    pub mass: f64,
+    #[serde(default)]
+    pub energy: f64,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum PrioritySpec {
+    High,
+    #[default]
+    Normal,
+    Low,
+}
+
+impl From<PrioritySpec> for ConsumerPriority {
+    fn from(priority: PrioritySpec) -> Self {
+        match priority {
+            PrioritySpec::High => ConsumerPriority::High,
+            PrioritySpec::Normal => ConsumerPriority::Normal,
+            PrioritySpec::Low => ConsumerPriority::Low,
+        }
+    }
+}
+
+/// Entities an action applies to
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct Selector {
+    /// named scenario entities
+    #[serde(default)]
+    pub entities: Vec<String>,
+    /// all executing units with this blueprint id
+    pub unit_type: Option<String>,
+}
+
+/// Condition on the state of the world
+#[derive(Deserialize, Clone, Debug)]
+#[serde(tag = "condition", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum Condition {
+    /// tick reached
+    Tick { at: u64 },
+    /// number of executing units with a blueprint id
+    UnitCount { unit_type: String, at_least: usize },
+    /// build progress of a named entity, as a fraction
+    BuildProgress { entity: String, at_least: f64 },
+    /// resources in storage
+    Stored {
+        #[serde(default)]
+        army: u32,
+        mass: Option<f64>,
+        energy: Option<f64>,
+    },
+}
+
+/// Order executed once its tick is reached and its condition holds
+#[derive(Deserialize, Clone, Debug)]
+pub struct Order {
+    /// earliest tick to execute at
+    pub tick: Option<u64>,
+    pub when: Option<Condition>,
+    #[serde(flatten)]
+    pub action: Action,
+}
+
+/// Change to the world made by an order
+#[derive(Deserialize, Clone, Debug)]
+#[serde(tag = "action", rename_all = "kebab-case")]
+pub enum Action {
+    /// start constructing a named entity, or a new unit of a blueprint id
+    Construct {
+        #[serde(flatten)]
+        builders: Selector,
+        target: Option<String>,
+        spawn: Option<String>,
+        /// name for the spawned unit
+        name: Option<String>,
+        /// position of the spawned unit on the build grid
+        position: Option<GridPosition>,
+    },
+    /// assist a named entity
+    Assist {
+        #[serde(flatten)]
+        select: Selector,
+        target: String,
+    },
+    /// pause construction
+    Pause {
+        #[serde(flatten)]
+        select: Selector,
+    },
+    /// resume construction
+    Resume {
+        #[serde(flatten)]
+        select: Selector,
+    },
+    /// stop executing
+    Stop {
+        #[serde(flatten)]
+        select: Selector,
+    },
+    /// start executing
+    Start {
+        #[serde(flatten)]
+        select: Selector,
+    },
+    /// sacrifice into a named entity
+    Sacrifice {
+        #[serde(flatten)]
+        select: Selector,
+        target: String,
+    },
+    /// upgrade into another blueprint id
+    Upgrade {
+        #[serde(flatten)]
+        select: Selector,
+        unit: String,
+    },
+    /// reclaim a named entity
+    Reclaim {
+        #[serde(flatten)]
+        select: Selector,
+        target: String,
+    },
+    /// repair a named entity
+    Repair {
+        #[serde(flatten)]
+        select: Selector,
+        target: String,
+    },
+    /// destroy, leaving wrecks
+    Destroy {
+        #[serde(flatten)]
+        select: Selector,
+    },
+}
+
+#[derive(Debug)]
+pub enum ScenarioError {
+    Io(std::io::Error),
+    Parse(toml::de::Error),
+    Blueprint(BlueprintError),
+    /// blueprint id missing from the unit registry
+    UnknownUnit(String),
+    /// name not given to any scenario entity
+    UnknownEntity(String),
+    /// construct order needs exactly one of target and spawn, and a position
+    /// only with spawn
+    InvalidConstruct,
+    /// several entities would be spawned at the same position
+    InvalidPosition,
+    /// sacrifice orders only exist in RAS simulations
+    SacrificeOutsideRas,
+    /// failed to write the recording
+    Record(std::io::Error),
+    /// failed to write the replay of orders
+    OrderLog(ReplayError),
+}
+
+impl fmt::Display for ScenarioError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScenarioError::Io(err) => write!(f, "failed to read scenario: {}", err),
+            ScenarioError::Parse(err) => write!(f, "invalid scenario: {}", err),
+            ScenarioError::Blueprint(err) => write!(f, "{}", err),
+            ScenarioError::UnknownUnit(id) => write!(f, "unit {} is not registered", id),
+            ScenarioError::UnknownEntity(name) => write!(f, "no entity is named {}", name),
+            ScenarioError::InvalidConstruct => write!(
+                f,
+                "construct orders need exactly one of target and spawn, and a position only with spawn"
+            ),
+            ScenarioError::InvalidPosition => {
+                write!(f, "entities with a position must have a count of 1")
+            }
+            ScenarioError::SacrificeOutsideRas => {
+                write!(f, "sacrifice orders need simulation = \"ras\"")
+            }
+            ScenarioError::Record(err) => write!(f, "failed to write recording: {}", err),
+            ScenarioError::OrderLog(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for ScenarioError {}
+
+impl From<std::io::Error> for ScenarioError {
+    fn from(err: std::io::Error) -> Self {
+        ScenarioError::Io(err)
+    }
+}
+
+impl From<toml::de::Error> for ScenarioError {
+    fn from(err: toml::de::Error) -> Self {
+        ScenarioError::Parse(err)
+    }
+}
+
+impl From<BlueprintError> for ScenarioError {
+    fn from(err: BlueprintError) -> Self {
+        ScenarioError::Blueprint(err)
+    }
+}
+
+/// Outcome of a scenario run
+#[derive(Clone, Debug)]
+pub struct ScenarioReport {
+    /// tick the scenario stopped at
+    pub ticks: u64,
+    /// end condition was met before max_ticks
+    pub finished: bool,
+    /// orders executed, by index in the scenario
+    pub executed_orders: Vec<usize>,
+}
+
+impl Scenario {
+    pub fn parse(source: &str) -> Result<Scenario, ScenarioError> {
+        Ok(toml::from_str(source)?)
+    }
+
+    /// load a scenario file, resolving the blueprint directory relative to it
+    pub fn load(path: impl AsRef<Path>) -> Result<Scenario, ScenarioError> {
+        let path = path.as_ref();
+        let mut scenario = Scenario::parse(&std::fs::read_to_string(path)?)?;
+        if let (Some(blueprints), Some(parent)) = (&scenario.blueprints, path.parent()) {
+            scenario.blueprints = Some(parent.join(blueprints));
+        }
+        Ok(scenario)
+    }
+
+    /// names given to entities, including units spawned by orders
+    fn declared_names(&self) -> Vec<&str> {
+        let spawned = self.orders.iter().filter_map(|order| match &order.action {
+            Action::Construct { name, .. } => name.as_deref(),
+            _ => None,
+        });
+        self.entities
+            .iter()
+            .filter_map(|entity| entity.name.as_deref())
+            .chain(spawned)
+            .collect()
+    }
+
+    /// check that all referenced names and blueprint ids exist
+    fn validate(&self, registry: &UnitRegistry) -> Result<(), ScenarioError> {
+        let names = self.declared_names();
+        let check_name = |name: &String| {
+            if names.contains(&name.as_str()) {
+                Ok(())
+            } else {
+                Err(ScenarioError::UnknownEntity(name.clone()))
+            }
+        };
+        let check_unit = |id: &String| {
+            if registry.contains(id) {
+                Ok(())
+            } else {
+                Err(ScenarioError::UnknownUnit(id.clone()))
+            }
+        };
+        let check_selector = |selector: &Selector| -> Result<(), ScenarioError> {
+            selector.entities.iter().try_for_each(check_name)?;
+            selector.unit_type.iter().try_for_each(check_unit)
+        };
+        let check_condition = |condition: &Condition| match condition {
+            Condition::UnitCount { unit_type, .. } => check_unit(unit_type),
+            Condition::BuildProgress { entity, .. } => check_name(entity),
+            Condition::Tick { .. } | Condition::Stored { .. } => Ok(()),
+        };
+
+        for entity in &self.entities {
+            if entity.position.is_some() && entity.count != 1 {
+                return Err(ScenarioError::InvalidPosition);
+            }
+            entity.unit.iter().try_for_each(check_unit)?;
+            for order in entity.factory.iter().flat_map(|factory| &factory.queue) {
+                check_unit(&order.unit)?;
+            }
+        }
+        self.end.iter().try_for_each(check_condition)?;
+        for order in &self.orders {
+            order.when.iter().try_for_each(check_condition)?;
+            match &order.action {
+                Action::Construct {
+                    builders,
+                    target,
+                    spawn,
+                    position,
+                    ..
+                } => {
+                    check_selector(builders)?;
+                    match (target, spawn, position) {
+                        (Some(target), None, None) => check_name(target)?,
+                        (None, Some(spawn), _) => check_unit(spawn)?,
+                        _ => return Err(ScenarioError::InvalidConstruct),
+                    }
+                }
+                Action::Sacrifice { select, target } => {
+                    if self.simulation != SimulationKind::Ras {
+                        return Err(ScenarioError::SacrificeOutsideRas);
+                    }
+                    check_selector(select)?;
+                    check_name(target)?;
+                }
+                Action::Assist { select, target }
+                | Action::Reclaim { select, target }
+                | Action::Repair { select, target } => {
+                    check_selector(select)?;
+                    check_name(target)?;
+                }
+                Action::Upgrade { select, unit } => {
+                    check_selector(select)?;
+                    check_unit(unit)?;
+                }
+                Action::Pause { select }
+                | Action::Resume { select }
+                | Action::Stop { select }
+                | Action::Start { select }
+                | Action::Destroy { select } => check_selector(select)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// run the scenario in the simulation it asks for, optionally recording
+    /// its state and writing a replay of its orders
+    pub fn run(
+        &self,
+        record: Option<&RecordOptions>,
+        order_log: Option<&Path>,
+    ) -> Result<ScenarioReport, ScenarioError> {
+        match self.simulation {
+            SimulationKind::Fa => self.run_in(FASimulation::new(), record, order_log),
+            SimulationKind::Ras => self.run_in(RASSimulation::new(), record, order_log),
+        }
+    }
+
+    /// run the scenario in an existing simulation
+    pub fn run_in(
+        &self,
+        mut sim: impl Snapshots,
+        record: Option<&RecordOptions>,
+        order_log: Option<&Path>,
+    ) -> Result<ScenarioReport, ScenarioError> {
+        let order_triggers = self.setup(sim.world_mut())?;
+        if let Some(record) = record {
+            start_recording(sim.world_mut(), record).map_err(ScenarioError::Record)?;
+        }
+        if order_log.is_some() {
+            start_order_log(&mut sim);
+        }
+        let end_trigger = self
+            .end
+            .as_ref()
+            .map(|end| add_trigger(sim.world_mut(), "end", condition(end), |_| {}));
+        // orders due before the first tick
+        evaluate_triggers(sim.world_mut());
+        loop {
+            let tick = sim.world().resource::<CurrentTick>().0;
+            let triggers = sim.world().resource::<Triggers>();
+            let finished = match end_trigger {
+                Some(end_trigger) => triggers.has_fired(end_trigger),
+                None => order_triggers.iter().all(|id| triggers.has_fired(*id)),
+            };
+            if finished || tick >= self.max_ticks {
+                let report = ScenarioReport {
+                    ticks: tick,
+                    finished,
+                    executed_orders: (0..self.orders.len())
+                        .filter(|index| triggers.has_fired(order_triggers[*index]))
+                        .collect(),
+                };
+                finish_recording(sim.world_mut()).map_err(ScenarioError::Record)?;
+                if let (Some(path), Some(replay)) = (order_log, finish_order_log(&mut sim)) {
+                    replay.save(path).map_err(ScenarioError::OrderLog)?;
+                }
+                return Ok(report);
+            }
+            sim.run();
+        }
+    }
+
+    /// set up economies, alliances and initial entities, and register a
+    /// trigger for each order
+    fn setup(&self, world: &mut World) -> Result<Vec<TriggerId>, ScenarioError> {
+        self.populate(world)?;
+
+        let mut triggers = world.resource_mut::<Triggers>();
+        Ok(self
+            .orders
+            .iter()
+            .enumerate()
+            .map(|(index, order)| {
+                let mut order_condition = trigger::Condition::tick_reached(order.tick.unwrap_or(0));
+                if let Some(when) = &order.when {
+                    order_condition = order_condition.and(condition(when));
+                }
+                let action = order.action.clone();
+                triggers.add(
+                    format!("order {} {:?}", index, action),
+                    order_condition,
+                    move |world| execute(world, &action),
+                )
+            })
+            .collect())
+    }
+
+    /// set up blueprints, economies, alliances and initial entities without
+    /// registering orders
+    pub fn populate(&self, world: &mut World) -> Result<(), ScenarioError> {
+        if let Some(blueprints) = &self.blueprints {
+            let mut loaded = UnitRegistry::new();
+            loaded.load_directory(blueprints)?;
+            let mut registry = world.resource_mut::<UnitRegistry>();
+            for id in loaded.ids() {
+                // keep units the simulation registered itself
+                if !registry.contains(id) {
+                    registry.register(loaded.blueprint(id).unwrap().clone());
+                }
+            }
+        }
+        self.validate(world.resource::<UnitRegistry>())?;
+
+        {
+            let mut economies = world.resource_mut::<Economies>();
+            for spec in &self.economies {
+                let economy = economies.get_or_default(Army(spec.army));
+                economy.mass = spec.mass;
+                economy.energy = spec.energy;
+                if let Some(mass_capacity) = spec.mass_capacity {
+                    economy.mass_capacity = mass_capacity;
+                }
+                if let Some(energy_capacity) = spec.energy_capacity {
+                    economy.energy_capacity = energy_capacity;
+                }
+            }
+        }
+        if !self.allies.is_empty() {
+            let mut alliances = world.resource_mut::<Alliances>();
+            for team in &self.allies {
+                let team: Vec<Army> = team.iter().map(|army| Army(*army)).collect();
+                alliances.ally(&team);
+            }
+        }
+
+        let mut names = ScenarioNames::default();
+        for spec in &self.entities {
+            for _ in 0..spec.count {
+                let entity = spawn_entity(world, spec)?;
+                if let Some(name) = &spec.name {
+                    names.0.entry(name.clone()).or_default().push(entity);
+                }
+            }
+        }
+        world.insert_resource(names);
+        Ok(())
+    }
+}
+
+/// Scenario entities by name
+#[derive(Clone, Debug, Default)]
+pub struct ScenarioNames(pub HashMap<String, Vec<Entity>>);
+
+fn spawn_entity(world: &mut World, spec: &EntitySpec) -> Result<Entity, ScenarioError> {
+    let army = Army(spec.army);
+    let entity = match &spec.unit {
+        Some(unit) => {
+            spawn_unit(world, unit, army).ok_or_else(|| ScenarioError::UnknownUnit(unit.clone()))?
+        }
+        None => world.spawn().insert(army).id(),
+    };
+    let mut entity_mut = world.entity_mut(entity);
+    if let Some(position) = spec.position {
+        entity_mut.insert(position);
+    }
+    if spec.built {
+        if let Some(mut damage) = entity_mut.get_mut::<Damage>() {
+            damage.finish_construction();
+        }
+        entity_mut.remove::<WillExecuteOnConstruct>();
+        entity_mut.insert(Executing);
+    }
+    if let Some(factory_spec) = &spec.factory {
+        let mut factory = Factory::new(factory_spec.rolloff_time);
+        for order in &factory_spec.queue {
+            factory = factory.with_order(if order.repeat {
+                BuildOrder::repeat(order.unit.clone(), order.count)
+            } else {
+                BuildOrder::once(order.unit.clone(), order.count)
+            });
+        }
+        entity_mut.insert(factory);
+    }
+    if let Some(engineering) = &spec.engineering {
+        entity_mut.insert(Engineering {
+            build_rate: engineering.build_rate / TICK_RATE,
+        });
+    }
+    if let Some(producer) = &spec.producer {
+        entity_mut.insert(ResourceProducer {
+            mass_yield: producer.mass / TICK_RATE,
+            energy_yield: producer.energy / TICK_RATE,
+            ..Default::default()
+        });
+    }
+    if let Some(consumer) = &spec.consumer {
+        entity_mut.insert(ResourceConsumer {
+            priority: consumer.priority.into(),
+            ..Default::default()
+        });
+    } else if spec.engineering.is_some() && !entity_mut.contains::<ResourceConsumer>() {
+        // builders need a consumer to draw resources
+        entity_mut.insert(ResourceConsumer::default());
+    }
+    if let Some(reclaimable) = &spec.reclaimable {
+        entity_mut.insert(Reclaimable::new(reclaimable.mass, reclaimable.energy));
+    }
+    Ok(entity)
+}
+
+/// living entities with a name
+fn named(world: &World, name: &str) -> Vec<Entity> {
+    world
+        .resource::<ScenarioNames>()
+        .0
+        .get(name)
+        .into_iter()
+        .flatten()
+        .copied()
+        .filter(|entity| world.get_entity(*entity).is_some())
+        .collect()
+}
+
+fn selected(world: &mut World, selector: &Selector) -> Vec<Entity> {
+    let mut entities: Vec<Entity> = selector
+        .entities
+        .iter()
+        .flat_map(|name| named(world, name))
+        .collect();
+    if let Some(unit_type) = &selector.unit_type {
+        let mut query = world.query_filtered::<(Entity, &UnitId), With<Executing>>();
+        entities.extend(
+            query
+                .iter(world)
+                .filter(|(_, unit_id)| unit_id.0 == *unit_type)
+                .map(|(entity, _)| entity),
+        );
+    }
+    entities
+}
+
+/// trigger condition checking a scenario condition
+fn condition(condition: &Condition) -> trigger::Condition {
+    match condition.clone() {
+        Condition::Tick { at } => trigger::Condition::tick_reached(at),
+        Condition::UnitCount {
+            unit_type,
+            at_least,
+        } => trigger::Condition::new(move |world| {
+            let mut query = world.query_filtered::<&UnitId, With<Executing>>();
+            query
+                .iter(world)
+                .filter(|unit_id| unit_id.0 == unit_type)
+                .count()
+                >= at_least
+        }),
+        Condition::BuildProgress { entity, at_least } => trigger::Condition::new(move |world| {
+            named(world, &entity).iter().any(|entity| {
+                world
+                    .get::<Damage>(*entity)
+                    .is_some_and(|damage| damage.build_progress >= at_least)
+            })
+        }),
+        Condition::Stored { army, mass, energy } => {
+            trigger::Condition::stored(Army(army), mass, energy)
+        }
+    }
+}
+
+/// issue the order an action makes, if its target exists
+fn execute(world: &mut World, action: &Action) {
+    let named_target = |world: &World, target: &str| named(world, target).first().copied();
+    let order = match action {
+        Action::Construct {
+            builders,
+            target,
+            spawn,
+            name,
+            position,
+        } => {
+            let entities = selected(world, builders);
+            let target = match (target, spawn) {
+                (Some(target), _) => named_target(world, target),
+                (None, Some(spawn)) => {
+                    let army = entities
+                        .first()
+                        .and_then(|builder| world.get::<Army>(*builder).copied())
+                        .unwrap_or_default();
+                    let order = replay::Order::SpawnUnit {
+                        unit_id: spawn.clone(),
+                        army,
+                        position: *position,
+                    };
+                    let spawned = issue(world, order);
+                    if let (Some(spawned), Some(name)) = (spawned, name) {
+                        world
+                            .resource_mut::<ScenarioNames>()
+                            .0
+                            .entry(name.clone())
+                            .or_default()
+                            .push(spawned);
+                    }
+                    spawned
+                }
+                (None, None) => None,
+            };
+            target.map(|target| replay::Order::Construct { entities, target })
+        }
+        Action::Assist { select, target } => named_target(world, target).map(|target| {
+            let entities = selected(world, select);
+            replay::Order::Assist { entities, target }
+        }),
+        Action::Pause { select } => Some(replay::Order::Pause {
+            entities: selected(world, select),
+        }),
+        Action::Resume { select } => Some(replay::Order::Resume {
+            entities: selected(world, select),
+        }),
+        Action::Stop { select } => Some(replay::Order::Stop {
+            entities: selected(world, select),
+        }),
+        Action::Start { select } => Some(replay::Order::Start {
+            entities: selected(world, select),
+        }),
+        Action::Sacrifice { select, target } => named_target(world, target).map(|target| {
+            let entities = selected(world, select);
+            replay::Order::Sacrifice { entities, target }
+        }),
+        Action::Upgrade { select, unit } => Some(replay::Order::Upgrade {
+            entities: selected(world, select),
+            unit_id: unit.clone(),
+        }),
+        Action::Reclaim { select, target } => named_target(world, target).map(|target| {
+            let entities = selected(world, select);
+            replay::Order::Reclaim { entities, target }
+        }),
+        Action::Repair { select, target } => named_target(world, target).map(|target| {
+            let entities = selected(world, select);
+            replay::Order::Repair { entities, target }
+        }),
+        Action::Destroy { select } => Some(replay::Order::Destroy {
+            entities: selected(world, select),
+        }),
+    };
+    if let Some(order) = order {
+        issue(world, order);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::tests::*;
+
+    const ENGINEER_BUILDS_EXTRACTOR: &str = r#"
+        max_ticks = 2000
+
+        [[economy]]
+        mass = 500
+        energy = 5000
+        mass_capacity = 1000
+
+        [[entity]]
+        name = "engineers"
+        count = 2
+        engineering = { build_rate = 10 }
+
+        [[entity]]
+        name = "extractor"
+        unit = "ueb1103"
+        built = false
+        position = { x = 4, z = 6 }
+
+        [[order]]
+        tick = 5
+        action = "construct"
+        entities = ["engineers"]
+        target = "extractor"
+
+        [[order]]
+        when = { condition = "build-progress", entity = "extractor", at_least = 1.0 }
+        action = "construct"
+        entities = ["engineers"]
+        spawn = "ueb1103"
+        name = "second"
+    "#;
+
+    fn validate(source: &str) -> Result<(), ScenarioError> {
+        let sim = test_simulation();
+        Scenario::parse(source)?.validate(sim.world.resource::<UnitRegistry>())
+    }
+
+    #[test]
+    fn parse_defaults_and_specs() {
+        let scenario = Scenario::parse(ENGINEER_BUILDS_EXTRACTOR).unwrap();
+        assert_eq!(scenario.simulation, SimulationKind::Fa);
+        assert_eq!(scenario.max_ticks, 2000);
+        assert!(scenario.end.is_none());
+        assert_eq!(scenario.economies[0].army, 0);
+        assert_eq!(scenario.economies[0].energy_capacity, None);
+        let engineers = &scenario.entities[0];
+        assert_eq!(engineers.count, 2);
+        assert!(engineers.built);
+        assert!(engineers.unit.is_none());
+        let extractor = &scenario.entities[1];
+        assert_eq!(extractor.count, 1);
+        assert!(!extractor.built);
+        assert_eq!(extractor.position, Some(GridPosition { x: 4, z: 6 }));
+        assert_eq!(scenario.orders[0].tick, Some(5));
+        assert!(matches!(
+            scenario.orders[1].when,
+            Some(Condition::BuildProgress { at_least, .. }) if at_least == 1.0
+        ));
+        assert!(matches!(
+            &scenario.orders[1].action,
+            Action::Construct { spawn: Some(spawn), .. } if spawn == "ueb1103"
+        ));
+
+        let defaults = Scenario::parse("").unwrap();
+        assert_eq!(defaults.max_ticks, 6 * 60 * 60 * 10);
+        assert!(defaults.entities.is_empty());
+    }
+
+    #[test]
+    fn parse_errors() {
+        let parse_error = |source| matches!(Scenario::parse(source), Err(ScenarioError::Parse(_)));
+        assert!(parse_error("unknown = 1"));
+        assert!(parse_error("[[entity]]\nbuild_rate = 10"));
+        assert!(parse_error("[[order]]\naction = \"explode\""));
+        assert!(parse_error("[end]\ncondition = \"tick\""));
+        assert!(parse_error("simulation = \"ta\""));
+    }
+
+    #[test]
+    fn validate_references() {
+        assert!(validate(ENGINEER_BUILDS_EXTRACTOR).is_ok());
+        let unknown_unit = |source| matches!(validate(source), Err(ScenarioError::UnknownUnit(id)) if id == "unknown");
+        assert!(unknown_unit("[[entity]]\nunit = \"unknown\""));
+        assert!(unknown_unit(
+            "[[entity]]\nfactory = { queue = [{ unit = \"unknown\" }] }"
+        ));
+        assert!(unknown_unit(
+            "[end]\ncondition = \"unit-count\"\nunit_type = \"unknown\"\nat_least = 1"
+        ));
+        assert!(unknown_unit(
+            "[[order]]\naction = \"upgrade\"\nunit_type = \"ueb1103\"\nunit = \"unknown\""
+        ));
+        assert!(matches!(
+            validate("[[order]]\naction = \"stop\"\nentities = [\"nobody\"]"),
+            Err(ScenarioError::UnknownEntity(name)) if name == "nobody"
+        ));
+        // units spawned by orders may be named before they exist
+        assert!(validate(
+            "[[order]]\naction = \"construct\"\nspawn = \"ueb1103\"\nname = \"later\"\n\
+             [[order]]\naction = \"destroy\"\nentities = [\"later\"]"
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn validate_rejects_invalid_orders() {
+        let invalid_construct = |source| {
+            matches!(
+                validate(&format!(
+                    "[[entity]]\nname = \"a\"\n[[order]]\naction = \"construct\"\n{}",
+                    source
+                )),
+                Err(ScenarioError::InvalidConstruct)
+            )
+        };
+        assert!(invalid_construct(""));
+        assert!(invalid_construct("target = \"a\"\nspawn = \"ueb1103\""));
+        assert!(invalid_construct(
+            "target = \"a\"\nposition = { x = 0, z = 0 }"
+        ));
+        assert!(matches!(
+            validate("[[entity]]\ncount = 2\nposition = { x = 0, z = 0 }"),
+            Err(ScenarioError::InvalidPosition)
+        ));
+
+        let sacrifice = "[[entity]]\nname = \"a\"\n\
+                         [[order]]\naction = \"sacrifice\"\nentities = [\"a\"]\ntarget = \"a\"";
+        assert!(matches!(
+            validate(sacrifice),
+            Err(ScenarioError::SacrificeOutsideRas)
+        ));
+        assert!(validate(&format!("simulation = \"ras\"\n{}", sacrifice)).is_ok());
+    }
+
+    #[test]
+    fn populate_world() {
+        let scenario = Scenario::parse(&format!(
+            "allies = [[0, 1]]\n{}\n[[economy]]\narmy = 1\n\
+             [[entity]]\nname = \"income\"\narmy = 1\nproducer = {{ mass = 10 }}\n\
+             consumer = {{ priority = \"low\" }}",
+            ENGINEER_BUILDS_EXTRACTOR
+        ))
+        .unwrap();
+        let mut sim = test_simulation();
+        scenario.populate(&mut sim.world).unwrap();
+
+        let economy = sim.economy(Army(0)).unwrap();
+        assert_eq!((economy.mass, economy.energy), (500.0, 5000.0));
+        assert_eq!(economy.mass_capacity, 1000.0);
+        assert_eq!(economy.energy_capacity, Economy::default().energy_capacity);
+        assert!(sim.economy(Army(1)).is_some());
+        assert_eq!(
+            sim.world.resource::<Alliances>().allies_of(Army(0)),
+            vec![Army(1)]
+        );
+
+        let names = sim.world.resource::<ScenarioNames>().0.clone();
+        let engineers = &names["engineers"];
+        assert_eq!(engineers.len(), 2);
+        for engineer in engineers {
+            let entity = sim.world.entity(*engineer);
+            assert!(entity.contains::<Executing>());
+            assert_eq!(entity.get::<Army>(), Some(&Army(0)));
+            assert_eq!(entity.get::<Engineering>().unwrap().build_rate, 1.0);
+            assert!(entity.contains::<ResourceConsumer>());
+        }
+        let extractor = sim.world.entity(names["extractor"][0]);
+        assert!(!extractor.contains::<Executing>());
+        assert!(extractor.contains::<WillExecuteOnConstruct>());
+        assert_eq!(extractor.get::<Damage>().unwrap().build_progress, 0.0);
+        assert_eq!(
+            extractor.get::<GridPosition>(),
+            Some(&GridPosition { x: 4, z: 6 })
+        );
+        let income = sim.world.entity(names["income"][0]);
+        assert_eq!(income.get::<Army>(), Some(&Army(1)));
+        assert_eq!(income.get::<ResourceProducer>().unwrap().mass_yield, 1.0);
+        assert_eq!(
+            income.get::<ResourceConsumer>().unwrap().priority,
+            ConsumerPriority::Low
+        );
+    }
+
+    #[test]
+    fn run_in_executes_orders() {
+        let scenario = Scenario::parse(ENGINEER_BUILDS_EXTRACTOR).unwrap();
+        let report = scenario.run_in(test_simulation(), None, None).unwrap();
+        // extractor takes 60 build time at 2 build rate per tick
+        assert!(report.finished);
+        assert_eq!(report.executed_orders, [0, 1]);
+        assert_eq!(report.ticks, 5 + 30);
+
+        // stops at max_ticks if the end condition never holds
+        let never = format!(
+            "{}\n[end]\ncondition = \"tick\"\nat = 5000",
+            ENGINEER_BUILDS_EXTRACTOR
+        );
+        let report = Scenario::parse(&never)
+            .unwrap()
+            .run_in(test_simulation(), None, None)
+            .unwrap();
+        assert!(!report.finished);
+        assert_eq!(report.ticks, 2000);
+        assert_eq!(report.executed_orders, [0, 1]);
+    }
+
+    #[test]
+    fn scenario_file_runs_to_the_end() {
+        let path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/scenarios/extractor_upgrade.toml"
+        );
+        let scenario = Scenario::load(path).unwrap();
+        assert_eq!(
+            scenario.blueprints,
+            Some(Path::new(path).parent().unwrap().join("../blueprints"))
+        );
+        let report = scenario.run(None, None).unwrap();
+        assert!(report.finished);
+        assert_eq!(report.executed_orders, [0, 1]);
+        assert!(report.ticks < scenario.max_ticks);
+    }
+}
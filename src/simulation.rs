@@ -1,4 +1,21 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
 use bevy_ecs::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::adjacency::{update_adjacency_bonus, AdjacencyBonus};
+use crate::alliance::{share_overflow, Alliances};
+use crate::fabricator::{mass_fabricator_convert, mass_fabricator_request};
+use crate::factory::{factory_production, UnitRolledOff};
+use crate::reclaim::{destroy_units, do_reclaim};
+use crate::recorder::record_state;
+use crate::registry::UnitRegistry;
+use crate::repair::{do_repair, do_repair_resources_request};
+use crate::replay::{issue, Order};
+use crate::snapshot::*;
+use crate::trigger::{evaluate_triggers, Triggers};
+use crate::upgrade::{finish_upgrades, start_upgrades};
 
 /// ticks per second
 pub const TICK_RATE: f64 = 10.0;
@@ -6,7 +23,7 @@ pub const TICK_RATE: f64 = 10.0;
 pub const EPSILON: f64 = 1e-6;
 
 /// FA resource economy
-#[derive(Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Economy {
     /// current available mass
     pub mass: f64,
@@ -20,6 +37,10 @@ pub struct Economy {
     pub mass_stall: f64,
     /// energy stall ratio
     pub energy_stall: f64,
+    /// mass stall ratio for each priority tier
+    pub mass_stall_by_priority: [f64; ConsumerPriority::COUNT],
+    /// energy stall ratio for each priority tier
+    pub energy_stall_by_priority: [f64; ConsumerPriority::COUNT],
     /// total mass production
     pub mass_produced: f64,
     /// total energy production
@@ -32,6 +53,42 @@ pub struct Economy {
     pub mass_consumed: f64,
     /// total energy consumed
     pub energy_consumed: f64,
+    /// mass above capacity this tick
+    pub mass_overflow: f64,
+    /// energy above capacity this tick
+    pub energy_overflow: f64,
+    /// overflowing mass given to allies this tick
+    pub mass_shared: f64,
+    /// overflowing energy given to allies this tick
+    pub energy_shared: f64,
+    /// mass received from allies this tick
+    pub mass_received: f64,
+    /// energy received from allies this tick
+    pub energy_received: f64,
+    /// overflowing mass lost this tick
+    pub mass_wasted: f64,
+    /// overflowing energy lost this tick
+    pub energy_wasted: f64,
+    /// mass reclaimed from wrecks and props this tick
+    pub mass_reclaimed: f64,
+    /// energy reclaimed from wrecks and props this tick
+    pub energy_reclaimed: f64,
+    /// total mass given to allies
+    pub total_mass_shared: f64,
+    /// total energy given to allies
+    pub total_energy_shared: f64,
+    /// total mass received from allies
+    pub total_mass_received: f64,
+    /// total energy received from allies
+    pub total_energy_received: f64,
+    /// total mass lost to full storage
+    pub total_mass_wasted: f64,
+    /// total energy lost to full storage
+    pub total_energy_wasted: f64,
+    /// total mass reclaimed
+    pub total_mass_reclaimed: f64,
+    /// total energy reclaimed
+    pub total_energy_reclaimed: f64,
 }
 
 impl Default for Economy {
@@ -43,13 +100,155 @@ impl Default for Economy {
             energy_capacity: 100000.0,
             mass_stall: 1.0,
             energy_stall: 1.0,
+            mass_stall_by_priority: [1.0; ConsumerPriority::COUNT],
+            energy_stall_by_priority: [1.0; ConsumerPriority::COUNT],
             mass_produced: 0.0,
             energy_produced: 0.0,
             mass_requested: 0.0,
             energy_requested: 0.0,
             mass_consumed: 0.0,
             energy_consumed: 0.0,
+            mass_overflow: 0.0,
+            energy_overflow: 0.0,
+            mass_shared: 0.0,
+            energy_shared: 0.0,
+            mass_received: 0.0,
+            energy_received: 0.0,
+            mass_wasted: 0.0,
+            energy_wasted: 0.0,
+            mass_reclaimed: 0.0,
+            energy_reclaimed: 0.0,
+            total_mass_shared: 0.0,
+            total_energy_shared: 0.0,
+            total_mass_received: 0.0,
+            total_energy_received: 0.0,
+            total_mass_wasted: 0.0,
+            total_energy_wasted: 0.0,
+            total_mass_reclaimed: 0.0,
+            total_energy_reclaimed: 0.0,
+        }
+    }
+}
+
+impl Economy {
+    /// mass stall ratio applying to consumers of a priority tier
+    pub fn mass_stall_for(&self, priority: ConsumerPriority) -> f64 {
+        self.mass_stall_by_priority[priority as usize]
+    }
+
+    /// energy stall ratio applying to consumers of a priority tier
+    pub fn energy_stall_for(&self, priority: ConsumerPriority) -> f64 {
+        self.energy_stall_by_priority[priority as usize]
+    }
+
+    /// compute stall ratios from total requests of each priority tier
+    pub fn allocate(
+        &mut self,
+        mass_requested: [f64; ConsumerPriority::COUNT],
+        energy_requested: [f64; ConsumerPriority::COUNT],
+    ) {
+        let total_mass_requested: f64 = mass_requested.iter().sum();
+        let total_energy_requested: f64 = energy_requested.iter().sum();
+
+        // serve tiers in order, each tier is stalled only by what higher tiers left over
+        let mut mass_remaining = f64::max(0.0, self.mass);
+        let mut energy_remaining = f64::max(0.0, self.energy);
+        for priority in ConsumerPriority::ALL {
+            let index = priority as usize;
+            let mass_stall = f64::min(1.0, mass_remaining / mass_requested[index]);
+            let energy_stall = f64::min(1.0, energy_remaining / energy_requested[index]);
+            self.mass_stall_by_priority[index] = mass_stall;
+            self.energy_stall_by_priority[index] = energy_stall;
+            mass_remaining = f64::max(0.0, mass_remaining - mass_requested[index] * mass_stall);
+            energy_remaining = f64::max(
+                0.0,
+                energy_remaining - energy_requested[index] * energy_stall,
+            );
         }
+
+        self.mass_stall = f64::min(1.0, self.mass / total_mass_requested);
+        self.energy_stall = f64::min(1.0, self.energy / total_energy_requested);
+        self.mass_requested = total_mass_requested;
+        self.energy_requested = total_energy_requested;
+    }
+
+    /// withdraw mass from storage, returns amount actually withdrawn
+    pub fn pull_mass(&mut self, amount: f64) -> f64 {
+        let pulled = amount.clamp(0.0, f64::max(0.0, self.mass));
+        self.mass -= pulled;
+        pulled
+    }
+
+    /// withdraw energy from storage, returns amount actually withdrawn
+    pub fn pull_energy(&mut self, amount: f64) -> f64 {
+        let pulled = amount.clamp(0.0, f64::max(0.0, self.energy));
+        self.energy -= pulled;
+        pulled
+    }
+}
+
+/// Army (player) an entity belongs to
+#[derive(
+    Component,
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Serialize,
+    Deserialize,
+)]
+pub struct Army(pub u32);
+
+/// Economies of all armies
+#[derive(Clone, Debug, Default)]
+pub struct Economies {
+    economies: BTreeMap<Army, Economy>,
+}
+
+impl Economies {
+    /// create with a single economy for army 0
+    pub fn single(economy: Economy) -> Self {
+        let mut economies = Economies::default();
+        economies.insert(Army(0), economy);
+        economies
+    }
+
+    pub fn insert(&mut self, army: Army, economy: Economy) {
+        self.economies.insert(army, economy);
+    }
+
+    pub fn get(&self, army: Army) -> Option<&Economy> {
+        self.economies.get(&army)
+    }
+
+    pub fn get_mut(&mut self, army: Army) -> Option<&mut Economy> {
+        self.economies.get_mut(&army)
+    }
+
+    /// get economy of army, creating a default economy if the army has none
+    pub fn get_or_default(&mut self, army: Army) -> &mut Economy {
+        self.economies.entry(army).or_default()
+    }
+
+    pub fn armies(&self) -> impl Iterator<Item = Army> + '_ {
+        self.economies.keys().copied()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Army, &Economy)> {
+        self.economies
+            .iter()
+            .map(|(army, economy)| (*army, economy))
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (Army, &mut Economy)> {
+        self.economies
+            .iter_mut()
+            .map(|(army, economy)| (*army, economy))
     }
 }
 
@@ -57,31 +256,36 @@ impl Default for Economy {
 pub struct CurrentTick(pub u64);
 
 /// System log handler
+#[derive(Clone)]
 pub struct LogHandler {
-    pub emit: Box<dyn Fn(String) + Send + Sync>,
+    pub emit: Arc<dyn Fn(String) + Send + Sync>,
 }
 
 impl LogHandler {
     pub fn new(handler: impl Fn(String) + Send + Sync + 'static) -> LogHandler {
         LogHandler {
-            emit: Box::new(handler),
+            emit: Arc::new(handler),
         }
     }
 }
 
-#[derive(Component)]
+/// Entity has been destroyed and will be despawned
+#[derive(Component, Clone, Debug, Default)]
+pub struct Destroyed;
+
+#[derive(Component, Clone, Debug, Default)]
 pub struct ConstructionPaused;
 
 /// Indicates entities which are currently executing
-#[derive(Component)]
+#[derive(Component, Clone, Debug, Default)]
 pub struct Executing;
 
 /// Indicates entities which will begin executing following construction
-#[derive(Component)]
+#[derive(Component, Clone, Debug, Default)]
 pub struct WillExecuteOnConstruct;
 
 /// Entity produces resources
-#[derive(Component)]
+#[derive(Component, Clone, Debug, Serialize, Deserialize)]
 pub struct ResourceProducer {
     /// mass produced per tick
     pub mass_yield: f64,
@@ -104,10 +308,31 @@ impl Default for ResourceProducer {
     }
 }
 
+/// Resource allocation tier, higher tiers are served before lower tiers
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum ConsumerPriority {
+    High = 0,
+    #[default]
+    Normal = 1,
+    Low = 2,
+}
+
+impl ConsumerPriority {
+    pub const COUNT: usize = 3;
+    /// all tiers in allocation order
+    pub const ALL: [ConsumerPriority; ConsumerPriority::COUNT] = [
+        ConsumerPriority::High,
+        ConsumerPriority::Normal,
+        ConsumerPriority::Low,
+    ];
+}
+
 /// Entity consumes resources
-/// TODO: refactor this: units declare resource consumption, stall ratio
-/// calculated, then units pull resources as necessary instead of allocations
-#[derive(Component)]
+///
+/// Units declare how much they want in `*_request`, the economy computes a
+/// stall ratio for each priority tier, then units pull what they use from
+/// storage and record it in `*_consumed`.
+#[derive(Component, Clone, Debug, Serialize, Deserialize)]
 pub struct ResourceConsumer {
     /// how much mass the entity wants
     pub mass_request: f64,
@@ -117,6 +342,12 @@ pub struct ResourceConsumer {
     pub mass_consumed: f64,
     /// how much  energy the entity actually consumed
     pub energy_consumed: f64,
+    /// allocation tier when resources are stalled
+    pub priority: ConsumerPriority,
+    /// total mass consumed
+    pub total_mass_consumed: f64,
+    /// total energy consumed
+    pub total_energy_consumed: f64,
 }
 
 impl Default for ResourceConsumer {
@@ -126,16 +357,21 @@ impl Default for ResourceConsumer {
             energy_request: 0.0,
             mass_consumed: 0.0,
             energy_consumed: 0.0,
+            priority: ConsumerPriority::default(),
+            total_mass_consumed: 0.0,
+            total_energy_consumed: 0.0,
         }
     }
 }
 
 /// Entity can be damaged
-#[derive(Component)]
+#[derive(Component, Clone, Debug, Serialize, Deserialize)]
 pub struct Damage {
-    /// health as a fraction (0.0 = dead, 1.0 = full health)
+    /// construction progress as a fraction (1.0 = finished)
+    pub build_progress: f64,
+    /// current health points
     pub health: f64,
-    /// total health points of unit
+    /// maximum health points of unit
     pub health_points: u64,
     /// total mass cost of unit
     pub mass_total: f64,
@@ -145,17 +381,59 @@ pub struct Damage {
     pub build_time: f64,
 }
 
+impl Damage {
+    /// health as a fraction (0.0 = dead, 1.0 = full health)
+    pub fn health_fraction(&self) -> f64 {
+        if self.health_points == 0 {
+            return 1.0;
+        }
+        self.health / self.health_points as f64
+    }
+
+    /// construction has finished
+    pub fn is_finished(&self) -> bool {
+        self.build_progress >= 1.0
+    }
+
+    /// unit is finished but below maximum health
+    pub fn is_damaged(&self) -> bool {
+        self.is_finished() && self.health < self.health_points as f64
+    }
+
+    /// apply construction progress, health grows along with it
+    pub fn add_build_progress(&mut self, portion: f64) {
+        let remaining = 1.0 - self.build_progress;
+        let portion = f64::min(portion, remaining);
+        // exactly finished once what is left is covered, whatever the rounding
+        self.build_progress = if portion >= remaining {
+            1.0
+        } else {
+            self.build_progress + portion
+        };
+        self.health = f64::min(
+            self.health_points as f64,
+            self.health + portion * self.health_points as f64,
+        );
+    }
+
+    /// mark construction as finished
+    pub fn finish_construction(&mut self) {
+        self.add_build_progress(1.0 - self.build_progress);
+    }
+}
+
 /// Entity has an engineering suite (can build stuff)
-#[derive(Component)]
+#[derive(Component, Clone, Debug, Serialize, Deserialize)]
 pub struct Engineering {
     /// how fast this unit can build (build_time per tick)
     pub build_rate: f64,
 }
 
 /// Entity is currently constructing another entity
-#[derive(Component)]
+#[derive(Component, Clone, Debug, Serialize, Deserialize)]
 pub struct Constructing {
     /// entity currently being constructed
+    #[serde(with = "crate::snapshot::entity_bits")]
     pub target: Entity,
     /// mass requested for construction
     pub mass_requested: f64,
@@ -169,6 +447,27 @@ pub struct Constructing {
     pub build_amount: f64,
 }
 
+impl Constructing {
+    pub fn new(target: Entity) -> Self {
+        Constructing {
+            target,
+            mass_requested: 0.0,
+            energy_requested: 0.0,
+            mass_consumption_multiplier: 1.0,
+            energy_consumption_multiplier: 1.0,
+            build_amount: 0.0,
+        }
+    }
+}
+
+/// Entity assists another entity, constructing whatever it is constructing
+#[derive(Component, Clone, Debug, Serialize, Deserialize)]
+pub struct Assisting {
+    /// entity being assisted
+    #[serde(with = "crate::snapshot::entity_bits")]
+    pub target: Entity,
+}
+
 // systems
 /// update tick counter
 pub fn count_tick(mut tick_counter: ResMut<CurrentTick>) {
@@ -177,73 +476,92 @@ pub fn count_tick(mut tick_counter: ResMut<CurrentTick>) {
 
 /// resource production accounting
 pub fn economy_resource_producers(
-    mut query: Query<&mut ResourceProducer, With<Executing>>,
-    mut economy: ResMut<Economy>,
+    mut query: Query<(&Army, &mut ResourceProducer, Option<&AdjacencyBonus>), With<Executing>>,
+    mut economies: ResMut<Economies>,
 ) {
-    let mut total_mass = 0.0;
-    let mut total_energy = 0.0;
-    for mut producer in &mut query {
-        total_mass += producer.mass_yield;
-        total_energy += producer.energy_yield;
-        producer.total_mass += producer.mass_yield;
-        producer.total_energy += producer.energy_yield;
+    for (_, economy) in economies.iter_mut() {
+        economy.mass_produced = 0.0;
+        economy.energy_produced = 0.0;
+    }
+    for (army, mut producer, adjacency_bonus) in &mut query {
+        let (mass_yield, energy_yield) = match adjacency_bonus {
+            Some(bonus) => (
+                producer.mass_yield * bonus.mass_production_multiplier,
+                producer.energy_yield * bonus.energy_production_multiplier,
+            ),
+            None => (producer.mass_yield, producer.energy_yield),
+        };
+        producer.total_mass += mass_yield;
+        producer.total_energy += energy_yield;
+        let economy = economies.get_or_default(*army);
+        economy.mass += mass_yield;
+        economy.energy += energy_yield;
+        economy.mass_produced += mass_yield;
+        economy.energy_produced += energy_yield;
     }
-    economy.mass += total_mass;
-    economy.energy += total_energy;
-    economy.mass_produced = total_mass;
-    economy.energy_produced = total_energy;
 }
 
 pub fn economy_process_resource_requests(
-    query: Query<&mut ResourceConsumer, With<Executing>>,
-    mut economy: ResMut<Economy>,
+    query: Query<(&Army, &ResourceConsumer), With<Executing>>,
+    mut economies: ResMut<Economies>,
 ) {
-    let mut total_mass_requested = 0.0;
-    let mut total_energy_requested = 0.0;
-    for consumer in &query {
-        total_mass_requested += consumer.mass_request;
-        total_energy_requested += consumer.energy_request;
+    type TierRequests = [f64; ConsumerPriority::COUNT];
+    let mut requests: BTreeMap<Army, (TierRequests, TierRequests)> = economies
+        .armies()
+        .map(|army| (army, Default::default()))
+        .collect();
+    for (army, consumer) in &query {
+        let (mass_requested, energy_requested) = requests.entry(*army).or_default();
+        mass_requested[consumer.priority as usize] += consumer.mass_request;
+        energy_requested[consumer.priority as usize] += consumer.energy_request;
     }
 
-    economy.mass_stall = f64::min(1.0, economy.mass / total_mass_requested);
-    economy.energy_stall = f64::min(1.0, economy.energy / total_energy_requested);
-    economy.mass_requested = total_mass_requested;
-    economy.energy_requested = total_energy_requested;
+    for (army, (mass_requested, energy_requested)) in requests {
+        economies
+            .get_or_default(army)
+            .allocate(mass_requested, energy_requested);
+    }
 }
 
 pub fn economy_process_resource_consumption(
-    mut query: Query<&mut ResourceConsumer, With<Executing>>,
-    mut economy: ResMut<Economy>,
-    current_tick: Res<CurrentTick>,
-    log_handler: Res<LogHandler>,
+    mut query: Query<(&Army, &mut ResourceConsumer), With<Executing>>,
+    mut economies: ResMut<Economies>,
 ) {
-    let mut total_mass_consumed = 0.0;
-    let mut total_energy_consumed = 0.0;
-    for mut consumer in &mut query {
-        total_mass_consumed += consumer.mass_consumed;
-        total_energy_consumed += consumer.energy_consumed;
+    for (_, economy) in economies.iter_mut() {
+        economy.mass_consumed = 0.0;
+        economy.energy_consumed = 0.0;
+    }
+    for (army, mut consumer) in &mut query {
+        let economy = economies.get_or_default(*army);
+        economy.mass_consumed += consumer.mass_consumed;
+        economy.energy_consumed += consumer.energy_consumed;
+        consumer.total_mass_consumed += consumer.mass_consumed;
+        consumer.total_energy_consumed += consumer.energy_consumed;
         consumer.mass_consumed = 0.0;
         consumer.energy_consumed = 0.0;
         consumer.mass_request = 0.0;
         consumer.energy_request = 0.0;
     }
 
-    economy.mass = f64::min(economy.mass_capacity, economy.mass - total_mass_consumed);
-    economy.energy = f64::min(
-        economy.energy_capacity,
-        economy.energy - total_energy_consumed,
-    );
-    economy.mass_consumed = total_mass_consumed;
-    economy.energy_consumed = total_energy_consumed;
-
-    if economy.mass < -1.0 || economy.energy < -1.0 {
-        (log_handler.emit)(format!(
-            "tick {}: warn: overconsumption, mass {} energy {}",
-            current_tick.0, economy.mass, economy.energy
-        ));
+    // consumed resources were already pulled from storage
+    for (_, economy) in economies.iter_mut() {
+        economy.mass_overflow = f64::max(0.0, economy.mass - economy.mass_capacity);
+        economy.energy_overflow = f64::max(0.0, economy.energy - economy.energy_capacity);
+        economy.mass = f64::min(economy.mass_capacity, economy.mass);
+        economy.energy = f64::min(economy.energy_capacity, economy.energy);
+        // overflow is lost unless shared with allies afterwards
+        economy.mass_shared = 0.0;
+        economy.energy_shared = 0.0;
+        economy.mass_received = 0.0;
+        economy.energy_received = 0.0;
+        economy.mass_wasted = economy.mass_overflow;
+        economy.energy_wasted = economy.energy_overflow;
+        economy.total_mass_wasted += economy.mass_wasted;
+        economy.total_energy_wasted += economy.energy_wasted;
     }
 }
 
+#[allow(clippy::type_complexity)]
 pub fn execute_on_finished_construction(
     query: Query<
         (Entity, &Damage),
@@ -256,13 +574,63 @@ pub fn execute_on_finished_construction(
     mut commands: Commands,
 ) {
     for (entity, damage) in &query {
-        if damage.health == 1.0 {
+        if damage.is_finished() {
             commands.entity(entity).remove::<WillExecuteOnConstruct>();
             commands.entity(entity).insert(Executing);
         }
     }
 }
 
+/// point assisting entities at the current construction target of the assisted entity
+#[allow(clippy::type_complexity)]
+pub fn resolve_assist(
+    mut param_set: ParamSet<(
+        Query<(Entity, &Assisting, Option<&Constructing>), With<Executing>>,
+        Query<&Constructing, (With<Executing>, Without<ConstructionPaused>)>,
+    )>,
+    entities: Query<Entity>,
+    mut commands: Commands,
+) {
+    let assists: Vec<(Entity, Entity, Option<Entity>)> = param_set
+        .p0()
+        .iter()
+        .map(|(entity, assisting, constructing)| {
+            (
+                entity,
+                assisting.target,
+                constructing.map(|constructing| constructing.target),
+            )
+        })
+        .collect();
+    let assisted_query = param_set.p1();
+    for (entity, assisted, current_target) in assists {
+        if !entities.contains(assisted) {
+            // assisted entity gone, stop assisting
+            commands
+                .entity(entity)
+                .remove::<Assisting>()
+                .remove::<Constructing>();
+            continue;
+        }
+        let new_target = assisted_query
+            .get(assisted)
+            .ok()
+            .map(|constructing| constructing.target);
+        if new_target == current_target {
+            continue;
+        }
+        match new_target {
+            Some(target) => {
+                commands.entity(entity).insert(Constructing::new(target));
+            }
+            None => {
+                commands.entity(entity).remove::<Constructing>();
+            }
+        }
+    }
+}
+
+#[allow(clippy::type_complexity)]
 pub fn do_construct_resources_request(
     mut construct_query: Query<
         (
@@ -270,14 +638,21 @@ pub fn do_construct_resources_request(
             &mut Constructing,
             &Engineering,
             &mut ResourceConsumer,
+            Option<&AdjacencyBonus>,
         ),
         (With<Executing>, Without<ConstructionPaused>),
     >,
     mut target_query: Query<&mut Damage>,
     mut commands: Commands,
 ) {
-    for (entity, mut constructing, engineering, mut resource_consumer) in &mut construct_query {
+    for (entity, mut constructing, engineering, mut resource_consumer, adjacency_bonus) in
+        &mut construct_query
+    {
         if let Ok(target_damage) = target_query.get_mut(constructing.target) {
+            if let Some(bonus) = adjacency_bonus {
+                constructing.mass_consumption_multiplier = bonus.mass_consumption_multiplier;
+                constructing.energy_consumption_multiplier = bonus.energy_consumption_multiplier;
+            }
             let build_amount = engineering.build_rate / target_damage.build_time;
             constructing.build_amount = build_amount;
             constructing.mass_requested =
@@ -294,58 +669,67 @@ pub fn do_construct_resources_request(
     }
 }
 
+#[allow(clippy::type_complexity)]
 pub fn do_construct(
     mut construct_query: Query<
-        (Entity, &Constructing, &mut ResourceConsumer),
+        (Entity, &Army, &Constructing, &mut ResourceConsumer),
         (With<Executing>, Without<ConstructionPaused>),
     >,
     mut target_query: Query<&mut Damage>,
     mut commands: Commands,
-    economy: Res<Economy>,
+    mut economies: ResMut<Economies>,
 ) {
-    for (entity, constructing, mut resource_consumer) in &mut construct_query {
+    for (entity, army, constructing, mut resource_consumer) in &mut construct_query {
+        let economy = economies.get_or_default(*army);
         if let Ok(mut target_damage) = target_query.get_mut(constructing.target) {
             // if target is done constructing, remove constructing component
-            if target_damage.health >= 1.0 {
-                // greater should never happen
+            if target_damage.is_finished() {
+                // finished targets are repaired, not constructed
                 commands.entity(entity).remove::<Constructing>();
                 continue;
             }
             // determine resource usage
             // resources available to use
-            let mass_available = constructing.mass_requested * economy.mass_stall
+            let mass_available = constructing.mass_requested
+                * economy.mass_stall_for(resource_consumer.priority)
                 / constructing.mass_consumption_multiplier;
-            let energy_available = constructing.energy_requested * economy.energy_stall
+            let energy_available = constructing.energy_requested
+                * economy.energy_stall_for(resource_consumer.priority)
                 / constructing.energy_consumption_multiplier;
-            // determine resource bottleneck
+            // cost of one full unit of progress
+            let mass_cost = target_damage.mass_total * constructing.mass_consumption_multiplier;
+            let energy_cost =
+                target_damage.energy_total * constructing.energy_consumption_multiplier;
+            // determine resource bottleneck, never exceeding what is left in storage
             let min_portion = f64::min(
-                mass_available / target_damage.mass_total,
-                energy_available / target_damage.energy_total,
+                f64::min(
+                    mass_available / target_damage.mass_total,
+                    energy_available / target_damage.energy_total,
+                ),
+                f64::min(
+                    f64::max(0.0, economy.mass) / mass_cost,
+                    f64::max(0.0, economy.energy) / energy_cost,
+                ),
             );
-            // calculate total used
-            let mass_used =
-                min_portion * target_damage.mass_total * constructing.mass_consumption_multiplier;
-            let energy_used = min_portion
-                * target_damage.energy_total
-                * constructing.energy_consumption_multiplier;
+            // clamp to what is left of the target
+            let finished = target_damage.build_progress + min_portion >= 1.0;
+            let portion = if finished {
+                1.0 - target_damage.build_progress
+            } else {
+                min_portion
+            };
+
+            // pull resources actually used
+            resource_consumer.mass_consumed += economy.pull_mass(portion * mass_cost);
+            resource_consumer.energy_consumed += economy.pull_energy(portion * energy_cost);
 
-            if target_damage.health + min_portion >= 1.0 {
-                // allocation would overflow target total mass/energy cost
-                let mass_remaining = (1.0 - target_damage.health) * target_damage.mass_total;
-                let energy_remaining = (1.0 - target_damage.health) * target_damage.energy_total;
-                resource_consumer.mass_consumed +=
-                    mass_remaining * constructing.mass_consumption_multiplier;
-                resource_consumer.energy_consumed +=
-                    energy_remaining * constructing.energy_consumption_multiplier;
+            if finished {
                 // target is done
-                target_damage.health = 1.0;
+                target_damage.finish_construction();
                 commands.entity(entity).remove::<Constructing>();
             } else {
-                // update resource consumption
-                resource_consumer.mass_consumed += mass_used;
-                resource_consumer.energy_consumed += energy_used;
                 // apply construction progress
-                target_damage.health += min_portion;
+                target_damage.add_build_progress(portion);
             }
         }
     }
@@ -356,45 +740,489 @@ pub struct FASimulation {
     pub update_schedule: Schedule,
 }
 
+impl Default for FASimulation {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl FASimulation {
     pub fn new() -> Self {
         let mut world = World::new();
 
         // resources
         world.insert_resource(CurrentTick(0));
-        world.insert_resource(Economy {
+        world.insert_resource(Economies::single(Economy {
             mass_capacity: 4000.0,
             energy_capacity: 100000.0,
             ..Default::default()
-        });
+        }));
         world.insert_resource(LogHandler::new(|message| println!("{}", message)));
+        world.insert_resource(UnitRegistry::default());
+        world.insert_resource(Alliances::default());
+        world.insert_resource(Events::<UnitRolledOff>::default());
+        world.insert_resource(Triggers::default());
 
-        // schedule and stages
+        FASimulation {
+            world,
+            update_schedule: FASimulation::base_schedule(),
+        }
+    }
+
+    /// update schedule run once per tick, which other simulations extend with
+    /// their own systems
+    pub fn base_schedule() -> Schedule {
         let mut schedule = Schedule::default();
-        let tick_stage = SystemStage::single_threaded().with_system(count_tick);
+        let tick_stage = SystemStage::single_threaded()
+            .with_system(count_tick)
+            .with_system(Events::<UnitRolledOff>::update_system);
+        let unit_spawn_stage = SystemStage::parallel().with_system(factory_production);
+        let order_stage = SystemStage::parallel()
+            .with_system(resolve_assist)
+            .with_system(finish_upgrades)
+            .with_system(start_upgrades);
+        // after commands of orders have been applied, which may target destroyed units
+        let destruction_stage = SystemStage::parallel().with_system(destroy_units);
         let update_stage = SystemStage::parallel()
             .with_system(execute_on_finished_construction)
-            .with_system(do_construct_resources_request);
+            .with_system(update_adjacency_bonus.before(do_construct_resources_request))
+            .with_system(do_construct_resources_request)
+            .with_system(do_repair_resources_request.after(do_construct_resources_request))
+            .with_system(mass_fabricator_request);
         let economy_request_stage = SystemStage::parallel()
             .with_system(economy_resource_producers)
+            .with_system(
+                do_reclaim
+                    .after(economy_resource_producers)
+                    .before(economy_process_resource_requests),
+            )
             .with_system(economy_process_resource_requests.after(economy_resource_producers));
-        let resource_usage_stage = SystemStage::parallel().with_system(do_construct);
-        let economy_accounting_stage =
-            SystemStage::parallel().with_system(economy_process_resource_consumption);
+        let resource_usage_stage = SystemStage::parallel()
+            .with_system(do_construct)
+            .with_system(do_repair.after(do_construct))
+            .with_system(mass_fabricator_convert.after(do_repair));
+        let economy_accounting_stage = SystemStage::parallel()
+            .with_system(economy_process_resource_consumption)
+            .with_system(share_overflow.after(economy_process_resource_consumption));
+        let trigger_stage =
+            SystemStage::single_threaded().with_system(evaluate_triggers.exclusive_system());
+        let recording_stage = SystemStage::single_threaded().with_system(record_state);
 
         schedule.add_stage("tick count", tick_stage);
+        schedule.add_stage("unit spawning", unit_spawn_stage);
+        schedule.add_stage("orders", order_stage);
+        schedule.add_stage("destruction", destruction_stage);
         schedule.add_stage("update", update_stage);
         schedule.add_stage("economy request", economy_request_stage);
         schedule.add_stage("resource usage", resource_usage_stage);
         schedule.add_stage("economy accounting", economy_accounting_stage);
+        schedule.add_stage("triggers", trigger_stage);
+        schedule.add_stage("recording", recording_stage);
+        schedule
+    }
+
+    pub fn run(&mut self) {
+        self.update_schedule.run(&mut self.world);
+    }
+
+    /// add or replace the economy of an army
+    pub fn add_army(&mut self, army: Army, economy: Economy) {
+        self.world.resource_mut::<Economies>().insert(army, economy);
+    }
+
+    pub fn economy(&self, army: Army) -> Option<&Economy> {
+        self.world.resource::<Economies>().get(army)
+    }
+
+    /// spawn an unbuilt unit by blueprint id
+    pub fn spawn_unit(&mut self, id: &str, army: Army) -> Option<Entity> {
+        issue(
+            &mut self.world,
+            Order::SpawnUnit {
+                unit_id: id.to_string(),
+                army,
+                position: None,
+            },
+        )
+    }
+}
+
+/// World driven by an update schedule, one run per tick
+pub trait Simulation {
+    fn world(&self) -> &World;
+    fn world_mut(&mut self) -> &mut World;
+    /// run one tick
+    fn run(&mut self);
+}
+
+impl Simulation for FASimulation {
+    fn world(&self) -> &World {
+        &self.world
+    }
+
+    fn world_mut(&mut self) -> &mut World {
+        &mut self.world
+    }
+
+    fn run(&mut self) {
+        FASimulation::run(self)
+    }
+}
+
+impl Snapshots for FASimulation {
+    fn components() -> Vec<SnapshotComponent> {
+        FA_COMPONENTS.to_vec()
+    }
+
+    fn kind() -> SimulationKind {
+        SimulationKind::Fa
+    }
 
+    fn from_world(world: World) -> Self {
         FASimulation {
             world,
-            update_schedule: schedule,
+            update_schedule: FASimulation::base_schedule(),
         }
     }
+}
 
-    pub fn run(&mut self) {
-        self.update_schedule.run(&mut self.world);
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// simulation with the sample blueprints registered and no log output
+    pub(crate) fn test_simulation() -> FASimulation {
+        let mut sim = FASimulation::new();
+        sim.world.insert_resource(LogHandler::new(|_| {}));
+        sim.world
+            .resource_mut::<UnitRegistry>()
+            .load_directory(concat!(env!("CARGO_MANIFEST_DIR"), "/blueprints"))
+            .expect("sample blueprints are valid");
+        sim
+    }
+
+    /// spawn a finished unit which is executing
+    pub(crate) fn spawn_built(sim: &mut FASimulation, id: &str, army: Army) -> Entity {
+        let entity = sim.spawn_unit(id, army).expect("unit is registered");
+        let mut entity_mut = sim.world.entity_mut(entity);
+        entity_mut
+            .get_mut::<Damage>()
+            .unwrap()
+            .finish_construction();
+        entity_mut.remove::<WillExecuteOnConstruct>();
+        entity_mut.insert(Executing);
+        entity
+    }
+
+    /// spawn an executing builder with build rate per second
+    pub(crate) fn spawn_builder(sim: &mut FASimulation, army: Army, build_rate: f64) -> Entity {
+        sim.world
+            .spawn()
+            .insert(army)
+            .insert(Executing)
+            .insert(Engineering {
+                build_rate: build_rate / TICK_RATE,
+            })
+            .insert(ResourceConsumer::default())
+            .id()
+    }
+
+    /// spawn an unbuilt target costing 900 mass, 5400 energy and 900 build time
+    pub(crate) fn spawn_target(sim: &mut FASimulation, army: Army) -> Entity {
+        sim.world
+            .spawn()
+            .insert(army)
+            .insert(Damage {
+                build_progress: 0.0,
+                health: 0.0,
+                health_points: 3000,
+                mass_total: 900.0,
+                energy_total: 5400.0,
+                build_time: 900.0,
+            })
+            .id()
+    }
+
+    /// start constructing target with builder
+    pub(crate) fn construct(sim: &mut FASimulation, builder: Entity, target: Entity) {
+        sim.world
+            .entity_mut(builder)
+            .insert(Constructing::new(target));
+    }
+
+    /// set the stored resources of an army
+    pub(crate) fn set_stored(sim: &mut FASimulation, army: Army, mass: f64, energy: f64) {
+        let mut economies = sim.world.resource_mut::<Economies>();
+        let economy = economies.get_or_default(army);
+        economy.mass = mass;
+        economy.energy = energy;
+    }
+
+    #[test]
+    fn allocate_serves_higher_tiers_first() {
+        let mut economy = Economy {
+            mass: 100.0,
+            energy: 1000.0,
+            ..Default::default()
+        };
+        economy.allocate([60.0, 80.0, 20.0], [100.0, 0.0, 400.0]);
+        // high gets all it asked for, normal splits what is left, low gets nothing
+        assert_eq!(economy.mass_stall_for(ConsumerPriority::High), 1.0);
+        assert_eq!(economy.mass_stall_for(ConsumerPriority::Normal), 0.5);
+        assert_eq!(economy.mass_stall_for(ConsumerPriority::Low), 0.0);
+        assert_eq!(economy.energy_stall_for(ConsumerPriority::High), 1.0);
+        assert_eq!(economy.energy_stall_for(ConsumerPriority::Low), 1.0);
+        // overall ratio ignores tiers
+        assert_eq!(economy.mass_stall, 100.0 / 160.0);
+        assert_eq!(economy.energy_stall, 1.0);
+        assert_eq!(economy.mass_requested, 160.0);
+        assert_eq!(economy.energy_requested, 500.0);
+    }
+
+    #[test]
+    fn allocate_with_empty_storage() {
+        let mut economy = Economy {
+            mass: -5.0,
+            ..Default::default()
+        };
+        economy.allocate([10.0, 10.0, 10.0], [0.0; ConsumerPriority::COUNT]);
+        for priority in ConsumerPriority::ALL {
+            assert_eq!(economy.mass_stall_for(priority), 0.0);
+        }
+    }
+
+    #[test]
+    fn stalled_builders_are_served_by_priority() {
+        let mut sim = test_simulation();
+        set_stored(&mut sim, Army(0), 10.0, 100000.0);
+        let high = spawn_builder(&mut sim, Army(0), 100.0);
+        let low = spawn_builder(&mut sim, Army(0), 100.0);
+        sim.world
+            .get_mut::<ResourceConsumer>(high)
+            .unwrap()
+            .priority = ConsumerPriority::High;
+        sim.world.get_mut::<ResourceConsumer>(low).unwrap().priority = ConsumerPriority::Low;
+        let high_target = spawn_target(&mut sim, Army(0));
+        let low_target = spawn_target(&mut sim, Army(0));
+        construct(&mut sim, high, high_target);
+        construct(&mut sim, low, low_target);
+        sim.run();
+
+        // each wants 100 / 10 / 900 * 900 = 10 mass this tick, only 10 is stored
+        let economy = sim.economy(Army(0)).unwrap();
+        assert_eq!(economy.mass_stall_for(ConsumerPriority::High), 1.0);
+        assert_eq!(economy.mass_stall_for(ConsumerPriority::Low), 0.0);
+        let progress = |entity| sim.world.get::<Damage>(entity).unwrap().build_progress;
+        assert!((progress(high_target) - 10.0 / 900.0).abs() < 1e-9);
+        assert_eq!(progress(low_target), 0.0);
+    }
+
+    #[test]
+    fn pull_is_limited_to_storage() {
+        let mut economy = Economy {
+            mass: 5.0,
+            energy: -1.0,
+            ..Default::default()
+        };
+        assert_eq!(economy.pull_mass(3.0), 3.0);
+        assert_eq!(economy.pull_mass(3.0), 2.0);
+        assert_eq!(economy.mass, 0.0);
+        assert_eq!(economy.pull_mass(-1.0), 0.0);
+        assert_eq!(economy.pull_energy(1.0), 0.0);
+        assert_eq!(economy.energy, -1.0);
+    }
+
+    #[test]
+    fn builders_never_draw_storage_below_zero() {
+        let mut sim = test_simulation();
+        set_stored(&mut sim, Army(0), 50.0, 500.0);
+        sim.world
+            .spawn()
+            .insert(Army(0))
+            .insert(Executing)
+            .insert(ResourceProducer {
+                mass_yield: 0.7,
+                energy_yield: 9.0,
+                ..Default::default()
+            });
+        let target = spawn_target(&mut sim, Army(0));
+        for _ in 0..5 {
+            let builder = spawn_builder(&mut sim, Army(0), 30.0);
+            construct(&mut sim, builder, target);
+        }
+        let mut consumed = 0.0;
+        for _ in 0..200 {
+            sim.run();
+            let economy = sim.economy(Army(0)).unwrap();
+            assert!(economy.mass >= 0.0, "mass {}", economy.mass);
+            assert!(economy.energy >= 0.0, "energy {}", economy.energy);
+            consumed += economy.mass_consumed;
+        }
+
+        // everything produced is either stored or consumed
+        let economy = sim.economy(Army(0)).unwrap();
+        assert!((50.0 + 200.0 * 0.7 - consumed - economy.mass).abs() < 1e-6);
+        let progress = sim.world.get::<Damage>(target).unwrap().build_progress;
+        assert!((progress - consumed / 900.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn armies_have_separate_economies() {
+        let mut sim = test_simulation();
+        sim.add_army(Army(1), Economy::default());
+        for army in [Army(0), Army(1)] {
+            sim.world
+                .spawn()
+                .insert(army)
+                .insert(Executing)
+                .insert(ResourceProducer {
+                    mass_yield: 1.0 + army.0 as f64,
+                    energy_yield: 0.0,
+                    ..Default::default()
+                });
+        }
+        sim.run();
+        assert_eq!(sim.economy(Army(0)).unwrap().mass_produced, 1.0);
+        assert_eq!(sim.economy(Army(1)).unwrap().mass_produced, 2.0);
+        assert_eq!(sim.economy(Army(1)).unwrap().mass, 2.0);
+    }
+
+    #[test]
+    fn overflow_without_allies_is_wasted() {
+        let mut sim = test_simulation();
+        sim.add_army(
+            Army(0),
+            Economy {
+                mass: 95.0,
+                mass_capacity: 100.0,
+                energy_capacity: 1000.0,
+                ..Default::default()
+            },
+        );
+        sim.world
+            .spawn()
+            .insert(Army(0))
+            .insert(Executing)
+            .insert(ResourceProducer {
+                mass_yield: 2.0,
+                energy_yield: 1.0,
+                ..Default::default()
+            });
+        for _ in 0..10 {
+            sim.run();
+        }
+        let economy = sim.economy(Army(0)).unwrap();
+        assert_eq!(economy.mass, 100.0);
+        assert!((economy.mass_overflow - 2.0).abs() < 1e-9);
+        assert!((economy.mass_wasted - 2.0).abs() < 1e-9);
+        assert!((economy.total_mass_wasted - 15.0).abs() < 1e-9);
+        // energy storage never filled
+        assert_eq!(economy.total_energy_wasted, 0.0);
+        assert!((economy.energy - 10.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn assist_follows_target_with_accounting_per_builder() {
+        let mut sim = test_simulation();
+        set_stored(&mut sim, Army(0), 4000.0, 100000.0);
+        let builder = spawn_builder(&mut sim, Army(0), 10.0);
+        let first = spawn_target(&mut sim, Army(0));
+        construct(&mut sim, builder, first);
+        let engineer = spawn_builder(&mut sim, Army(0), 30.0);
+        sim.world
+            .entity_mut(engineer)
+            .insert(Assisting { target: builder });
+        let target = |sim: &FASimulation, entity| {
+            sim.world
+                .get::<Constructing>(entity)
+                .map(|constructing| constructing.target)
+        };
+        let consumed = |sim: &FASimulation, entity| {
+            sim.world
+                .get::<ResourceConsumer>(entity)
+                .unwrap()
+                .total_mass_consumed
+        };
+
+        for _ in 0..10 {
+            sim.run();
+        }
+        assert_eq!(target(&sim, engineer), Some(first));
+        // both worked on the unit since the first tick, the engineer 3 times as fast
+        let (builder_mass, engineer_mass) = (consumed(&sim, builder), consumed(&sim, engineer));
+        assert!((engineer_mass - 3.0 * builder_mass).abs() < 1e-9);
+        let progress = sim.world.get::<Damage>(first).unwrap().build_progress;
+        assert!((builder_mass + engineer_mass - progress * 900.0).abs() < 1e-9);
+
+        // engineer stops with the assisted builder and moves on along with it
+        for _ in 0..400 {
+            sim.run();
+            if target(&sim, builder).is_none() {
+                break;
+            }
+        }
+        assert!(sim.world.get::<Damage>(first).unwrap().is_finished());
+        sim.run();
+        assert_eq!(target(&sim, engineer), None);
+        let second = spawn_target(&mut sim, Army(0));
+        construct(&mut sim, builder, second);
+        sim.run();
+        assert_eq!(target(&sim, engineer), Some(second));
+
+        // stop assisting once the assisted entity is gone
+        sim.world.despawn(builder);
+        sim.run();
+        assert!(!sim.world.entity(engineer).contains::<Assisting>());
+        assert!(!sim.world.entity(engineer).contains::<Constructing>());
+    }
+
+    #[test]
+    fn build_progress_grows_health() {
+        let mut damage = Damage {
+            build_progress: 0.0,
+            health: 0.0,
+            health_points: 1000,
+            mass_total: 100.0,
+            energy_total: 1000.0,
+            build_time: 100.0,
+        };
+        damage.add_build_progress(0.25);
+        assert_eq!(damage.build_progress, 0.25);
+        assert_eq!(damage.health, 250.0);
+        assert!(!damage.is_finished());
+        assert!(!damage.is_damaged());
+
+        // progress never exceeds what is left, health never exceeds its maximum
+        damage.health = 900.0;
+        damage.add_build_progress(2.0);
+        assert_eq!(damage.build_progress, 1.0);
+        assert_eq!(damage.health, 1000.0);
+        assert!(damage.is_finished());
+        assert!(!damage.is_damaged());
+
+        // covering what is left finishes exactly, whatever the rounding
+        damage.build_progress = 0.1 + 0.2;
+        damage.add_build_progress(0.7);
+        assert_eq!(damage.build_progress, 1.0);
+        assert!(damage.is_finished());
+
+        damage.health = 400.0;
+        assert!(damage.is_damaged());
+        assert_eq!(damage.health_fraction(), 0.4);
+    }
+
+    #[test]
+    fn damaged_units_keep_executing() {
+        let mut sim = test_simulation();
+        let extractor = spawn_built(&mut sim, "ueb1103", Army(0));
+        sim.world.get_mut::<Damage>(extractor).unwrap().health = 1.0;
+        for _ in 0..10 {
+            sim.run();
+        }
+        let entity = sim.world.entity(extractor);
+        assert!(entity.get::<Damage>().unwrap().is_damaged());
+        assert!(entity.contains::<Executing>());
+        let producer = entity.get::<ResourceProducer>().unwrap();
+        assert!((producer.total_mass - 10.0 * producer.mass_yield).abs() < 1e-9);
     }
 }
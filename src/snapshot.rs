@@ -0,0 +1,787 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use bevy_ecs::prelude::*;
+use bevy_ecs::system::Resource;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::adjacency::*;
+use crate::alliance::Alliances;
+use crate::blueprint::Blueprint;
+use crate::fabricator::MassFabricator;
+use crate::factory::{Factory, UnitRolledOff};
+use crate::reclaim::*;
+use crate::recorder::Recorder;
+use crate::registry::{UnitId, UnitRegistry};
+use crate::repair::Repairing;
+use crate::replay::{start_order_log, OrderLog};
+use crate::scenario::ScenarioNames;
+use crate::simulation::*;
+use crate::trigger::Triggers;
+use crate::upgrade::*;
+
+/// Component type making up simulation state, with how to copy it between
+/// worlds and how to write it to snapshot files
+#[derive(Clone, Copy)]
+pub struct SnapshotComponent {
+    /// key of the component in snapshot files
+    pub name: &'static str,
+    copy: fn(&World, Entity, &mut World),
+    save: fn(&World, Entity) -> Option<Result<toml::Value, toml::ser::Error>>,
+    load: fn(&mut World, Entity, toml::Value) -> Result<(), toml::de::Error>,
+}
+
+impl SnapshotComponent {
+    /// component saved with all its fields
+    pub const fn new<T: Component + Clone + Serialize + DeserializeOwned>(
+        name: &'static str,
+    ) -> Self {
+        SnapshotComponent {
+            name,
+            copy: copy_component::<T>,
+            save: save_component::<T>,
+            load: load_component::<T>,
+        }
+    }
+
+    /// marker component without fields, saved as `true`
+    pub const fn marker<T: Component + Clone + Default>(name: &'static str) -> Self {
+        SnapshotComponent {
+            name,
+            copy: copy_component::<T>,
+            save: save_marker::<T>,
+            load: load_marker::<T>,
+        }
+    }
+}
+
+fn copy_component<T: Component + Clone>(source: &World, entity: Entity, target: &mut World) {
+    if let Some(component) = source.get::<T>(entity) {
+        target.entity_mut(entity).insert(component.clone());
+    }
+}
+
+fn save_component<T: Component + Serialize>(
+    world: &World,
+    entity: Entity,
+) -> Option<Result<toml::Value, toml::ser::Error>> {
+    world.get::<T>(entity).map(toml::Value::try_from)
+}
+
+fn load_component<T: Component + DeserializeOwned>(
+    world: &mut World,
+    entity: Entity,
+    value: toml::Value,
+) -> Result<(), toml::de::Error> {
+    world.entity_mut(entity).insert(value.try_into::<T>()?);
+    Ok(())
+}
+
+fn save_marker<T: Component>(
+    world: &World,
+    entity: Entity,
+) -> Option<Result<toml::Value, toml::ser::Error>> {
+    world
+        .get::<T>(entity)
+        .map(|_| Ok(toml::Value::Boolean(true)))
+}
+
+fn load_marker<T: Component + Default>(
+    world: &mut World,
+    entity: Entity,
+    value: toml::Value,
+) -> Result<(), toml::de::Error> {
+    if value.try_into::<bool>()? {
+        world.entity_mut(entity).insert(T::default());
+    }
+    Ok(())
+}
+
+/// Resource type making up simulation state besides those every simulation
+/// has, with how to copy it between worlds and how to write it to snapshot
+/// files
+#[derive(Clone, Copy)]
+pub struct SnapshotResource {
+    /// key of the resource in snapshot files
+    pub name: &'static str,
+    copy: fn(&World, &mut World),
+    save: fn(&World) -> Option<Result<toml::Value, toml::ser::Error>>,
+    load: fn(&mut World, toml::Value) -> Result<(), toml::de::Error>,
+}
+
+impl SnapshotResource {
+    pub const fn new<T: Resource + Clone + Serialize + DeserializeOwned>(
+        name: &'static str,
+    ) -> Self {
+        SnapshotResource {
+            name,
+            copy: copy_resource::<T>,
+            save: save_resource::<T>,
+            load: load_resource::<T>,
+        }
+    }
+}
+
+fn copy_resource<T: Resource + Clone>(source: &World, target: &mut World) {
+    if let Some(resource) = source.get_resource::<T>() {
+        target.insert_resource(resource.clone());
+    }
+}
+
+fn save_resource<T: Resource + Serialize>(
+    world: &World,
+) -> Option<Result<toml::Value, toml::ser::Error>> {
+    world.get_resource::<T>().map(toml::Value::try_from)
+}
+
+fn load_resource<T: Resource + DeserializeOwned>(
+    world: &mut World,
+    value: toml::Value,
+) -> Result<(), toml::de::Error> {
+    world.insert_resource(value.try_into::<T>()?);
+    Ok(())
+}
+
+/// Components of FASimulation
+pub const FA_COMPONENTS: &[SnapshotComponent] = &[
+    SnapshotComponent::new::<Army>("army"),
+    SnapshotComponent::marker::<Destroyed>("destroyed"),
+    SnapshotComponent::marker::<ConstructionPaused>("construction_paused"),
+    SnapshotComponent::marker::<Executing>("executing"),
+    SnapshotComponent::marker::<WillExecuteOnConstruct>("will_execute_on_construct"),
+    SnapshotComponent::new::<ResourceProducer>("resource_producer"),
+    SnapshotComponent::new::<ResourceConsumer>("resource_consumer"),
+    SnapshotComponent::new::<Damage>("damage"),
+    SnapshotComponent::new::<Engineering>("engineering"),
+    SnapshotComponent::new::<Constructing>("constructing"),
+    SnapshotComponent::new::<Assisting>("assisting"),
+    SnapshotComponent::new::<UnitId>("unit_id"),
+    SnapshotComponent::new::<Footprint>("footprint"),
+    SnapshotComponent::new::<GridPosition>("grid_position"),
+    SnapshotComponent::new::<AdjacencyCategory>("adjacency_category"),
+    SnapshotComponent::new::<AdjacencyBonus>("adjacency_bonus"),
+    SnapshotComponent::new::<MassFabricator>("mass_fabricator"),
+    SnapshotComponent::new::<Factory>("factory"),
+    SnapshotComponent::new::<Reclaimable>("reclaimable"),
+    SnapshotComponent::new::<Wreck>("wreck"),
+    SnapshotComponent::new::<Reclaiming>("reclaiming"),
+    SnapshotComponent::new::<ReclaimTotals>("reclaim_totals"),
+    SnapshotComponent::new::<Repairing>("repairing"),
+    SnapshotComponent::new::<Upgrading>("upgrading"),
+    SnapshotComponent::new::<UpgradeProgress>("upgrade_progress"),
+];
+
+/// serde helpers for entity fields, stored as `Entity::to_bits`
+pub mod entity_bits {
+    use bevy_ecs::entity::Entity;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(entity: &Entity, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(entity.to_bits())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Entity, D::Error> {
+        Ok(Entity::from_bits(u64::deserialize(deserializer)?))
+    }
+}
+
+/// serde helpers for optional entity fields
+pub mod option_entity_bits {
+    use bevy_ecs::entity::Entity;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        entity: &Option<Entity>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match entity {
+            Some(entity) => serializer.serialize_some(&entity.to_bits()),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Entity>, D::Error> {
+        Ok(Option::<u64>::deserialize(deserializer)?.map(Entity::from_bits))
+    }
+}
+
+/// serde helpers for lists of entities
+pub mod entity_vec_bits {
+    use bevy_ecs::entity::Entity;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(entities: &[Entity], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(entities.iter().map(|entity| entity.to_bits()))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<Entity>, D::Error> {
+        let bits = Vec::<u64>::deserialize(deserializer)?;
+        Ok(bits.into_iter().map(Entity::from_bits).collect())
+    }
+}
+
+/// Simulation state at one tick, held in memory
+pub struct Snapshot {
+    pub(crate) world: World,
+}
+
+impl Snapshot {
+    pub fn tick(&self) -> u64 {
+        self.world.resource::<CurrentTick>().0
+    }
+}
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    Serialize(toml::ser::Error),
+    Parse(toml::de::Error),
+    /// component in the snapshot file which the simulation doesn't have
+    UnknownComponent(String),
+    /// resource in the snapshot file which the simulation doesn't have
+    UnknownResource(String),
+    /// entity id appearing twice in the snapshot file
+    DuplicateEntity(u64),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Io(err) => write!(f, "failed to access snapshot: {}", err),
+            SnapshotError::Serialize(err) => write!(f, "failed to write snapshot: {}", err),
+            SnapshotError::Parse(err) => write!(f, "invalid snapshot: {}", err),
+            SnapshotError::UnknownComponent(name) => {
+                write!(f, "snapshot has unknown component {}", name)
+            }
+            SnapshotError::UnknownResource(name) => {
+                write!(f, "snapshot has unknown resource {}", name)
+            }
+            SnapshotError::DuplicateEntity(id) => write!(f, "snapshot has entity {} twice", id),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl From<io::Error> for SnapshotError {
+    fn from(err: io::Error) -> Self {
+        SnapshotError::Io(err)
+    }
+}
+
+impl From<toml::ser::Error> for SnapshotError {
+    fn from(err: toml::ser::Error) -> Self {
+        SnapshotError::Serialize(err)
+    }
+}
+
+impl From<toml::de::Error> for SnapshotError {
+    fn from(err: toml::de::Error) -> Self {
+        SnapshotError::Parse(err)
+    }
+}
+
+/// Which simulation a scenario, replay or snapshot belongs to
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum SimulationKind {
+    /// plain FA units
+    #[default]
+    Fa,
+    /// RAS SACUs and paragon, with sacrifice
+    Ras,
+}
+
+/// Simulation whose state can be snapshot and restored
+///
+/// Triggers are part of snapshots held in memory, but hold closures so aren't
+/// saved to files. Recorders hold open files, so a fork doesn't record and
+/// restoring keeps recording to the simulation's recorder. A fork of a
+/// simulation logging orders logs to its own copy of the log, while restoring
+/// starts the log over since earlier orders don't lead to the restored state.
+pub trait Snapshots: Simulation + Sized {
+    /// component types making up the state of the simulation's entities
+    fn components() -> Vec<SnapshotComponent>;
+
+    /// resource types making up the state of the simulation besides those
+    /// every simulation has
+    fn resources() -> Vec<SnapshotResource> {
+        Vec::new()
+    }
+
+    /// which simulation this is, as named in scenario and replay files
+    fn kind() -> SimulationKind;
+
+    /// simulation with a new update schedule running world
+    fn from_world(world: World) -> Self;
+
+    fn snapshot(&self) -> Snapshot {
+        let mut world = World::new();
+        copy_world(
+            self.world(),
+            &mut world,
+            &Self::components(),
+            &Self::resources(),
+        );
+        Snapshot { world }
+    }
+
+    /// return to the state of a snapshot, still recording to the same
+    /// recorder if there is one
+    fn restore(&mut self, snapshot: &Snapshot) {
+        let recorder = self.world_mut().remove_resource::<Recorder>();
+        let logging = self.world().contains_resource::<OrderLog>();
+        *self = Self::from_world(World::new());
+        copy_world(
+            &snapshot.world,
+            self.world_mut(),
+            &Self::components(),
+            &Self::resources(),
+        );
+        if let Some(recorder) = recorder {
+            self.world_mut().insert_resource(recorder);
+        }
+        if logging {
+            start_order_log(self);
+        }
+    }
+
+    /// independent copy of the simulation at its current tick, which isn't
+    /// recorded
+    fn fork(&self) -> Self {
+        let mut fork = Self::from_world(World::new());
+        copy_world(
+            self.world(),
+            fork.world_mut(),
+            &Self::components(),
+            &Self::resources(),
+        );
+        if let Some(log) = self.world().get_resource::<OrderLog>() {
+            fork.world_mut().insert_resource(log.fork::<Self>());
+        }
+        fork
+    }
+
+    /// write the simulation's state besides its triggers to a file
+    fn save_snapshot(&self, path: impl AsRef<Path>) -> Result<(), SnapshotError> {
+        let file = SnapshotFile::new(self.world(), &Self::components(), &Self::resources())?;
+        fs::write(path, toml::to_string(&file)?)?;
+        Ok(())
+    }
+
+    /// return to the state saved in a file
+    ///
+    /// The log handler, recorder, order log and units registered in this
+    /// simulation are kept as when restoring, units registered when the
+    /// snapshot was saved are added. No
+    /// triggers are pending afterwards, so register them again.
+    fn load_snapshot(&mut self, path: impl AsRef<Path>) -> Result<(), SnapshotError> {
+        let file: SnapshotFile = toml::from_str(&fs::read_to_string(path)?)?;
+        let snapshot = file.into_snapshot(self.world(), &Self::components(), &Self::resources())?;
+        let recorder = self.world_mut().remove_resource::<Recorder>();
+        let logging = self.world().contains_resource::<OrderLog>();
+        *self = Self::from_world(snapshot.world);
+        if let Some(recorder) = recorder {
+            self.world_mut().insert_resource(recorder);
+        }
+        if logging {
+            start_order_log(self);
+        }
+        Ok(())
+    }
+}
+
+/// copy all entities and simulation resources of source into target, an empty
+/// world, keeping entity ids so components referring to other entities stay
+/// valid
+///
+/// Only the listed component and resource types are copied besides the
+/// resources every simulation has, and target gets no recorder. Despawned ids
+/// are kept free at their generations, so stale references don't resolve to
+/// new entities and entities spawned in source and target after the copy get
+/// the same ids.
+pub fn copy_world(
+    source: &World,
+    target: &mut World,
+    components: &[SnapshotComponent],
+    resources: &[SnapshotResource],
+) {
+    let mut entities: Vec<Entity> = source
+        .archetypes()
+        .iter()
+        .flat_map(|archetype| archetype.entities().iter().copied())
+        .collect();
+    // same archetype and table layout whatever order source was built in
+    entities.sort_unstable_by_key(|entity| entity.id());
+    for entity in entities {
+        target
+            .get_or_spawn(entity)
+            .expect("target world is not empty");
+        for component in components {
+            (component.copy)(source, entity, target);
+        }
+    }
+    free_entities(target, &despawned_entities(source));
+
+    target.insert_resource(CurrentTick(source.resource::<CurrentTick>().0));
+    target.insert_resource(source.resource::<Economies>().clone());
+    target.insert_resource(source.resource::<LogHandler>().clone());
+    target.insert_resource(source.resource::<UnitRegistry>().clone());
+    target.insert_resource(source.resource::<Alliances>().clone());
+    target.insert_resource(source.resource::<Triggers>().clone());
+    if let Some(names) = source.get_resource::<ScenarioNames>() {
+        target.insert_resource(names.clone());
+    }
+    for resource in resources {
+        (resource.copy)(source, target);
+    }
+
+    let source_events = source.resource::<Events<UnitRolledOff>>();
+    let mut events = Events::<UnitRolledOff>::default();
+    for event in source_events.get_reader().iter(source_events) {
+        events.send(*event);
+    }
+    target.insert_resource(events);
+}
+
+/// ids of despawned entities in a world, at the generation they'll be reused
+/// with
+fn despawned_entities(world: &World) -> Vec<Entity> {
+    let entities = world.entities();
+    (0..entities.meta_len() as u32)
+        .filter_map(|id| entities.resolve_from_id(id))
+        .filter(|entity| world.get_entity(*entity).is_none())
+        .collect()
+}
+
+/// free ids of despawned entities in a world with all its live entities
+/// spawned, so they're reused at the same generations
+///
+/// Ids are freed from lowest to highest whatever order they were despawned in,
+/// so worlds copied from each other reuse them in the same order. Ids which
+/// were never used are freed at generation 1.
+fn free_entities(world: &mut World, despawned: &[Entity]) {
+    for entity in despawned {
+        let generation = entity.generation().saturating_sub(1);
+        let previous = Entity::from_bits((generation as u64) << 32 | entity.id() as u64);
+        world
+            .get_or_spawn(previous)
+            .expect("despawned entity is alive");
+        world.despawn(previous);
+    }
+}
+
+/// Contents of a snapshot file
+#[derive(Serialize, Deserialize)]
+pub(crate) struct SnapshotFile {
+    tick: u64,
+    alliances: Alliances,
+    economies: Vec<ArmyEconomy>,
+    /// scenario entity names
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    names: Option<BTreeMap<String, Vec<u64>>>,
+    /// blueprints of registered units
+    blueprints: Vec<Blueprint>,
+    /// simulation specific resources by name
+    #[serde(default, skip_serializing_if = "toml::Table::is_empty")]
+    resources: toml::Table,
+    /// units which left a factory in the last two ticks
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    rolled_off: Vec<UnitRolledOff>,
+    entities: Vec<EntityRecord>,
+    /// `Entity::to_bits` of despawned entities, at the generation their ids
+    /// will be reused with
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    despawned: Vec<u64>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ArmyEconomy {
+    army: Army,
+    economy: Economy,
+}
+
+#[derive(Serialize, Deserialize)]
+struct EntityRecord {
+    /// `Entity::to_bits`
+    id: u64,
+    /// components by name
+    components: toml::Table,
+}
+
+impl SnapshotFile {
+    pub(crate) fn new(
+        world: &World,
+        components: &[SnapshotComponent],
+        resources: &[SnapshotResource],
+    ) -> Result<Self, SnapshotError> {
+        let mut entities = Vec::new();
+        for archetype in world.archetypes().iter() {
+            for entity in archetype.entities() {
+                let mut record = EntityRecord {
+                    id: entity.to_bits(),
+                    components: toml::Table::new(),
+                };
+                for component in components {
+                    if let Some(value) = (component.save)(world, *entity) {
+                        record.components.insert(component.name.to_string(), value?);
+                    }
+                }
+                entities.push(record);
+            }
+        }
+        entities.sort_by_key(|record| Entity::from_bits(record.id).id());
+
+        let mut resource_values = toml::Table::new();
+        for resource in resources {
+            if let Some(value) = (resource.save)(world) {
+                resource_values.insert(resource.name.to_string(), value?);
+            }
+        }
+        let events = world.resource::<Events<UnitRolledOff>>();
+
+        let registry = world.resource::<UnitRegistry>();
+        let mut ids: Vec<&str> = registry.ids().collect();
+        ids.sort_unstable();
+        Ok(SnapshotFile {
+            tick: world.resource::<CurrentTick>().0,
+            alliances: world.resource::<Alliances>().clone(),
+            economies: world
+                .resource::<Economies>()
+                .iter()
+                .map(|(army, economy)| ArmyEconomy {
+                    army,
+                    economy: economy.clone(),
+                })
+                .collect(),
+            names: world.get_resource::<ScenarioNames>().map(|names| {
+                names
+                    .0
+                    .iter()
+                    .map(|(name, entities)| {
+                        let ids = entities.iter().map(|entity| entity.to_bits()).collect();
+                        (name.clone(), ids)
+                    })
+                    .collect()
+            }),
+            blueprints: ids
+                .into_iter()
+                .filter_map(|id| registry.blueprint(id).cloned())
+                .collect(),
+            resources: resource_values,
+            rolled_off: events.get_reader().iter(events).copied().collect(),
+            entities,
+            despawned: despawned_entities(world)
+                .into_iter()
+                .map(|entity| entity.to_bits())
+                .collect(),
+        })
+    }
+
+    /// snapshot using the log handler and units registered in base, with the
+    /// units registered when the file was saved added
+    pub(crate) fn into_snapshot(
+        self,
+        base: &World,
+        components: &[SnapshotComponent],
+        resources: &[SnapshotResource],
+    ) -> Result<Snapshot, SnapshotError> {
+        let mut world = World::new();
+        world.insert_resource(base.resource::<LogHandler>().clone());
+        world.insert_resource(base.resource::<UnitRegistry>().clone());
+        self.load(&mut world, components, resources)?;
+        Ok(Snapshot { world })
+    }
+
+    /// fill a world containing only a log handler and unit registry
+    fn load(
+        self,
+        world: &mut World,
+        components: &[SnapshotComponent],
+        resources: &[SnapshotResource],
+    ) -> Result<(), SnapshotError> {
+        let mut registry = world.resource_mut::<UnitRegistry>();
+        for blueprint in self.blueprints {
+            // keep units the simulation registered itself
+            if !registry.contains(&blueprint.id) {
+                registry.register(blueprint);
+            }
+        }
+
+        let mut entities = self.entities;
+        entities.sort_by_key(|record| Entity::from_bits(record.id).id());
+        for mut record in entities {
+            let entity = Entity::from_bits(record.id);
+            if world.get_entity(entity).is_some() || world.get_or_spawn(entity).is_none() {
+                return Err(SnapshotError::DuplicateEntity(record.id));
+            }
+            // insert in the same order as copy_world, for the same layout
+            for component in components {
+                if let Some(value) = record.components.remove(component.name) {
+                    (component.load)(world, entity, value)?;
+                }
+            }
+            if let Some(name) = record.components.keys().next() {
+                return Err(SnapshotError::UnknownComponent(name.clone()));
+            }
+        }
+        // ids skipped by the file were never used
+        let mut despawned: BTreeMap<u32, Entity> = despawned_entities(world)
+            .into_iter()
+            .map(|entity| (entity.id(), entity))
+            .collect();
+        for bits in self.despawned {
+            let entity = Entity::from_bits(bits);
+            let current = world.entities().resolve_from_id(entity.id());
+            if current.is_some_and(|current| world.get_entity(current).is_some()) {
+                return Err(SnapshotError::DuplicateEntity(bits));
+            }
+            despawned.insert(entity.id(), entity);
+        }
+        let despawned: Vec<Entity> = despawned.into_values().collect();
+        free_entities(world, &despawned);
+
+        let mut economies = Economies::default();
+        for ArmyEconomy { army, economy } in self.economies {
+            economies.insert(army, economy);
+        }
+        world.insert_resource(CurrentTick(self.tick));
+        world.insert_resource(economies);
+        world.insert_resource(self.alliances);
+        world.insert_resource(Triggers::default());
+        let mut events = Events::<UnitRolledOff>::default();
+        events.extend(self.rolled_off);
+        world.insert_resource(events);
+        for (name, value) in self.resources {
+            let resource = resources
+                .iter()
+                .find(|resource| resource.name == name)
+                .ok_or(SnapshotError::UnknownResource(name))?;
+            (resource.load)(world, value)?;
+        }
+        if let Some(names) = self.names {
+            world.insert_resource(ScenarioNames(
+                names
+                    .into_iter()
+                    .map(|(name, ids)| (name, ids.into_iter().map(Entity::from_bits).collect()))
+                    .collect(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::tests::*;
+    use crate::trigger::{add_trigger, Condition};
+
+    #[test]
+    fn fork_keeps_despawned_ids_free() {
+        let mut sim = test_simulation();
+        let first = spawn_built(&mut sim, "ueb1103", Army(0));
+        let second = spawn_built(&mut sim, "ueb1103", Army(0));
+        let third = spawn_built(&mut sim, "ueb1103", Army(0));
+        sim.world.despawn(third);
+        sim.world.despawn(first);
+        let builder = spawn_builder(&mut sim, Army(0), 10.0);
+        sim.world
+            .entity_mut(builder)
+            .insert(Constructing::new(first));
+        sim.world.despawn(builder);
+
+        let mut fork = sim.fork();
+        assert!(fork.world.get_entity(second).is_some());
+        // stale references stay dangling instead of finding new units
+        let spawned = fork.spawn_unit("ueb1103", Army(0)).unwrap();
+        assert_ne!(spawned, first);
+        assert_ne!(spawned, builder);
+        assert_ne!(spawned, third);
+
+        // forks of forks spawn the same entities
+        let mut other = sim.fork().fork();
+        assert_eq!(other.spawn_unit("ueb1103", Army(0)), Some(spawned));
+        assert_eq!(
+            fork.spawn_unit("ueb1103", Army(0)),
+            other.spawn_unit("ueb1103", Army(0))
+        );
+    }
+
+    #[test]
+    fn snapshot_file_keeps_despawned_ids_free() {
+        let mut sim = test_simulation();
+        let first = spawn_built(&mut sim, "ueb1103", Army(0));
+        spawn_built(&mut sim, "ueb1202", Army(0));
+        sim.world.despawn(first);
+
+        let file = SnapshotFile::new(&sim.world, FA_COMPONENTS, &[]).unwrap();
+        let file: SnapshotFile = toml::from_str(&toml::to_string(&file).unwrap()).unwrap();
+        let snapshot = file.into_snapshot(&sim.world, FA_COMPONENTS, &[]).unwrap();
+        let mut loaded = <FASimulation as Snapshots>::from_world(snapshot.world);
+        let mut fork = sim.fork();
+
+        let spawned = loaded.spawn_unit("ueb1103", Army(0)).unwrap();
+        assert_ne!(spawned, first);
+        assert_eq!(fork.spawn_unit("ueb1103", Army(0)), Some(spawned));
+    }
+
+    #[test]
+    fn fork_keeps_pending_triggers() {
+        let mut sim = test_simulation();
+        let id = add_trigger(&mut sim.world, "tick 2", Condition::tick_reached(2), |_| {});
+        sim.run();
+        let mut fork = sim.fork();
+        fork.run();
+        assert!(fork.world.resource::<Triggers>().has_fired(id));
+        assert!(!sim.world.resource::<Triggers>().has_fired(id));
+    }
+
+    #[test]
+    fn saved_snapshot_continues_like_the_original() {
+        let mut sim = test_simulation();
+        set_stored(&mut sim, Army(0), 500.0, 5000.0);
+        let builder = spawn_builder(&mut sim, Army(0), 10.0);
+        let target = spawn_target(&mut sim, Army(0));
+        construct(&mut sim, builder, target);
+        for _ in 0..10 {
+            sim.run();
+        }
+        add_trigger(&mut sim.world, "never", Condition::new(|_| false), |_| {});
+
+        let path = std::env::temp_dir().join(format!("snapshot-{}.toml", std::process::id()));
+        sim.save_snapshot(&path).unwrap();
+        let mut loaded = test_simulation();
+        let result = loaded.load_snapshot(&path);
+        std::fs::remove_file(&path).unwrap();
+        result.unwrap();
+
+        assert_eq!(loaded.world.resource::<CurrentTick>().0, 10);
+        assert_eq!(loaded.world.resource::<Triggers>().pending(), 0);
+        for _ in 0..10 {
+            sim.run();
+            loaded.run();
+        }
+        let progress = |sim: &FASimulation| sim.world.get::<Damage>(target).unwrap().build_progress;
+        assert!(progress(&sim) > 0.0);
+        assert_eq!(progress(&loaded), progress(&sim));
+        let mass =
+            |sim: &FASimulation| sim.world.resource::<Economies>().get(Army(0)).unwrap().mass;
+        assert_eq!(mass(&loaded), mass(&sim));
+        assert_eq!(
+            loaded
+                .world
+                .get::<Constructing>(builder)
+                .map(|constructing| constructing.target),
+            Some(target)
+        );
+    }
+}
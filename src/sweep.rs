@@ -0,0 +1,137 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use crate::ras::*;
+
+/// Result of the RAS paragon experiment for one combination of parameters
+#[derive(Clone, Debug)]
+pub struct SweepPoint {
+    pub sacu_count: u32,
+    pub mass_income: f64,
+    pub report: RasParagonReport,
+}
+
+impl SweepPoint {
+    /// minutes saved compared to building the paragon directly
+    pub fn minutes_saved(&self) -> f64 {
+        self.report.direct_build_minutes - self.report.total_minutes()
+    }
+}
+
+/// run the RAS paragon experiment for every combination of SACU count and mass
+/// income, with one simulation per thread, returning points ordered by mass
+/// income then SACU count
+pub fn sweep_ras_paragon(
+    base: &RasParagonOptions,
+    sacu_counts: &[u32],
+    mass_incomes: &[f64],
+    threads: usize,
+) -> Result<Vec<SweepPoint>, RasParagonError> {
+    let grid: Vec<(u32, f64)> = mass_incomes
+        .iter()
+        .flat_map(|mass_income| {
+            sacu_counts
+                .iter()
+                .map(move |sacu_count| (*sacu_count, *mass_income))
+        })
+        .collect();
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new((0..grid.len()).map(|_| None).collect::<Vec<_>>());
+
+    thread::scope(|scope| {
+        for _ in 0..threads.clamp(1, grid.len().max(1)) {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let (sacu_count, mass_income) = match grid.get(index) {
+                    Some(point) => *point,
+                    None => break,
+                };
+                let result = run_ras_paragon(&RasParagonOptions {
+                    sacu_count,
+                    mass_income,
+                    verbose: false,
+                    record: None,
+                    order_log: None,
+                    ..base.clone()
+                });
+                results.lock().unwrap()[index] = Some(result);
+            });
+        }
+    });
+
+    grid.iter()
+        .zip(results.into_inner().unwrap())
+        .map(|((sacu_count, mass_income), result)| {
+            Ok(SweepPoint {
+                sacu_count: *sacu_count,
+                mass_income: *mass_income,
+                report: result.expect("every point is run")?,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fast_options() -> RasParagonOptions {
+        RasParagonOptions {
+            mass_income: 20000.0,
+            energy_income: 1_000_000.0,
+            mass_capacity: 1_000_000.0,
+            energy_capacity: 10_000_000.0,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn points_are_ordered_by_mass_income_then_sacu_count() {
+        let base = fast_options();
+        let sacu_counts = [40, 30, 35];
+        let mass_incomes = [20000.0, 10000.0];
+        let points = sweep_ras_paragon(&base, &sacu_counts, &mass_incomes, 4).unwrap();
+
+        let order: Vec<(f64, u32)> = points
+            .iter()
+            .map(|point| (point.mass_income, point.sacu_count))
+            .collect();
+        assert_eq!(
+            order,
+            vec![
+                (20000.0, 40),
+                (20000.0, 30),
+                (20000.0, 35),
+                (10000.0, 40),
+                (10000.0, 30),
+                (10000.0, 35),
+            ]
+        );
+        // each point holds the report of its own parameters
+        for point in &points {
+            let report = run_ras_paragon(&RasParagonOptions {
+                sacu_count: point.sacu_count,
+                mass_income: point.mass_income,
+                ..base.clone()
+            })
+            .unwrap();
+            assert_eq!(point.report.total_ticks, report.total_ticks);
+        }
+    }
+
+    #[test]
+    fn failing_point_fails_the_sweep() {
+        let base = RasParagonOptions {
+            max_ticks: 100,
+            ..fast_options()
+        };
+        assert!(matches!(
+            sweep_ras_paragon(&base, &[40, 30], &[20000.0], 2),
+            Err(RasParagonError::TickLimit(100))
+        ));
+        assert!(sweep_ras_paragon(&base, &[], &[20000.0], 2)
+            .unwrap()
+            .is_empty());
+    }
+}
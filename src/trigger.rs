@@ -0,0 +1,357 @@
+use std::sync::Arc;
+
+use bevy_ecs::prelude::*;
+use bevy_ecs::query::WorldQuery;
+
+use crate::simulation::*;
+
+/// Check of world state deciding when a trigger fires
+///
+/// Copies of a condition share its check.
+#[derive(Clone)]
+pub struct Condition {
+    check: Arc<dyn Fn(&mut World) -> bool + Send + Sync>,
+}
+
+impl Condition {
+    pub fn new(check: impl Fn(&mut World) -> bool + Send + Sync + 'static) -> Self {
+        Condition {
+            check: Arc::new(check),
+        }
+    }
+
+    /// current tick is at least tick
+    pub fn tick_reached(tick: u64) -> Self {
+        Condition::new(move |world| world.resource::<CurrentTick>().0 >= tick)
+    }
+
+    /// at least count entities match filter F, for example
+    /// `(With<Engineering>, With<Executing>)`
+    pub fn count_at_least<F: WorldQuery + 'static>(count: usize) -> Self {
+        Condition::new(move |world| {
+            let mut query = world.query_filtered::<Entity, F>();
+            query.iter(world).count() >= count
+        })
+    }
+
+    /// build progress of entity is at least at_least, false once entity is gone
+    pub fn build_progress(entity: Entity, at_least: f64) -> Self {
+        Condition::new(move |world| {
+            world
+                .get::<Damage>(entity)
+                .is_some_and(|damage| damage.build_progress >= at_least)
+        })
+    }
+
+    /// army has at least this much mass and energy stored
+    pub fn stored(army: Army, mass: Option<f64>, energy: Option<f64>) -> Self {
+        Condition::new(move |world| match world.resource::<Economies>().get(army) {
+            Some(economy) => {
+                mass.is_none_or(|mass| economy.mass >= mass)
+                    && energy.is_none_or(|energy| economy.energy >= energy)
+            }
+            None => false,
+        })
+    }
+
+    /// both conditions hold
+    pub fn and(self, other: Condition) -> Self {
+        Condition::new(move |world| self.check(world) && other.check(world))
+    }
+
+    pub fn check(&self, world: &mut World) -> bool {
+        (self.check)(world)
+    }
+}
+
+/// Identifies a trigger registered with [`Triggers`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TriggerId(pub usize);
+
+/// Condition paired with an action run on the world once it holds
+#[derive(Clone)]
+pub struct Trigger {
+    pub id: TriggerId,
+    pub name: String,
+    pub condition: Condition,
+    pub action: Arc<dyn Fn(&mut World) + Send + Sync>,
+    /// keep the trigger after it fires, firing again each tick its condition holds
+    pub repeat: bool,
+}
+
+/// Triggers evaluated at the end of every tick
+///
+/// Copied with the rest of the world when forking or snapshotting in memory,
+/// but not written to snapshot files.
+#[derive(Clone, Default)]
+pub struct Triggers {
+    pending: Vec<Trigger>,
+    next_id: usize,
+    /// fired triggers with the tick they fired at
+    pub fired: Vec<(u64, TriggerId)>,
+}
+
+impl Triggers {
+    /// register a trigger which fires once
+    pub fn add(
+        &mut self,
+        name: impl Into<String>,
+        condition: Condition,
+        action: impl Fn(&mut World) + Send + Sync + 'static,
+    ) -> TriggerId {
+        self.insert(name.into(), condition, Arc::new(action), false)
+    }
+
+    /// register a trigger which fires every tick its condition holds
+    pub fn add_repeating(
+        &mut self,
+        name: impl Into<String>,
+        condition: Condition,
+        action: impl Fn(&mut World) + Send + Sync + 'static,
+    ) -> TriggerId {
+        self.insert(name.into(), condition, Arc::new(action), true)
+    }
+
+    fn insert(
+        &mut self,
+        name: String,
+        condition: Condition,
+        action: Arc<dyn Fn(&mut World) + Send + Sync>,
+        repeat: bool,
+    ) -> TriggerId {
+        let id = TriggerId(self.next_id);
+        self.next_id += 1;
+        self.pending.push(Trigger {
+            id,
+            name,
+            condition,
+            action,
+            repeat,
+        });
+        id
+    }
+
+    /// remove a trigger before it fires
+    pub fn remove(&mut self, id: TriggerId) {
+        self.pending.retain(|trigger| trigger.id != id);
+    }
+
+    pub fn has_fired(&self, id: TriggerId) -> bool {
+        self.fired.iter().any(|(_, fired)| *fired == id)
+    }
+
+    /// number of triggers which have not fired yet, or repeat
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+/// register a trigger which fires once in the world's Triggers
+pub fn add_trigger(
+    world: &mut World,
+    name: impl Into<String>,
+    condition: Condition,
+    action: impl Fn(&mut World) + Send + Sync + 'static,
+) -> TriggerId {
+    world
+        .resource_mut::<Triggers>()
+        .add(name, condition, action)
+}
+
+/// run actions of triggers whose condition holds
+pub fn evaluate_triggers(world: &mut World) {
+    let mut pending = std::mem::take(&mut world.resource_mut::<Triggers>().pending);
+    let tick = world.resource::<CurrentTick>().0;
+    let mut fired = Vec::new();
+    pending.retain_mut(|trigger| {
+        if !trigger.condition.check(world) {
+            return true;
+        }
+        (world.resource::<LogHandler>().emit)(format!("tick {}: trigger {}", tick, trigger.name));
+        (trigger.action)(world);
+        fired.push((tick, trigger.id));
+        trigger.repeat
+    });
+
+    let mut triggers = world.resource_mut::<Triggers>();
+    // keep triggers added by actions
+    pending.append(&mut triggers.pending);
+    triggers.pending = pending;
+    triggers.fired.append(&mut fired);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::tests::*;
+
+    /// number of times a test action ran
+    #[derive(Default)]
+    struct Runs(u32);
+
+    fn count_run(world: &mut World) {
+        world.resource_mut::<Runs>().0 += 1;
+    }
+
+    fn test_world() -> World {
+        let mut world = World::new();
+        world.insert_resource(CurrentTick(0));
+        world.insert_resource(LogHandler::new(|_| {}));
+        world.insert_resource(Economies::single(Economy::default()));
+        world.insert_resource(Triggers::default());
+        world.insert_resource(Runs::default());
+        world
+    }
+
+    fn set_tick(world: &mut World, tick: u64) {
+        world.resource_mut::<CurrentTick>().0 = tick;
+    }
+
+    #[test]
+    fn tick_reached() {
+        let mut world = test_world();
+        let condition = Condition::tick_reached(5);
+        set_tick(&mut world, 4);
+        assert!(!condition.check(&mut world));
+        set_tick(&mut world, 5);
+        assert!(condition.check(&mut world));
+        set_tick(&mut world, 6);
+        assert!(condition.check(&mut world));
+    }
+
+    #[test]
+    fn count_at_least() {
+        let mut sim = test_simulation();
+        let condition = Condition::count_at_least::<(With<Engineering>, With<Executing>)>(2);
+        spawn_builder(&mut sim, Army(0), 10.0);
+        assert!(!condition.check(&mut sim.world));
+        // not executing, not counted
+        let idle = spawn_builder(&mut sim, Army(0), 10.0);
+        sim.world.entity_mut(idle).remove::<Executing>();
+        assert!(!condition.check(&mut sim.world));
+        spawn_builder(&mut sim, Army(0), 10.0);
+        assert!(condition.check(&mut sim.world));
+    }
+
+    #[test]
+    fn build_progress() {
+        let mut sim = test_simulation();
+        let target = spawn_target(&mut sim, Army(0));
+        let condition = Condition::build_progress(target, 0.5);
+        assert!(!condition.check(&mut sim.world));
+        sim.world
+            .get_mut::<Damage>(target)
+            .unwrap()
+            .add_build_progress(0.5);
+        assert!(condition.check(&mut sim.world));
+        // gone entities never satisfy it
+        sim.world.despawn(target);
+        assert!(!condition.check(&mut sim.world));
+    }
+
+    #[test]
+    fn stored() {
+        let mut sim = test_simulation();
+        set_stored(&mut sim, Army(0), 100.0, 1000.0);
+        assert!(Condition::stored(Army(0), Some(100.0), None).check(&mut sim.world));
+        assert!(Condition::stored(Army(0), None, Some(1000.0)).check(&mut sim.world));
+        assert!(Condition::stored(Army(0), Some(50.0), Some(500.0)).check(&mut sim.world));
+        assert!(!Condition::stored(Army(0), Some(101.0), Some(500.0)).check(&mut sim.world));
+        assert!(!Condition::stored(Army(0), Some(50.0), Some(1001.0)).check(&mut sim.world));
+        // armies without an economy have nothing stored
+        assert!(!Condition::stored(Army(7), None, None).check(&mut sim.world));
+    }
+
+    #[test]
+    fn and() {
+        let mut world = test_world();
+        let condition =
+            Condition::tick_reached(5).and(Condition::new(|world| world.resource::<Runs>().0 > 0));
+        set_tick(&mut world, 5);
+        assert!(!condition.check(&mut world));
+        count_run(&mut world);
+        assert!(condition.check(&mut world));
+        set_tick(&mut world, 4);
+        assert!(!condition.check(&mut world));
+    }
+
+    #[test]
+    fn one_shot_fires_once() {
+        let mut world = test_world();
+        let id = add_trigger(&mut world, "once", Condition::tick_reached(2), count_run);
+        assert_eq!(world.resource::<Triggers>().pending(), 1);
+
+        evaluate_triggers(&mut world);
+        assert_eq!(world.resource::<Runs>().0, 0);
+        assert!(!world.resource::<Triggers>().has_fired(id));
+
+        for tick in 2..5 {
+            set_tick(&mut world, tick);
+            evaluate_triggers(&mut world);
+        }
+        let triggers = world.resource::<Triggers>();
+        assert_eq!(world.resource::<Runs>().0, 1);
+        assert!(triggers.has_fired(id));
+        assert_eq!(triggers.fired, vec![(2, id)]);
+        assert_eq!(triggers.pending(), 0);
+    }
+
+    #[test]
+    fn repeating_fires_every_tick_its_condition_holds() {
+        let mut world = test_world();
+        let id = world.resource_mut::<Triggers>().add_repeating(
+            "repeat",
+            Condition::tick_reached(2),
+            count_run,
+        );
+        for tick in 0..5 {
+            set_tick(&mut world, tick);
+            evaluate_triggers(&mut world);
+        }
+        let triggers = world.resource::<Triggers>();
+        assert_eq!(world.resource::<Runs>().0, 3);
+        assert_eq!(triggers.fired, vec![(2, id), (3, id), (4, id)]);
+        assert_eq!(triggers.pending(), 1);
+    }
+
+    #[test]
+    fn removed_triggers_do_not_fire() {
+        let mut world = test_world();
+        let removed = add_trigger(&mut world, "removed", Condition::tick_reached(0), count_run);
+        let kept = add_trigger(&mut world, "kept", Condition::tick_reached(0), count_run);
+        assert_ne!(removed, kept);
+        world.resource_mut::<Triggers>().remove(removed);
+        assert_eq!(world.resource::<Triggers>().pending(), 1);
+
+        evaluate_triggers(&mut world);
+        let triggers = world.resource::<Triggers>();
+        assert_eq!(world.resource::<Runs>().0, 1);
+        assert!(!triggers.has_fired(removed));
+        assert!(triggers.has_fired(kept));
+    }
+
+    #[test]
+    fn triggers_added_by_actions_fire_on_later_evaluations() {
+        let mut world = test_world();
+        let first = add_trigger(
+            &mut world,
+            "first",
+            Condition::tick_reached(0),
+            |world: &mut World| {
+                add_trigger(world, "second", Condition::tick_reached(0), count_run);
+            },
+        );
+        evaluate_triggers(&mut world);
+        // the new trigger is kept but not evaluated in the pass that added it
+        assert!(world.resource::<Triggers>().has_fired(first));
+        assert_eq!(world.resource::<Triggers>().pending(), 1);
+        assert_eq!(world.resource::<Runs>().0, 0);
+
+        set_tick(&mut world, 1);
+        evaluate_triggers(&mut world);
+        let triggers = world.resource::<Triggers>();
+        assert_eq!(world.resource::<Runs>().0, 1);
+        assert_eq!(triggers.fired, vec![(0, first), (1, TriggerId(1))]);
+        assert_eq!(triggers.pending(), 0);
+    }
+}
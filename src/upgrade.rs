@@ -0,0 +1,202 @@
+use bevy_ecs::prelude::*;
+use bevy_ecs::system::CommandQueue;
+use serde::{Deserialize, Serialize};
+
+use crate::registry::{remove_blueprint_components, UnitId, UnitRegistry};
+use crate::simulation::*;
+
+/// Entity is upgrading into another unit type while continuing to execute
+#[derive(Component, Clone, Debug, Serialize, Deserialize)]
+pub struct Upgrading {
+    /// blueprint id of upgraded unit
+    pub unit_id: String,
+    /// entity tracking upgrade progress, created when the upgrade starts
+    #[serde(default, with = "crate::snapshot::option_entity_bits")]
+    pub progress: Option<Entity>,
+}
+
+impl Upgrading {
+    pub fn new(unit_id: impl Into<String>) -> Self {
+        Upgrading {
+            unit_id: unit_id.into(),
+            progress: None,
+        }
+    }
+}
+
+/// Placeholder being constructed to track progress of an upgrade
+#[derive(Component, Clone, Debug, Serialize, Deserialize)]
+pub struct UpgradeProgress {
+    /// entity being upgraded
+    #[serde(with = "crate::snapshot::entity_bits")]
+    pub upgrading: Entity,
+}
+
+/// begin pending upgrades by constructing a placeholder costing the difference
+/// between the current and upgraded blueprints
+pub fn start_upgrades(
+    mut query: Query<(Entity, &UnitId, &mut Upgrading, Option<&Engineering>), With<Executing>>,
+    registry: Res<UnitRegistry>,
+    current_tick: Res<CurrentTick>,
+    log_handler: Res<LogHandler>,
+    mut commands: Commands,
+) {
+    for (entity, unit_id, mut upgrading, engineering) in &mut query {
+        if upgrading.progress.is_some() {
+            continue;
+        }
+        let (current, upgraded) = match (
+            registry.blueprint(&unit_id.0),
+            registry.blueprint(&upgrading.unit_id),
+        ) {
+            (Some(current), Some(upgraded)) if engineering.is_some() => (current, upgraded),
+            _ => {
+                (log_handler.emit)(format!(
+                    "tick {}: warn: cannot upgrade {} to {}",
+                    current_tick.0, unit_id.0, upgrading.unit_id
+                ));
+                commands.entity(entity).remove::<Upgrading>();
+                continue;
+            }
+        };
+
+        let progress = commands
+            .spawn()
+            .insert(UpgradeProgress { upgrading: entity })
+            .insert(Damage {
+                mass_total: f64::max(0.0, upgraded.build_cost_mass - current.build_cost_mass),
+                energy_total: f64::max(0.0, upgraded.build_cost_energy - current.build_cost_energy),
+                build_time: upgraded.build_time,
+                build_progress: 0.0,
+                health: 0.0,
+                health_points: upgraded.max_health as u64,
+            })
+            .id();
+        upgrading.progress = Some(progress);
+        commands.entity(entity).insert(Constructing::new(progress));
+    }
+}
+
+/// swap an entity's components for those of the upgraded unit type
+fn complete_upgrade(world: &mut World, entity: Entity, unit_id: &str) {
+    let totals = match world.get_entity_mut(entity) {
+        Some(mut entity_mut) => {
+            entity_mut.remove::<Upgrading>();
+            entity_mut.remove::<Constructing>();
+            // keep production totals across the upgrade
+            entity_mut
+                .get::<ResourceProducer>()
+                .map(|producer| (producer.total_mass, producer.total_energy))
+        }
+        None => return,
+    };
+    world.resource_scope(|world, registry: Mut<UnitRegistry>| {
+        let mut command_queue = CommandQueue::default();
+        let mut commands = Commands::new(&mut command_queue, world);
+        let mut entity_commands = commands.entity(entity);
+        remove_blueprint_components(&mut entity_commands);
+        registry.insert_components(&mut entity_commands, unit_id);
+        command_queue.apply(world);
+    });
+
+    let mut entity_mut = world.entity_mut(entity);
+    if let Some(mut damage) = entity_mut.get_mut::<Damage>() {
+        damage.finish_construction();
+    }
+    if let (Some((total_mass, total_energy)), Some(mut producer)) =
+        (totals, entity_mut.get_mut::<ResourceProducer>())
+    {
+        producer.total_mass = total_mass;
+        producer.total_energy = total_energy;
+    }
+}
+
+/// swap components of upgraded entities once the upgrade is complete, and clean
+/// up progress placeholders of cancelled upgrades
+pub fn finish_upgrades(
+    query: Query<&Upgrading>,
+    progress_query: Query<(Entity, &UpgradeProgress, &Damage)>,
+    mut commands: Commands,
+) {
+    for (progress_entity, upgrade_progress, damage) in &progress_query {
+        let upgrading = match query.get(upgrade_progress.upgrading) {
+            Ok(upgrading) if upgrading.progress == Some(progress_entity) => upgrading,
+            _ => {
+                // upgrade cancelled
+                commands.entity(progress_entity).despawn();
+                continue;
+            }
+        };
+        if !damage.is_finished() {
+            continue;
+        }
+
+        let entity = upgrade_progress.upgrading;
+        let unit_id = upgrading.unit_id.clone();
+        commands.entity(progress_entity).despawn();
+        commands.add(move |world: &mut World| complete_upgrade(world, entity, &unit_id));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simulation::tests::*;
+
+    #[test]
+    fn upgrade_costs_difference_and_keeps_producing() {
+        let mut sim = test_simulation();
+        set_stored(&mut sim, Army(0), 2000.0, 20000.0);
+        let extractor = spawn_built(&mut sim, "ueb1103", Army(0));
+        sim.world
+            .entity_mut(extractor)
+            .insert(Upgrading::new("ueb1202"));
+        sim.run();
+        let progress = sim
+            .world
+            .get::<Upgrading>(extractor)
+            .unwrap()
+            .progress
+            .unwrap();
+        let damage = sim.world.get::<Damage>(progress).unwrap();
+        assert_eq!(damage.mass_total, 900.0 - 36.0);
+        assert_eq!(damage.energy_total, 5400.0 - 360.0);
+
+        // build rate 10 and build time 900 take 90 seconds
+        for _ in 0..1000 {
+            sim.run();
+            if !sim.world.entity(extractor).contains::<Upgrading>() {
+                break;
+            }
+        }
+        let entity = sim.world.entity(extractor);
+        assert_eq!(entity.get::<UnitId>().unwrap().0, "ueb1202");
+        assert!(!entity.contains::<Upgrading>());
+        assert!(entity.get::<Damage>().unwrap().is_finished());
+        assert!(sim.world.get_entity(progress).is_none());
+        let producer = entity.get::<ResourceProducer>().unwrap();
+        assert_eq!(producer.mass_yield, 6.0 / TICK_RATE);
+        // kept producing as a T1 extractor until the tick the upgrade finished
+        let ticks = sim.world.resource::<CurrentTick>().0 as f64;
+        let expected = (ticks - 1.0) * 2.0 / TICK_RATE + 6.0 / TICK_RATE;
+        assert!((producer.total_mass - expected).abs() < 1e-6);
+
+        let economy = sim.economy(Army(0)).unwrap();
+        assert!((economy.mass - (2000.0 + producer.total_mass - 864.0)).abs() < 1e-6);
+        assert!((economy.energy - (20000.0 - 5040.0)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn upgrade_to_unknown_unit_is_dropped() {
+        let mut sim = test_simulation();
+        let extractor = spawn_built(&mut sim, "ueb1103", Army(0));
+        sim.world
+            .entity_mut(extractor)
+            .insert(Upgrading::new("unknown"));
+        sim.run();
+        let entity = sim.world.entity(extractor);
+        assert!(!entity.contains::<Upgrading>());
+        assert!(!entity.contains::<Constructing>());
+        assert_eq!(entity.get::<UnitId>().unwrap().0, "ueb1103");
+    }
+}